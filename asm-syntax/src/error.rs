@@ -12,12 +12,6 @@ pub enum Error {
     InvalidDisplacement
 }
 
-impl From<std::num::ParseIntError> for Error {
-    fn from(_err: std::num::ParseIntError) -> Error {
-        Error::InvalidDisplacement
-    }
-}
-
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.write_str(match self {
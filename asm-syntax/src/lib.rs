@@ -9,6 +9,7 @@ pub mod parser;
 pub use error::Error;
 
 use string_interner::Symbol;
+use tokenizer::{IntSuffix, IntegerLiteral};
 
 use std::num::{NonZeroI8, NonZeroI16, NonZeroI32};
 
@@ -115,19 +116,40 @@ pub enum Immediate {
 }
 
 impl Immediate {
-    pub fn from(t: &str, ty: Constant) -> Result<Self, std::num::ParseIntError> {
-        Ok(match ty {
-            Constant::U8 => Immediate::U8(t.parse::<u8>()?),
-            Constant::U16 => Immediate::U16(t.parse::<u16>()?),
-            Constant::U32 => Immediate::U32(t.parse::<u32>()?),
-            Constant::U64 => Immediate::U64(t.parse::<u64>()?),
-            Constant::I8 => Immediate::I8(t.parse::<i8>()?),
-            Constant::I16 => Immediate::I16(t.parse::<i16>()?),
-            Constant::I32 => Immediate::I32(t.parse::<i32>()?),
-            Constant::I64 => Immediate::I64(t.parse::<i64>()?),
+    /// Converts a decoded integer literal to an immediate of type `ty`. Returns `None` if the
+    /// value doesn't fit.
+    pub fn from_literal(i: IntegerLiteral, ty: Constant) -> Option<Self> {
+        let (min, max) = ty.range();
+        if !i.fits(min, max) {
+            return None;
+        }
+
+        let v = i.value;
+        Some(match ty {
+            Constant::U8 => Immediate::U8(v as u8),
+            Constant::U16 => Immediate::U16(v as u16),
+            Constant::U32 => Immediate::U32(v as u32),
+            Constant::U64 => Immediate::U64(v as u64),
+            Constant::I8 => Immediate::I8(v as i8),
+            Constant::I16 => Immediate::I16(v as i16),
+            Constant::I32 => Immediate::I32(v as i32),
+            Constant::I64 => Immediate::I64(v as i64),
         })
     }
 
+    /// Converts an integer literal of compiled code to an immediate of the type its suffix
+    /// names, or of type `default` if it has none. Pointer sized suffixes are 64 bits, as on every
+    /// ISA the compiler targets. Returns `None` if the value doesn't fit.
+    pub fn from_integer(i: IntegerLiteral, default: Constant) -> Option<Self> {
+        let ty = match i.suffix {
+            None => default,
+            Some(IntSuffix::Usize) => Constant::U64,
+            Some(IntSuffix::Isize) => Constant::I64,
+            Some(suffix) => Constant::from_suffix(suffix)?,
+        };
+        Immediate::from_literal(i, ty)
+    }
+
     pub fn to_le_bytes(self) -> Vec<u8> {
         match self {
             Immediate::Bytes(v) => v,
@@ -197,6 +219,36 @@ impl Constant {
         false
     }
 
+    /// Inclusive range of values representable by this type
+    pub fn range(self) -> (i128, i128) {
+        use self::Constant::*;
+        match self {
+            U8 => (0, u8::MAX as i128),
+            U16 => (0, u16::MAX as i128),
+            U32 => (0, u32::MAX as i128),
+            U64 => (0, u64::MAX as i128),
+            I8 => (i8::MIN as i128, i8::MAX as i128),
+            I16 => (i16::MIN as i128, i16::MAX as i128),
+            I32 => (i32::MIN as i128, i32::MAX as i128),
+            I64 => (i64::MIN as i128, i64::MAX as i128),
+        }
+    }
+
+    /// Pointer sized suffixes have no constant type as their size depends on the ISA.
+    pub fn from_suffix(suffix: IntSuffix) -> Option<Self> {
+        Some(match suffix {
+            IntSuffix::U8 => Constant::U8,
+            IntSuffix::U16 => Constant::U16,
+            IntSuffix::U32 => Constant::U32,
+            IntSuffix::U64 => Constant::U64,
+            IntSuffix::I8 => Constant::I8,
+            IntSuffix::I16 => Constant::I16,
+            IntSuffix::I32 => Constant::I32,
+            IntSuffix::I64 => Constant::I64,
+            IntSuffix::Usize | IntSuffix::Isize => return None,
+        })
+    }
+
     pub fn from_str(input: &str) -> Option<Self> {
         Some(match input {
             "u8" | "U8" => Constant::U8,
@@ -1,7 +1,7 @@
 use {Constant, Displacement, Error, Immediate, Instruction, Operand};
 
 use string_interner::{INTERNER, Symbol};
-use tokenizer::Token;
+use tokenizer::{IntegerLiteral, Token};
 
 use std::num::{NonZeroI8, NonZeroI16, NonZeroI32};
 
@@ -128,7 +128,14 @@ pub fn parse(tokens: &[Token], input: &str, inlinep: bool) -> Result<Vec<Instruc
                         return Err(Error::Mode);
                     }
                 }
-                Token::Integer(_) | Token::Float(_) => return Err(Error::NoType),
+                // Suffixed integers carry their own type, e.g. `0xffu8`
+                t @ Token::Integer(_) => {
+                    let i = t.integer(input).ok_or(Error::Token)?;
+                    let ty = i.suffix.and_then(Constant::from_suffix).ok_or(Error::NoType)?;
+                    let imm = Immediate::from_literal(i, ty).ok_or(Error::ConstantType)?;
+                    operands.push(Operand::Constant(imm));
+                }
+                Token::Float(_) => return Err(Error::NoType),
                 // We assume this is a register, but valid register names differ based on
                 // ISA, so we don't validate this until later.
                 t @ Token::Symbol(_) => operands.push(Operand::Register(get_symbol(t, input))),
@@ -144,6 +151,15 @@ fn parse_constant(ty: Constant, parser: &mut Parser, operands: &mut Vec<Operand>
     -> Result<(), Error>
 {
     let t = parser.next()?;
+    // Char literals can be used for any integer type that holds their value, e.g. `(u8 #'\n')`
+    if t.is_char() {
+        let c = t.char_value(input).map_err(|_| Error::Token)?;
+        let c = IntegerLiteral { value: c as i128, suffix: None };
+        assert!(parser.next()?.closerp());
+        operands.push(Operand::Constant(Immediate::from_literal(c, ty).ok_or(Error::ConstantType)?));
+        return Ok(());
+    }
+
     if !t.is_integer() && !t.is_float() {
         return Err(Error::Token);
     }
@@ -158,7 +174,12 @@ fn parse_constant(ty: Constant, parser: &mut Parser, operands: &mut Vec<Operand>
 
     assert!(parser.next()?.closerp());
 
-    operands.push(Operand::Constant(Immediate::from(t.as_str(input), ty)?));
+    let i = t.integer(input).ok_or(Error::ConstantType)?;
+    // A suffix must agree with the mode, `(u8 1i32)` is an error
+    if i.suffix.is_some() && i.suffix.and_then(Constant::from_suffix) != Some(ty) {
+        return Err(Error::ConstantType);
+    }
+    operands.push(Operand::Constant(Immediate::from_literal(i, ty).ok_or(Error::ConstantType)?));
     Ok(())
 }
 
@@ -238,35 +259,25 @@ fn parse_displacement(parser: &mut Parser, input: &str) -> Result<Operand, Error
     }
 
     let (mut displacement, reg) = if operand_one.is_integer() {
-        let d = operand_one.as_str(input);
+        let d = operand_one.integer(input).ok_or(Error::InvalidDisplacement)?;
         let ty = if let Some(c) = Constant::from_str(ty1.unwrap().as_str(input)) {
             c
         } else {
             return Err(Error::Mode);
         };
 
-        let d = match ty {
-            Constant::I8 => Displacement::Disp8(d.parse::<NonZeroI8>()?),
-            Constant::I16 => Displacement::Disp16(d.parse::<NonZeroI16>()?),
-            Constant::I32 => Displacement::Disp32(d.parse::<NonZeroI32>()?),
-            _ => return Err(Error::Mode),
-        };
+        let d = parse_disp(d, ty)?;
 
         (d, operand_two)
     } else {
-        let d = operand_two.as_str(input);
+        let d = operand_two.integer(input).ok_or(Error::InvalidDisplacement)?;
         let ty = if let Some(c) = Constant::from_str(ty2.unwrap().as_str(input)) {
             c
         } else {
             return Err(Error::Mode);
         };
 
-        let d = match ty {
-            Constant::I8 => Displacement::Disp8(d.parse::<NonZeroI8>()?),
-            Constant::I16 => Displacement::Disp16(d.parse::<NonZeroI16>()?),
-            Constant::I32 => Displacement::Disp32(d.parse::<NonZeroI32>()?),
-            _ => return Err(Error::Mode),
-        };
+        let d = parse_disp(d, ty)?;
 
         (d, operand_one)
     };
@@ -280,3 +291,18 @@ fn parse_displacement(parser: &mut Parser, input: &str) -> Result<Operand, Error
 
     Ok(Operand::Address(Some(displacement), get_symbol(reg, input)))
 }
+
+fn parse_disp(d: IntegerLiteral, ty: Constant) -> Result<Displacement, Error> {
+    let (min, max) = ty.range();
+    if !d.fits(min, max) {
+        return Err(Error::InvalidDisplacement);
+    }
+
+    let d = match ty {
+        Constant::I8 => NonZeroI8::new(d.value as i8).map(Displacement::Disp8),
+        Constant::I16 => NonZeroI16::new(d.value as i16).map(Displacement::Disp16),
+        Constant::I32 => NonZeroI32::new(d.value as i32).map(Displacement::Disp32),
+        _ => return Err(Error::Mode),
+    };
+    d.ok_or(Error::InvalidDisplacement)
+}
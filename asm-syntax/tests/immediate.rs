@@ -0,0 +1,28 @@
+extern crate asm_syntax;
+extern crate tokenizer;
+
+use asm_syntax::{Constant, Immediate};
+use tokenizer::IntegerLiteral;
+
+fn immediate(literal: &str, default: Constant) -> Option<Immediate> {
+    Immediate::from_integer(IntegerLiteral::parse(literal).unwrap(), default)
+}
+
+#[test]
+fn suffixed_widths() {
+    assert_eq!(immediate("255u8", Constant::I32), Some(Immediate::U8(255)));
+    assert_eq!(immediate("-2i16", Constant::I32), Some(Immediate::I16(-2)));
+    assert_eq!(immediate("0xffff_ffffu32", Constant::I32), Some(Immediate::U32(u32::MAX)));
+    assert_eq!(immediate("1usize", Constant::I32), Some(Immediate::U64(1)));
+    assert_eq!(immediate("-1isize", Constant::I32), Some(Immediate::I64(-1)));
+    assert_eq!(immediate("7", Constant::I32), Some(Immediate::I32(7)));
+    assert_eq!(immediate("7", Constant::U64), Some(Immediate::U64(7)));
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(immediate("256u8", Constant::I32), None);
+    assert_eq!(immediate("-1u64", Constant::I32), None);
+    assert_eq!(immediate("0x8000_0000", Constant::I32), None);
+    assert_eq!(immediate("0x1_0000_0000_0000_0000usize", Constant::I32), None);
+}
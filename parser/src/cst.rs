@@ -0,0 +1,95 @@
+use tokenizer::{FileId, LosslessToken, TokenizeError, Tokenizer, Trivia};
+
+/// Node of a concrete syntax tree. Unlike `Ast`, every token and all trivia is kept so that the
+/// original source can be reproduced exactly.
+#[derive(Clone, Debug, PartialEq, is_enum_variant)]
+pub enum SyntaxNode {
+    Atom(LosslessToken),
+    List {
+        open: LosslessToken,
+        children: Vec<SyntaxNode>,
+        /// Missing if the input ended before the list was closed
+        close: Option<LosslessToken>,
+    },
+}
+
+impl SyntaxNode {
+    pub fn write(&self, input: &str, out: &mut String) {
+        match self {
+            SyntaxNode::Atom(t) => t.write(input, out),
+            SyntaxNode::List { open, children, close } => {
+                open.write(input, out);
+                for child in children {
+                    child.write(input, out);
+                }
+                if let Some(close) = close {
+                    close.write(input, out);
+                }
+            }
+        }
+    }
+}
+
+/// Lossless tree over the s-expression structure of a file, intended for formatters and other
+/// tools that rewrite source code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTree {
+    pub nodes: Vec<SyntaxNode>,
+    /// Trivia after the last token
+    pub trailing: Vec<Trivia>,
+    pub errors: Vec<TokenizeError>,
+}
+
+impl SyntaxTree {
+    /// Builds a tree from any input. Closers without a matching opener become atoms and unclosed
+    /// lists are closed at the end of input.
+    pub fn parse(input: &str, file: FileId) -> Self {
+        let lossless = Tokenizer::tokenize_lossless(input, file);
+
+        // Stack of lists that are still open, the bottom entry holds the top level nodes.
+        let mut stack: Vec<(Option<LosslessToken>, Vec<SyntaxNode>)> = vec![(None, Vec::new())];
+        for t in lossless.tokens {
+            if t.token.openerp() {
+                stack.push((Some(t), Vec::new()));
+            } else if t.token.closerp() && stack.len() > 1 {
+                let (open, children) = stack.pop().unwrap();
+                let list = SyntaxNode::List {
+                    open: open.unwrap(),
+                    children,
+                    close: Some(t),
+                };
+                stack.last_mut().unwrap().1.push(list);
+            } else {
+                stack.last_mut().unwrap().1.push(SyntaxNode::Atom(t));
+            }
+        }
+
+        while stack.len() > 1 {
+            let (open, children) = stack.pop().unwrap();
+            let list = SyntaxNode::List {
+                open: open.unwrap(),
+                children,
+                close: None,
+            };
+            stack.last_mut().unwrap().1.push(list);
+        }
+
+        SyntaxTree {
+            nodes: stack.pop().unwrap().1,
+            trailing: lossless.trailing,
+            errors: lossless.errors,
+        }
+    }
+
+    /// Reproduces the source text the tree was built from.
+    pub fn to_source(&self, input: &str) -> String {
+        let mut out = String::with_capacity(input.len());
+        for node in &self.nodes {
+            node.write(input, &mut out);
+        }
+        for t in &self.trailing {
+            out.push_str(t.as_str(input));
+        }
+        out
+    }
+}
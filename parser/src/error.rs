@@ -1,7 +1,54 @@
+use tokenizer::{Index, TokenizeError};
+
 use std::fmt::{self, Display, Formatter};
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParserError {
+    pub kind: ParserErrorKind,
+    /// Span of the offending input
+    pub span: Index,
+    /// Description of what the parser was looking for
+    pub expected: Option<&'static str>,
+    /// Description of what was found instead
+    pub found: Option<String>,
+}
+
+impl ParserError {
+    pub fn new(kind: ParserErrorKind, span: Index) -> Self {
+        ParserError {
+            kind,
+            span,
+            expected: None,
+            found: None,
+        }
+    }
+
+    pub fn expected(mut self, expected: &'static str) -> Self {
+        self.expected = Some(expected);
+        self
+    }
+
+    pub fn found<S: Into<String>>(mut self, found: S) -> Self {
+        self.found = Some(found.into());
+        self
+    }
+}
+
+impl Display for ParserError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(expected) = self.expected {
+            write!(f, ", expected {}", expected)?;
+        }
+        if let Some(ref found) = self.found {
+            write!(f, ", found {}", found)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ParserError {
+pub enum ParserErrorKind {
     EOI,
     Closer,
     Token,
@@ -10,20 +57,117 @@ pub enum ParserError {
     ReturnType,
     NonfinalValue,
     Value,
+    Literal,
+    Type,
+    Delimiter,
+    Bracket,
+    Loop,
+    Else,
+    Ellipsis,
+    Include,
+    Cycle,
+    Namespace,
+    Export,
+    Intrinsic,
 }
 
-impl Display for ParserError {
+impl Display for ParserErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParserErrorKind::EOI => write!(f, "Unexpected end of input"),
+            ParserErrorKind::Closer => write!(f, "Expected closer"),
+            ParserErrorKind::Token => write!(f, "Unexpected token"),
+            ParserErrorKind::Item => write!(f, "Expected item"),
+            ParserErrorKind::Asm => write!(f, "Inline assembly must be the only item in a procedure"),
+            ParserErrorKind::ReturnType => write!(f, "Returned value does not match the expected return type"),
+            ParserErrorKind::NonfinalValue => write!(f, "Primitive/identifier can only be the last item in a procedure"),
+            ParserErrorKind::Value => write!(f, "Expected expression"),
+            ParserErrorKind::Literal => write!(f, "Invalid escape in literal"),
+            ParserErrorKind::Type => write!(f, "Unknown type"),
+            ParserErrorKind::Delimiter => write!(f, "Mismatched closing delimiter"),
+            ParserErrorKind::Bracket => write!(f, "Square brackets are only used for bindings and indexing"),
+            ParserErrorKind::Loop => write!(f, "`break` or `continue` outside of a loop"),
+            ParserErrorKind::Else => write!(f, "`else` must be the last clause of a `cond`"),
+            ParserErrorKind::Ellipsis => write!(f, "`...` must follow a pattern, at most once in each list"),
+            ParserErrorKind::Include => write!(f, "Included file can't be found or read"),
+            ParserErrorKind::Cycle => write!(f, "File includes itself, directly or through other files"),
+            ParserErrorKind::Namespace => write!(f, "File is already included under a different namespace"),
+            ParserErrorKind::Export => write!(f, "Exported name isn't a function or constant defined in this file"),
+            ParserErrorKind::Intrinsic => write!(f, "Unknown intrinsic operation"),
+        }
+    }
+}
+
+/// Error from loading a file or one of the files it includes
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    Tokenize(TokenizeError),
+    Parse(ParserError),
+}
+
+impl LoadError {
+    pub fn span(&self) -> Index {
+        match self {
+            LoadError::Tokenize(e) => e.index,
+            LoadError::Parse(e) => e.span,
+        }
+    }
+}
+
+impl Display for LoadError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            ParserError::EOI => write!(f, "Unexpected end of input"),
-            ParserError::Closer => write!(f, "Expected closer"),
-            ParserError::Token => write!(f, "Unexpected token"),
-            ParserError::Item => write!(f, "Expected item"),
-            ParserError::Asm => write!(f, "Inline assembly must be the only item in a procedure"),
-            ParserError::ReturnType => write!(f, "Returned value does not match the expected return type"),
-            ParserError::NonfinalValue => write!(f, "Primitive/identifier can only be the last item in a procedure"),
-            ParserError::Value => write!(f, "Expected expression"),
+            LoadError::Tokenize(e) => e.fmt(f),
+            LoadError::Parse(e) => e.fmt(f),
         }
     }
 }
 
+/// Error from expanding macros. If it's in the expansion of other macros, their uses are
+/// recorded on `span` and reported by `SourceMap::diagnostic`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroError {
+    pub kind: MacroErrorKind,
+    pub span: Index,
+}
+
+impl MacroError {
+    pub fn new(kind: MacroErrorKind, span: Index) -> Self {
+        MacroError { kind, span }
+    }
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MacroErrorKind {
+    NoMatch,
+    Redefined,
+    Nested,
+    Depth,
+    Ellipsis,
+    Lengths,
+    Binder,
+    Item,
+    Empty,
+}
+
+impl Display for MacroErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            MacroErrorKind::NoMatch => write!(f, "No rule of the macro matches this use"),
+            MacroErrorKind::Redefined => write!(f, "Macro is already defined"),
+            MacroErrorKind::Nested => write!(f, "Macros can only be defined at the top level"),
+            MacroErrorKind::Depth => write!(f, "Macro expansion is nested too deeply, the macro may expand to itself forever"),
+            MacroErrorKind::Ellipsis => write!(f, "Pattern variable is followed by a different number of `...` than in its pattern"),
+            MacroErrorKind::Lengths => write!(f, "Pattern variables repeated by the same `...` matched different numbers of expressions"),
+            MacroErrorKind::Binder => write!(f, "Pattern variable used as a name must match an identifier"),
+            MacroErrorKind::Item => write!(f, "Expected item"),
+            MacroErrorKind::Empty => write!(f, "Macro expands to an empty list"),
+        }
+    }
+}
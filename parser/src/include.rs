@@ -0,0 +1,170 @@
+use super::{parse_partial, Ast, ParserError, ParserErrorKind};
+use error::LoadError;
+use rewrite::{Rename, Rewrite};
+
+use string_interner::{get_symbol, get_symbol_uninterned, get_value, Symbol};
+use tokenizer::{FileId, Index, SourceMap};
+
+use std::collections::HashMap;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+/// Parses `file` along with every file it includes, directly or through other files, and returns
+/// the items of all of them. A file is only loaded once however often it is included, and its
+/// names are visible to the whole program from then on.
+///
+/// Included paths are looked up relative to the directory of the including file first, then in
+/// each of `search_paths` in order. A leading `libs/` is dropped when looking in the search paths,
+/// so that `libs/unix/lib.inc` names the same file of the standard library as `unix/lib.inc`.
+///
+/// A file included `as` a namespace has its functions and constants renamed to
+/// `namespace::name`. If a file has `export` lists, the functions and constants it doesn't export
+/// are renamed so that no other file can refer to them. Types and macros are always global, and
+/// macro templates keep referring to names as they were written.
+pub fn load(file: FileId, sources: &mut SourceMap, search_paths: &[PathBuf]) -> Result<Vec<Ast>, Vec<LoadError>> {
+    let root = canonical(Path::new(sources.name(file)));
+    let mut loader = Loader {
+        sources,
+        search_paths,
+        loaded: HashMap::new(),
+        stack: vec![root.clone()],
+        errors: Vec::new(),
+    };
+    loader.loaded.insert(root, None);
+
+    let ast = loader.load_file(file, None);
+    if loader.errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(loader.errors)
+    }
+}
+
+struct Loader<'a> {
+    sources: &'a mut SourceMap,
+    search_paths: &'a [PathBuf],
+    /// Namespace of every file loaded so far, by canonical path
+    loaded: HashMap<PathBuf, Option<Symbol>>,
+    /// Files being loaded, each included by the one before it
+    stack: Vec<PathBuf>,
+    errors: Vec<LoadError>,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&mut self, kind: ParserErrorKind, span: Index) {
+        self.errors.push(LoadError::Parse(ParserError::new(kind, span)));
+    }
+
+    /// Items of the files included by `file` followed by its own items
+    fn load_file(&mut self, file: FileId, namespace: Option<Symbol>) -> Vec<Ast> {
+        let tokens = match self.sources.tokenize(file) {
+            Ok(tokens) => tokens,
+            Err(errors) => {
+                self.errors.extend(errors.into_iter().map(LoadError::Tokenize));
+                return Vec::new();
+            }
+        };
+        let input = self.sources.source(file).to_string();
+        let (ast, errors) = parse_partial(tokens, &input);
+        self.errors.extend(errors.into_iter().map(LoadError::Parse));
+
+        let dir = Path::new(self.sources.name(file)).parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut items = Vec::new();
+        let mut own = Vec::new();
+        let mut exports = None;
+        for item in ast {
+            match item {
+                Ast::Include { path, namespace, span } => {
+                    let included = self.include(&dir, path, namespace, span);
+                    items.extend(included);
+                }
+                Ast::Export(names, span) => {
+                    exports.get_or_insert_with(Vec::new).extend(names.into_iter().map(|name| (name, span)));
+                }
+                _ => own.push(item),
+            }
+        }
+        items.extend(self.rename(own, namespace, exports));
+        items
+    }
+
+    fn include(&mut self, dir: &Path, path: Symbol, namespace: Option<Symbol>, span: Index) -> Vec<Ast> {
+        let name = get_value(path).unwrap_or_default();
+        let library = Path::new(&name).strip_prefix("libs").unwrap_or(Path::new(&name));
+        let found = iter::once(dir.join(&name))
+            .chain(self.search_paths.iter().map(|dir| dir.join(library)))
+            .find(|path| path.is_file());
+        let found = match found {
+            Some(found) => found,
+            None => {
+                self.error(ParserErrorKind::Include, span);
+                return Vec::new();
+            }
+        };
+
+        let path = canonical(&found);
+        if self.stack.contains(&path) {
+            self.error(ParserErrorKind::Cycle, span);
+            return Vec::new();
+        }
+        match self.loaded.get(&path) {
+            Some(loaded) if *loaded == namespace => return Vec::new(),
+            Some(_) => {
+                self.error(ParserErrorKind::Namespace, span);
+                return Vec::new();
+            }
+            None => (),
+        }
+
+        let source = match fs::read_to_string(&found) {
+            Ok(source) => source,
+            Err(_) => {
+                self.error(ParserErrorKind::Include, span);
+                return Vec::new();
+            }
+        };
+        let file = self.sources.add_file(found.display().to_string(), source);
+        self.loaded.insert(path.clone(), namespace);
+        self.stack.push(path);
+        let items = self.load_file(file, namespace);
+        self.stack.pop();
+        items
+    }
+
+    /// Applies the namespace and export lists of a file to the functions and constants it defines.
+    fn rename(&mut self, items: Vec<Ast>, namespace: Option<Symbol>, exports: Option<Vec<(Symbol, Index)>>) -> Vec<Ast> {
+        let defined: Vec<_> = items.iter()
+            .filter_map(|item| match item {
+                Ast::Defn { name, .. } | Ast::Define { name, .. } | Ast::Intrinsic { name, .. } => Some(*name),
+                _ => None,
+            })
+            .collect();
+        for (name, span) in exports.iter().flatten() {
+            if !defined.contains(name) {
+                self.error(ParserErrorKind::Export, *span);
+            }
+        }
+
+        let renames: HashMap<_, _> = defined.into_iter()
+            .filter_map(|name| {
+                let value = get_value(name).unwrap_or_default();
+                if exports.as_ref().is_some_and(|exports| !exports.iter().any(|(n, _)| *n == name)) {
+                    Some((name, get_symbol_uninterned(value)))
+                } else {
+                    namespace.map(|ns| (name, get_symbol(format!("{}::{}", get_value(ns).unwrap_or_default(), value))))
+                }
+            })
+            .collect();
+        if renames.is_empty() {
+            return items;
+        }
+
+        let mut rename = Rename { renames };
+        items.into_iter().map(|item| rename.expr(item).expect("renaming can't fail")).collect()
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,113 @@
+/// Operation that backends implement directly with machine instructions rather than with a call.
+/// `(#intrinsic op (name (args...) ret))` declares a function `name` which performs `op`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IntrinsicOp {
+    Add,
+    Sub,
+    Mul,
+    /// Division rounding towards zero
+    Div,
+    /// Remainder with the sign of the dividend
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    /// Arithmetic shift for signed integers, logical shift for unsigned ones
+    Shr,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Values an intrinsic can operate on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operands {
+    /// Integers and floats
+    Number,
+    Integer,
+    /// Integers and bools
+    Bits,
+    /// Numbers, chars and pointers
+    Ordered,
+    /// Numbers, chars, bools and pointers
+    Scalar,
+}
+
+impl IntrinsicOp {
+    pub fn from_name(s: &str) -> Option<Self> {
+        use self::IntrinsicOp::*;
+        Some(match s {
+            "add" => Add,
+            "sub" => Sub,
+            "mul" => Mul,
+            "div" => Div,
+            "rem" => Rem,
+            "and" => And,
+            "or" => Or,
+            "xor" => Xor,
+            "shl" => Shl,
+            "shr" => Shr,
+            "not" => Not,
+            "eq" => Eq,
+            "ne" => Ne,
+            "lt" => Lt,
+            "le" => Le,
+            "gt" => Gt,
+            "ge" => Ge,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        use self::IntrinsicOp::*;
+        match self {
+            Add => "add",
+            Sub => "sub",
+            Mul => "mul",
+            Div => "div",
+            Rem => "rem",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Shl => "shl",
+            Shr => "shr",
+            Not => "not",
+            Eq => "eq",
+            Ne => "ne",
+            Lt => "lt",
+            Le => "le",
+            Gt => "gt",
+            Ge => "ge",
+        }
+    }
+
+    /// Number of operands, which all have the same type
+    pub fn arity(self) -> usize {
+        match self {
+            IntrinsicOp::Not => 1,
+            _ => 2,
+        }
+    }
+
+    /// Whether the result is a bool rather than a value of the operand type
+    pub fn comparisonp(self) -> bool {
+        use self::IntrinsicOp::*;
+        matches!(self, Eq | Ne | Lt | Le | Gt | Ge)
+    }
+
+    pub fn operands(self) -> Operands {
+        use self::IntrinsicOp::*;
+        match self {
+            Add | Sub | Mul | Div => Operands::Number,
+            Rem | Shl | Shr => Operands::Integer,
+            And | Or | Xor | Not => Operands::Bits,
+            Lt | Le | Gt | Ge => Operands::Ordered,
+            Eq | Ne => Operands::Scalar,
+        }
+    }
+}
@@ -3,88 +3,432 @@ extern crate derive_is_enum_variant;
 extern crate string_interner;
 extern crate tokenizer;
 
+mod cst;
 mod error;
+mod include;
+mod intrinsic;
+mod macros;
+mod rewrite;
 
-pub use error::ParserError;
+pub use cst::{SyntaxNode, SyntaxTree};
+pub use error::{LoadError, MacroError, MacroErrorKind, ParserError, ParserErrorKind};
+pub use include::load;
+pub use intrinsic::{IntrinsicOp, Operands};
+pub use macros::expand;
+pub use rewrite::{walk, Rewrite};
 
 use string_interner::{INTERNER, Symbol};
-use tokenizer::Token;
+use tokenizer::{Index, IntSuffix, IntegerLiteral, Token};
+
+use std::fmt::{self, Display, Formatter};
 
 pub type Result<T> = std::result::Result<T, ParserError>;
 
 #[derive(Clone, Debug, is_enum_variant)]
 pub enum Ast {
-    Include(Symbol),
+    /// `(include path)` or `(include path as namespace)`, resolved by `load`
+    Include {
+        path: Symbol,
+        /// Prefix for the names defined by the file, which are then used as `namespace::name`
+        namespace: Option<Symbol>,
+        span: Index,
+    },
+    /// `(export name...)`, the functions and constants of the file that other files can use
+    Export(Vec<Symbol>, Index),
     Define {
         name: Symbol,
         ty: Type,
-        value: Box<Ast>
+        value: Box<Ast>,
+        span: Index,
     },
     Defn {
         name: Symbol,
+        /// Type parameters of a generic function
+        params: Vec<Symbol>,
         ty: Type,
         args: Vec<Arg>,
         body: Vec<Ast>,
+        /// Span of the return type, if one was given
+        ret_span: Option<Index>,
+        span: Index,
+    },
+    /// Struct declaration, fields are laid out in the order given
+    Struct {
+        name: Symbol,
+        /// Type parameters of a generic struct
+        params: Vec<Symbol>,
+        fields: Vec<Arg>,
+        span: Index,
+    },
+    /// Tagged union, every value is one of the variants along with its payload
+    Enum {
+        name: Symbol,
+        params: Vec<Symbol>,
+        variants: Vec<Variant>,
+        span: Index,
+    },
+    /// Methods of the struct or enum `name`. `params` are its type parameters, which the
+    /// methods may refer to.
+    Impl {
+        name: Symbol,
+        params: Vec<Symbol>,
+        methods: Vec<Ast>,
+        span: Index,
+    },
+    /// `(define-syntax name (syntax-rules (literals...) rule...))` or
+    /// `(defmacro (name pattern...) template)`. Macros are replaced by their expansions in
+    /// `expand`, so they never reach the type checker.
+    Macro {
+        name: Symbol,
+        /// Names which only match themselves in patterns
+        literals: Vec<Symbol>,
+        rules: Vec<Rule>,
+        /// Whether names bound in the templates are renamed on every expansion, so that they can't
+        /// capture names used at the call site, and free names in the templates refer to the top
+        /// level whatever is in scope at the call site. Only `syntax-rules` macros are hygienic.
+        hygienic: bool,
+        span: Index,
     },
     If {
         predicate: Box<Ast>,
         consequent: Box<Ast>,
         alternative: Option<Box<Ast>>,
+        span: Index,
+    },
+    Cond {
+        clauses: Vec<Clause>,
+        /// Body of the `else` clause
+        alternative: Option<Vec<Ast>>,
+        span: Index,
+    },
+    /// Evaluates the body of the first arm whose pattern matches the value
+    Match {
+        value: Box<Ast>,
+        arms: Vec<Arm>,
+        span: Index,
+    },
+    /// `let`, or `let*` if `sequential`, in which case each binding can see the ones before it
+    Let {
+        bindings: Vec<Binding>,
+        body: Vec<Ast>,
+        sequential: bool,
+        span: Index,
+    },
+    While {
+        predicate: Box<Ast>,
+        body: Vec<Ast>,
+        span: Index,
+    },
+    /// Infinite loop, only exited through `break`
+    Loop(Vec<Ast>, Index),
+    /// Exits the innermost loop, which evaluates to the value if one is given
+    Break(Option<Box<Ast>>, Index),
+    Continue(Index),
+    Block(Vec<Ast>, Index),
+    Primitive(CompilePrimitive, Index),
+    Asm(Vec<Token>, Index),
+    /// `(#intrinsic op (name (args...) ret))`, a function without a body that backends replace
+    /// with the instructions for `op`
+    Intrinsic {
+        op: IntrinsicOp,
+        name: Symbol,
+        /// Type parameters, for intrinsics that work on several types
+        params: Vec<Symbol>,
+        ty: Type,
+        args: Vec<Arg>,
+        span: Index,
+    },
+    Application(Vec<Ast>, Index),
+    Identifier(Symbol, Index),
+    /// `value.field`
+    Field {
+        value: Box<Ast>,
+        field: Symbol,
+        span: Index,
+    },
+    /// `(set! target value)`, where the target is a place as defined by `placep`
+    Set {
+        target: Box<Ast>,
+        value: Box<Ast>,
+        span: Index,
+    },
+    /// `value[index]`, the element at `index` of the memory `value` points to
+    Subscript {
+        value: Box<Ast>,
+        index: Box<Ast>,
+        span: Index,
     },
-    Block(Vec<Ast>),
-    Primitive(CompilePrimitive),
-    Asm(Vec<Token>),
-    Intrinsic(Vec<Ast>),
-    Application(Vec<Ast>),
-    Identifier(Symbol),
-    /*
+    /// `(deref pointer)`
+    Deref(Box<Ast>, Index),
+    /// `(addr-of place)`
+    AddrOf(Box<Ast>, Index),
+    /// `(as type value)`, converts `value` to another number type or pointer type
+    Cast {
+        ty: Type,
+        value: Box<Ast>,
+        span: Index,
+    },
+    /// `(lambda (args...) body...)`, an anonymous function whose return type is that of its body
     Lambda {
         args: Vec<Arg>,
-        ret_ty: Type,
         body: Vec<Ast>,
-    }
-    */
+        span: Index,
+    },
 }
 
 impl Ast {
     pub fn ty(&self) -> Type {
         use Ast::*;
         match self {
-            Identifier(_) => Type::Hole,
-            //Lambda { args, ret_ty, .. } =>
-            //    Type::Arrow(args.iter().map(|arg| arg.ty.clone()).collect(), Box::new(ret_ty.clone())),
-            Application(v) => v[0].ty(),
-            Primitive(p) => p.ty(),
+            Identifier(..) => Type::Hole,
+            // The return type is only known once the body has been checked
+            Lambda { .. } => Type::Hole,
+            Application(v, _) => v.first().map_or(Type::Empty, |f| f.ty()),
+            Primitive(p, _) => p.ty(),
             If { consequent, ..  } => consequent.ty(),
-            Block(v) => v.last().map_or(Type::Empty, |e| e.ty()),
-            _ => todo!(),
+            Cond { clauses, .. } => clauses[0].body.last().map_or(Type::Empty, |e| e.ty()),
+            Match { arms, .. } => arms.first()
+                .and_then(|arm| arm.body.last())
+                .map_or(Type::Empty, |e| e.ty()),
+            Block(v, _) => v.last().map_or(Type::Empty, |e| e.ty()),
+            Let { body, .. } => body.last().map_or(Type::Empty, |e| e.ty()),
+            While { .. } => Type::Empty,
+            // Depends on the breaks in the body
+            Loop(..) => Type::Hole,
+            Break(..) | Continue(..) => Type::Never,
+            // Items don't produce a value
+            Include { .. } | Define { .. } | Defn { .. } | Struct { .. } | Enum { .. } | Impl { .. } => Type::Empty,
+            Intrinsic { .. } | Export(..) | Macro { .. } => Type::Empty,
+            Field { .. } | Subscript { .. } | Deref(..) | AddrOf(..) => Type::Hole,
+            Cast { ty, .. } => ty.clone(),
+            Set { .. } => Type::Empty,
+            Asm(..) => Type::Hole,
+        }
+    }
+
+    /// Span of the source code this node was parsed from
+    pub fn span(&self) -> Index {
+        use Ast::*;
+        match self {
+            Include { span, .. } => *span,
+            Export(_, span) => *span,
+            Define { span, .. } => *span,
+            Defn { span, .. } => *span,
+            Struct { span, .. } => *span,
+            Enum { span, .. } => *span,
+            Impl { span, .. } => *span,
+            Macro { span, .. } => *span,
+            If { span, .. } => *span,
+            Cond { span, .. } => *span,
+            Match { span, .. } => *span,
+            Let { span, .. } => *span,
+            While { span, .. } => *span,
+            Loop(_, span) => *span,
+            Break(_, span) => *span,
+            Continue(span) => *span,
+            Block(_, span) => *span,
+            Primitive(_, span) => *span,
+            Asm(_, span) => *span,
+            Intrinsic { span, .. } => *span,
+            Application(_, span) => *span,
+            Identifier(_, span) => *span,
+            Field { span, .. } => *span,
+            Set { span, .. } => *span,
+            Subscript { span, .. } => *span,
+            Deref(_, span) => *span,
+            AddrOf(_, span) => *span,
+            Cast { span, .. } => *span,
+            Lambda { span, .. } => *span,
+        }
+    }
+
+    /// Short description of the kind of node, used in error messages
+    pub fn describe(&self) -> &'static str {
+        use Ast::*;
+        match self {
+            Include { .. } => "an include",
+            Export(..) => "an export list",
+            Define { .. } => "a definition",
+            Defn { .. } => "a function definition",
+            Struct { .. } => "a struct declaration",
+            Enum { .. } => "an enum declaration",
+            Impl { .. } => "an impl block",
+            Macro { .. } => "a macro definition",
+            If { .. } => "an if expression",
+            Cond { .. } => "a cond expression",
+            Match { .. } => "a match expression",
+            Let { .. } => "a let expression",
+            While { .. } => "a while loop",
+            Loop(..) => "a loop",
+            Break(..) => "a break",
+            Continue(..) => "a continue",
+            Block(..) => "a block",
+            Primitive(..) => "a literal",
+            Asm(..) => "inline assembly",
+            Intrinsic { .. } => "an intrinsic declaration",
+            Application(..) => "an application",
+            Identifier(..) => "an identifier",
+            Field { .. } => "a field access",
+            Set { .. } => "an assignment",
+            Subscript { .. } => "an index expression",
+            Deref(..) => "a dereference",
+            AddrOf(..) => "an address-of expression",
+            Cast { .. } => "a cast",
+            Lambda { .. } => "a lambda",
+        }
+    }
+
+    /// Whether this node produces a value, as opposed to being an item or inline assembly
+    pub fn valuep(&self) -> bool {
+        use Ast::*;
+        match self {
+            Identifier(..) | Primitive(..) | Application(..) | If { .. } | Cond { .. } | Let { .. } | Block(..) => true,
+            While { .. } | Loop(..) | Break(..) | Continue(..) => true,
+            Field { .. } | Set { .. } | Match { .. } => true,
+            Subscript { .. } | Deref(..) | AddrOf(..) | Cast { .. } | Lambda { .. } => true,
+            _ => false,
         }
     }
+
+    /// Whether this node names a location in memory, which can be assigned to and have its
+    /// address taken
+    pub fn placep(&self) -> bool {
+        use Ast::*;
+        matches!(self, Identifier(..) | Field { .. } | Subscript { .. } | Deref(..))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Arg {
     pub name: Symbol,
     pub ty: Type,
+    /// Span of the whole `[name type]` form
+    pub span: Index,
+    /// Span of the type annotation
+    pub ty_span: Index,
 }
 
 impl Arg {
-    pub fn new(name: Symbol, ty: Type) -> Self {
+    pub fn new(name: Symbol, ty: Type, span: Index, ty_span: Index) -> Self {
         Arg {
-            name: name,
-            ty: ty,
+            name,
+            ty,
+            span,
+            ty_span,
+        }
+    }
+}
+
+/// `(test body...)` arm of a `cond`
+#[derive(Clone, Debug)]
+pub struct Clause {
+    pub test: Ast,
+    pub body: Vec<Ast>,
+    pub span: Index,
+}
+
+/// `(Name payload...)` variant of an enum
+#[derive(Clone, Debug)]
+pub struct Variant {
+    pub name: Symbol,
+    pub fields: Vec<Type>,
+    pub span: Index,
+}
+
+/// `(pattern body...)` arm of a `match`
+#[derive(Clone, Debug)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Ast>,
+    pub span: Index,
+}
+
+#[derive(Clone, Debug, is_enum_variant)]
+pub enum Pattern {
+    /// `_`, which matches anything
+    Wildcard(Index),
+    /// Matches anything and binds it to the name
+    Binding(Symbol, Index),
+    /// `(Name pattern...)`, which matches the variant `Name` if its payload matches the patterns
+    Variant {
+        name: Symbol,
+        fields: Vec<Pattern>,
+        span: Index,
+    },
+}
+
+impl Pattern {
+    pub fn span(&self) -> Index {
+        match self {
+            Pattern::Wildcard(span) | Pattern::Binding(_, span) => *span,
+            Pattern::Variant { span, .. } => *span,
         }
     }
 }
 
+/// `((_ pattern...) template)` rule of a macro. A use of the macro is replaced by the template of
+/// the first rule whose pattern matches its arguments, with the pattern variables filled in.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    /// Pattern for the arguments, always a `MacroPattern::List`
+    pub pattern: MacroPattern,
+    pub template: Ast,
+    pub span: Index,
+}
+
+#[derive(Clone, Debug, is_enum_variant)]
+pub enum MacroPattern {
+    /// `_`, which matches any expression
+    Wildcard(Index),
+    /// Matches any expression and binds it to the name in the template
+    Var(Symbol, Index),
+    /// One of the macro's literals, which only matches an identifier with the same name
+    Literal(Symbol, Index),
+    /// `(pattern...)`, which matches an application whose elements match the patterns. The
+    /// pattern at `ellipsis`, if any, is followed by `...` and matches any number of elements.
+    List {
+        elements: Vec<MacroPattern>,
+        ellipsis: Option<usize>,
+        span: Index,
+    },
+}
+
+/// Local variable introduced by `let`
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub name: Symbol,
+    /// `Type::Hole` if the type is to be inferred from the value
+    pub ty: Type,
+    pub value: Ast,
+    /// Span of the whole `[name type value]` form
+    pub span: Index,
+    /// Span of the type annotation, if one was given
+    pub ty_span: Option<Index>,
+}
+
 #[derive(Clone, Debug, PartialEq, is_enum_variant)]
 pub enum Type {
     U8,
+    U16,
+    U32,
+    U64,
     Usize,
+    I8,
+    I16,
     I32,
+    I64,
+    Isize,
+    F32,
+    F64,
+    /// Unicode scalar value
+    Char,
     Bool,
     String,
     Ptr(Box<Type>),
+    /// User defined struct or enum, referred to by name along with any type arguments
+    Struct(Symbol, Vec<Type>),
+    /// Type parameter of a generic item
+    Var(Symbol),
     /// Function type
     Arrow(Vec<Type>, Box<Type>),
     /// ()
@@ -96,19 +440,86 @@ pub enum Type {
 }
 
 impl Type {
-    pub fn from_token(token: Token, input: &str, inner_ty: Option<Type>) -> Self {
-        match token.as_str(input) {
+    /// Reads the type named by `token`. `args` are the arguments of a type application such as
+    /// `(ptr u8)` or `(ArrayBuf T)`.
+    pub fn from_token(token: Token, input: &str, args: Vec<Type>) -> Result<Self> {
+        Type::from_name(token.as_str(input), token.index(), args)
+    }
+
+    /// Same as `from_token`, for names that aren't a whole token such as the `ptr` in `<ptr u8>`.
+    pub fn from_name(name: &str, index: Index, mut args: Vec<Type>) -> Result<Self> {
+        if !args.is_empty() {
+            return match name {
+                "ptr" if args.len() == 1 => Ok(Type::Ptr(Box::new(args.remove(0)))),
+                "ptr" => Err(ParserError::new(ParserErrorKind::Type, index)
+                             .expected("a single pointee type")
+                             .found(format!("{} types", args.len()))),
+                _ if Type::from_name(name, index, Vec::new())?.is_struct() => {
+                    Ok(Type::Struct(intern(name), args))
+                }
+                _ => Err(ParserError::new(ParserErrorKind::Type, index)
+                         .expected("`ptr` or a generic struct")
+                         .found(format!("`{}`", name))),
+            };
+        }
+
+        Ok(match name {
             "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
             "usize" => Type::Usize,
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "isize" => Type::Isize,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "char" => Type::Char,
             "bool" => Type::Bool,
             "string" => Type::String,
-            "i32" => Type::I32,
             "!" => Type::Never,
-            "ptr" if inner_ty.is_some() => Type::Ptr(Box::new(inner_ty.unwrap())),
-            _ => todo!(),
+            "ptr" => return Err(ParserError::new(ParserErrorKind::Type, index)
+                                .expected("a pointee type, as in `(ptr u8)`")
+                                .found("`ptr`")),
+            // Whether the struct exists is up to the type checker
+            _ => Type::Struct(intern(name), Vec::new()),
+        })
+    }
+
+    pub fn from_suffix(suffix: IntSuffix) -> Self {
+        match suffix {
+            IntSuffix::U8 => Type::U8,
+            IntSuffix::U16 => Type::U16,
+            IntSuffix::U32 => Type::U32,
+            IntSuffix::U64 => Type::U64,
+            IntSuffix::Usize => Type::Usize,
+            IntSuffix::I8 => Type::I8,
+            IntSuffix::I16 => Type::I16,
+            IntSuffix::I32 => Type::I32,
+            IntSuffix::I64 => Type::I64,
+            IntSuffix::Isize => Type::Isize,
         }
     }
 
+    /// Inverse of `from_suffix`, `None` for anything but an integer type.
+    pub fn int_suffix(&self) -> Option<IntSuffix> {
+        Some(match self {
+            Type::U8 => IntSuffix::U8,
+            Type::U16 => IntSuffix::U16,
+            Type::U32 => IntSuffix::U32,
+            Type::U64 => IntSuffix::U64,
+            Type::Usize => IntSuffix::Usize,
+            Type::I8 => IntSuffix::I8,
+            Type::I16 => IntSuffix::I16,
+            Type::I32 => IntSuffix::I32,
+            Type::I64 => IntSuffix::I64,
+            Type::Isize => IntSuffix::Isize,
+            _ => return None,
+        })
+    }
+
     pub fn arrow_split(&self) -> (Vec<Self>, Self) {
         match self {
             Type::Arrow(args, ty) => (args.clone(), *ty.clone()),
@@ -117,10 +528,67 @@ impl Type {
     }
 }
 
+/// Types are written the way they appear in source, e.g. `(ptr (ArrayBuf u8))`.
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Type::U8 => write!(f, "u8"),
+            Type::U16 => write!(f, "u16"),
+            Type::U32 => write!(f, "u32"),
+            Type::U64 => write!(f, "u64"),
+            Type::Usize => write!(f, "usize"),
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::Isize => write!(f, "isize"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::Char => write!(f, "char"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Ptr(ty) => write!(f, "(ptr {})", ty),
+            Type::Struct(name, args) if args.is_empty() => write!(f, "{}", symbol_name(*name)),
+            Type::Struct(name, args) => {
+                write!(f, "({}", symbol_name(*name))?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Type::Var(name) => write!(f, "{}", symbol_name(*name)),
+            Type::Arrow(args, ret) => {
+                write!(f, "(fn (")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ") {})", ret)
+            }
+            Type::Empty => write!(f, "()"),
+            Type::Never => write!(f, "!"),
+            Type::Hole => write!(f, "_"),
+        }
+    }
+}
+
+fn symbol_name(s: Symbol) -> String {
+    string_interner::get_value(s).unwrap_or_else(|| "?".into())
+}
+
 #[derive(Clone, Debug)]
 pub enum CompilePrimitive {
-    Integer(i32),
-    String(String),
+    /// Integers without a suffix are typed as `i32`, unless they are used where another integer
+    /// or float type is expected
+    Integer(IntegerLiteral),
+    /// Floats are typed as `f64`, unless they are used where an `f32` is expected
+    Float(f64),
+    /// Unicode scalar value, or a byte given by a `\xNN` escape
+    Char(u32),
+    /// Decoded contents of a string literal
+    String(Vec<u8>),
     Bool(bool),
 }
 
@@ -128,402 +596,1605 @@ impl CompilePrimitive {
     pub fn ty(&self) -> Type {
         use CompilePrimitive::*;
         match self {
-            Integer(_) => Type::I32,
+            Integer(i) => i.suffix.map_or(Type::I32, Type::from_suffix),
+            Float(_) => Type::F64,
+            Char(_) => Type::Char,
             String(_) => Type::String,
             Bool(_) => Type::Bool,
         }
     }
 }
 
-pub fn parse(tokens: Vec<Token>, input: &str) -> Result<Vec<Ast>> {
+
+/// Parses every item in `tokens`, returning all errors found if there are any.
+pub fn parse(tokens: Vec<Token>, input: &str) -> std::result::Result<Vec<Ast>, Vec<ParserError>> {
+    let (ast, errors) = parse_partial(tokens, input);
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses as much of `tokens` as possible. Items containing errors are left out of the returned
+/// `Ast`s. After an error parsing resumes at the end of the offending expression, or at the next
+/// top level item if the expression was never closed.
+pub fn parse_partial(tokens: Vec<Token>, input: &str) -> (Vec<Ast>, Vec<ParserError>) {
     let mut ast = Vec::new();
     let mut tokens = Tokens::new(tokens);
     while tokens.peek().is_some() {
-        if tokens.peek().unwrap().commentp() {
-            tokens.next();
-            continue;
+        let start = tokens.position;
+        let errors = tokens.errors.len();
+        match parse_item(&mut tokens, input) {
+            Ok(item) => if tokens.errors.len() == errors {
+                ast.push(item);
+            },
+            Err(e) => {
+                let closed = tokens.recover(start, input);
+                if e.kind != ParserErrorKind::EOI || tokens.peek().is_none() {
+                    tokens.errors.push(e);
+                }
+                if let (false, Some(t)) = (closed, tokens.peek()) {
+                    // Anything after the next item was parsed as part of this one, so those
+                    // errors will be found again.
+                    let resume = t.index().start();
+                    tokens.errors.retain(|e| e.span.start() < resume);
+                    tokens.errors.push(ParserError::new(ParserErrorKind::Closer, t.index())
+                                       .expected("a closer")
+                                       .found("the start of a new item"));
+                }
+            }
+        }
+    }
+
+    let mut errors = tokens.errors;
+    errors.sort_by_key(|e| e.span.start());
+    (ast, errors)
+}
+
+fn parse_item(tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    if let Some(expr) = parse_expr(tokens, input)? {
+        match expr {
+            Ast::Include { .. } | Ast::Export(..) | Ast::Define { .. } | Ast::Defn { .. } => Ok(expr),
+            Ast::Intrinsic { .. } => Ok(expr),
+            Ast::Struct { .. } | Ast::Enum { .. } | Ast::Impl { .. } | Ast::Macro { .. } => Ok(expr),
+            // Possibly the use of a macro, whose expansion `expand` checks is made of items
+            Ast::Application(ref elements, _) if elements.first().is_some_and(Ast::is_identifier) => Ok(expr),
+            _ => Err(ParserError::new(ParserErrorKind::Item, expr.span())
+                     .expected("an include, export, define, defn, intrinsic, struct, enum, impl, macro or use of a macro")
+                     .found(expr.describe())),
         }
+    } else {
+        // parse_expr only returns None after reading a closer
+        let t = tokens.last().unwrap();
+        Err(unexpected(ParserErrorKind::Closer, t, input, "an item"))
+    }
+}
 
-        if let Some(expr) = parse_expr(&mut tokens, input)? {
-            match expr {
-                Ast::Include(_) => ast.push(expr),
-                Ast::Define { .. } => ast.push(expr),
-                Ast::Defn { .. } => ast.push(expr),
-                _ => return Err(ParserError::Item),
+/// Parses the remaining expressions of a list, passing each to `f`. An expression which fails to
+/// parse is skipped and its error recorded so that the rest of the list can still be checked.
+/// Errors are only returned if the list couldn't be closed.
+fn parse_list<F>(open: Token, tokens: &mut Tokens, input: &str, mut f: F) -> Result<()>
+    where F: FnMut(&mut Tokens, Ast) -> Result<()>
+{
+    loop {
+        let start = tokens.position;
+        match parse_expr(tokens, input) {
+            Ok(Some(expr)) => if let Err(e) = f(tokens, expr) {
+                tokens.errors.push(e);
+            },
+            Ok(None) => {
+                let close = tokens.last().unwrap();
+                match_closer(open, close, tokens, input);
+                return Ok(());
             }
-        } else {
-            return Err(ParserError::Closer);
+            Err(e) => if tokens.recover(start, input) {
+                tokens.errors.push(e);
+            } else {
+                return Err(e);
+            },
         }
     }
-    Ok(ast)
 }
 
 macro_rules! next {
-    ( $token:ident, $tokens:ident, $body:expr) => {
+    ( $token:ident, $tokens:ident, $expected:expr, $body:expr) => {
         if let Some($token) = $tokens.next() {
             $body
         } else {
-            return Err(ParserError::EOI);
+            return Err($tokens.eoi($expected));
         }
     };
 }
 
 fn get_symbol(token: Token, input: &str) -> Symbol {
-    INTERNER.lock().unwrap().get_symbol(token.as_str(input).into())
+    intern(token.as_str(input))
+}
+
+fn intern(s: &str) -> Symbol {
+    INTERNER.lock().unwrap().get_symbol(s.into())
+}
+
+/// Describes a token for use in error messages
+fn describe(token: Token, input: &str) -> String {
+    format!("`{}`", token.as_str(input))
+}
+
+fn unexpected(kind: ParserErrorKind, token: Token, input: &str, expected: &'static str) -> ParserError {
+    ParserError::new(kind, token.index())
+        .expected(expected)
+        .found(describe(token, input))
 }
 
+/// Parses the next expression, returning `None` if the current list was closed instead.
 fn parse_expr(tokens: &mut Tokens, input: &str) -> Result<Option<Ast>> {
     if let Some(token) = tokens.next() {
         match token {
-            t if t.commentp() => parse_expr(tokens, input),
             t if t.closerp() => Ok(None),
-            t if t.openerp() => Ok(Some(parse_paren_expr(tokens, input)?)),
-            t @ Token::Symbol(_) => Ok(Some(Ast::Identifier(get_symbol(t, input)))),
-            t @ Token::String(_) => Ok(Some(Ast::Primitive(CompilePrimitive::String(t.as_str(input).into())))),
-            // TODO: need to check that this token fits an i32
-            t @ Token::Integer(_) => Ok(Some(Ast::Primitive(CompilePrimitive::Integer(t.as_str(input).parse().unwrap())))),
-            Token::Pound(_) => Ok(Some(parse_pound(tokens, input)?)),
-            _ => todo!(),
+            t @ Token::LParen(_) => Ok(Some(parse_paren_expr(t, tokens, input)?)),
+            t @ Token::LBrace(_) => Ok(Some(handle_brace(t, tokens, input)?)),
+            t @ Token::LSBracket(_) => Err(ParserError::new(ParserErrorKind::Bracket, t.index())
+                                           .expected("an expression")
+                                           .found("`[`")),
+            t @ Token::Symbol(_) => Ok(Some(parse_subscripts(t, parse_symbol(t, input), tokens, input)?)),
+            t @ Token::String(_) => match t.string_bytes(input) {
+                Ok(s) => Ok(Some(Ast::Primitive(CompilePrimitive::String(s), t.index()))),
+                Err(e) => Err(ParserError::new(ParserErrorKind::Literal, e.index).found(e.kind.to_string())),
+            },
+            t @ Token::Integer(_) => Ok(Some(Ast::Primitive(parse_integer(t, input)?, t.index()))),
+            t @ Token::Pound(_) => Ok(Some(parse_pound(t, tokens, input)?)),
+            t @ Token::Char(_) => match t.char_value(input) {
+                Ok(c) => Ok(Some(Ast::Primitive(CompilePrimitive::Char(c), t.index()))),
+                Err(e) => Err(ParserError::new(ParserErrorKind::Literal, e.index).found(e.kind.to_string())),
+            },
+            t @ Token::Float(_) => match t.as_str(input).parse() {
+                Ok(f) => Ok(Some(Ast::Primitive(CompilePrimitive::Float(f), t.index()))),
+                Err(_) => Err(unexpected(ParserErrorKind::Token, t, input, "a float")),
+            },
+            // Comments are filtered out by Tokens
+            t => Err(unexpected(ParserErrorKind::Token, t, input, "an expression")),
         }
     } else {
-        Ok(None)
+        Err(tokens.eoi("an expression or a closer"))
+    }
+}
+
+/// Parses an identifier, or field accesses if it is of the form `value.field`.
+fn parse_symbol(token: Token, input: &str) -> Ast {
+    let index = token.index();
+    let s = token.as_str(input);
+    if s.contains('|') || s.split('.').any(|part| part.is_empty()) {
+        return Ast::Identifier(get_symbol(token, input), index);
+    }
+
+    let mut parts = s.split('.');
+    let first = parts.next().unwrap();
+    let mut end = index.start() + first.len();
+    let mut ast = Ast::Identifier(intern(first), Index::new(index.file(), index.start() + 1, end));
+    for part in parts {
+        end += part.len() + 1;
+        ast = Ast::Field {
+            value: Box::new(ast),
+            field: intern(part),
+            span: Index::new(index.file(), index.start() + 1, end),
+        };
+    }
+    ast
+}
+
+/// Parses `value[index]`, where the `[` directly follows `value`, which was read starting at
+/// `start`. Subscripts can be chained as in `grid[i][j]`.
+fn parse_subscripts(start: Token, mut value: Ast, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    while let Some(open) = tokens.peek() {
+        if !open.is_ls_bracket() || open.index().start() != value.span().end() {
+            break;
+        }
+        tokens.next();
+
+        let index = match parse_expr(tokens, input)? {
+            Some(expr) => if expr.valuep() {
+                expr
+            } else {
+                return Err(not_a_value(&expr));
+            },
+            None => return Err(missing(tokens, "an index")),
+        };
+        handle_closer(open, tokens, input)?;
+        value = Ast::Subscript {
+            value: Box::new(value),
+            index: Box::new(index),
+            span: tokens.span_from(start),
+        };
+    }
+    Ok(value)
+}
+
+fn parse_integer(token: Token, input: &str) -> Result<CompilePrimitive> {
+    // Suffixed literals have already been range checked by the tokenizer, the others are checked
+    // against the type they are given by the type checker
+    match token.integer(input) {
+        Some(integer) => Ok(CompilePrimitive::Integer(integer)),
+        None => Err(unexpected(ParserErrorKind::Token, token, input, "an integer")),
     }
 }
 
-fn parse_pound(tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    next!(token, tokens, {
+fn parse_pound(pound: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    next!(token, tokens, "a literal after `#`", {
+        let span = pound.index().to(token.index());
         match token {
             t @ Token::Symbol(_) => match t.as_str(input) {
                 // We should only encounter #asm at the beginning of a paren expression so this is
                 // handled in parse_paren_expr
-                "asm" => return Err(ParserError::Token),
-                "t" => Ok(Ast::Primitive(CompilePrimitive::Bool(true))),
-                "f" => Ok(Ast::Primitive(CompilePrimitive::Bool(false))),
-                _ => todo!(),
+                "asm" => return Err(ParserError::new(ParserErrorKind::Token, span)
+                                    .expected("`#asm` at the start of a list")
+                                    .found("`#asm`")),
+                "t" => Ok(Ast::Primitive(CompilePrimitive::Bool(true), span)),
+                "f" => Ok(Ast::Primitive(CompilePrimitive::Bool(false), span)),
+                _ => Err(ParserError::new(ParserErrorKind::Token, span)
+                         .expected("`#t` or `#f`")
+                         .found(format!("`#{}`", t.as_str(input)))),
             },
-            _ => todo!(),
+            t => Err(unexpected(ParserErrorKind::Token, t, input, "`#t` or `#f`")),
         }
     })
 }
 
-fn parse_paren_expr(tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    next!(token, tokens, {
+fn parse_paren_expr(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    next!(token, tokens, "an expression", {
         match token {
-            t if t.commentp() => parse_paren_expr(tokens, input),
-            t @ Token::Symbol(_) => return parse_identifier(t, tokens, input),
-            Token::Pound(_) => if let Some(t) = tokens.peek() {
-                if t.is_symbol() {
-                    match t.as_str(input) {
-                        "asm" => {
-                            tokens.next();
-                            handle_inline_asm(tokens, input)
-                        }
-                        "intrinsic" => {
-                            tokens.next();
-                            handle_intrinsic(tokens, input)
-                        },
-                        _ => todo!(),
-                    }
+            t @ Token::Symbol(_) => parse_identifier(open, t, tokens, input),
+            pound @ Token::Pound(_) => next!(t, tokens, "`asm` or `intrinsic`", {
+                match t {
+                    Token::Symbol(_) if t.as_str(input) == "asm" => handle_inline_asm(open, tokens, input),
+                    Token::Symbol(_) if t.as_str(input) == "intrinsic" => handle_intrinsic(open, tokens, input),
+                    _ => Err(ParserError::new(ParserErrorKind::Token, pound.index().to(t.index()))
+                             .expected("`#asm` or `#intrinsic`")
+                             .found(format!("`#{}`", t.as_str(input)))),
+                }
+            }),
+            // Calls through a function that is the result of an expression
+            t @ Token::LParen(_) | t @ Token::LBrace(_) => {
+                let callee = if t.is_l_paren() {
+                    parse_paren_expr(t, tokens, input)?
                 } else {
-                    todo!()
+                    handle_brace(t, tokens, input)?
+                };
+                if !callee.valuep() {
+                    return Err(not_a_value(&callee));
                 }
-            } else {
-                return Err(ParserError::EOI);
-            },
-            _ => todo!(),
+                handle_application(open, callee, tokens, input)
+            }
+            t if t.closerp() => Err(unexpected(ParserErrorKind::Token, t, input, "an expression")),
+            t => Err(unexpected(ParserErrorKind::Token, t, input, "a symbol, `#asm` or `#intrinsic`")),
         }
     })
 }
 
-fn handle_inline_asm(tokens: &mut Tokens, _input: &str) -> Result<Ast> {
+fn handle_inline_asm(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
     let mut asm = Vec::new();
-    let mut openers = 1;
+    let mut openers = vec![open];
     while let Some(t) = tokens.next() {
         if t.openerp() {
-            openers += 1;
+            openers.push(t);
         } else if t.closerp() {
-            if openers == 1 {
-                return Ok(Ast::Asm(asm));
-            } else {
-                openers -= 1;
+            match_closer(openers.pop().unwrap(), t, tokens, input);
+            if openers.is_empty() {
+                return Ok(Ast::Asm(asm, open.index().to(t.index())));
             }
         }
         asm.push(t);
     }
 
-    return Err(ParserError::EOI);
+    return Err(tokens.eoi("a closer"));
 }
 
-// TODO
-fn handle_intrinsic(tokens: &mut Tokens, _input: &str) -> Result<Ast> {
-    let mut application = Vec::new();
-    /*
-    application.push(Ast::Identifier(get_symbol(t, input)));
-    while let Some(expr) = parse_expr(tokens, input)? {
-        if expr.is_identifier() || expr.is_primitive() || expr.is_application() {
-            application.push(expr);
-        } else {
-            return Err(ParserError::Value);
+/// `(#intrinsic op (name (args...) ret))`, where the name may be generic as for `defn`.
+fn handle_intrinsic(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let op = next!(t, tokens, "an intrinsic operation", {
+        match IntrinsicOp::from_name(t.as_str(input)) {
+            Some(op) if t.is_symbol() => op,
+            _ => return Err(unexpected(ParserErrorKind::Intrinsic, t, input, "an intrinsic operation")),
         }
-    }
-    */
+    });
+    let signature = next!(t, tokens, "a function signature", {
+        if !t.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a function signature"));
+        }
+        t
+    });
 
-    Ok(Ast::Intrinsic(application))
+    let (name, params) = read_generic_name("a function name", tokens, input)?;
+    with_type_params(&params, tokens, |tokens| {
+        let arg_list = next!(t, tokens, "an argument list", {
+            if !t.is_l_paren() {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "an argument list"));
+            }
+            t
+        });
+        let args = read_args(arg_list, "an argument", tokens, input)?;
+        let ret_ty = match tokens.peek() {
+            Some(t) if t.closerp() => Type::Empty,
+            Some(_) => read_type(tokens, input)?.0,
+            None => return Err(tokens.eoi("a return type or a closer")),
+        };
+        handle_closer(signature, tokens, input)?;
+        handle_closer(open, tokens, input)?;
+
+        Ok(Ast::Intrinsic {
+            op,
+            name,
+            params: params.clone(),
+            ty: Type::Arrow(args.iter().map(|arg| arg.ty.clone()).collect(), Box::new(ret_ty)),
+            args,
+            span: tokens.span_from(open),
+        })
+    })
 }
 
-fn parse_identifier(t: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+fn parse_identifier(open: Token, t: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
     match t.as_str(input) {
-        "include" => handle_include(tokens, input),
-        "define" => handle_define(tokens, input),
-        "defn" => handle_defn(tokens, input),
-        "if" => handle_if(tokens, input),
-        "begin" => handle_block(tokens, input),
-        _ => handle_application(t, tokens, input),
+        "include" => handle_include(open, tokens, input),
+        "export" => handle_export(open, tokens, input),
+        "define" => handle_define(open, tokens, input),
+        "defn" => handle_defn(open, tokens, input),
+        "struct" => handle_struct(open, tokens, input),
+        "enum" => handle_enum(open, tokens, input),
+        "impl" => handle_impl(open, tokens, input),
+        "define-syntax" => handle_define_syntax(open, tokens, input),
+        "defmacro" => handle_defmacro(open, tokens, input),
+        "set!" | "set" => handle_set(open, tokens, input),
+        "deref" => handle_deref(open, tokens, input),
+        "addr-of" => handle_addr_of(open, tokens, input),
+        "as" => handle_cast(open, tokens, input),
+        "lambda" => handle_lambda(open, tokens, input),
+        "if" => handle_if(open, tokens, input),
+        "cond" => handle_cond(open, tokens, input),
+        "match" => handle_match(open, tokens, input),
+        "begin" => handle_block(open, tokens, input),
+        "let" => handle_let(open, false, tokens, input),
+        "let*" => handle_let(open, true, tokens, input),
+        "while" => handle_while(open, tokens, input),
+        "loop" => handle_loop(open, tokens, input),
+        "break" => handle_break(open, tokens, input),
+        "continue" => handle_continue(open, tokens, input),
+        _ => handle_application(open, parse_symbol(t, input), tokens, input),
     }
 }
 
-fn handle_block(tokens: &mut Tokens, input: &str) -> Result<Ast> {
+/// `{...}` is shorthand for `(begin ...)`. A leading `begin` is allowed as well.
+fn handle_brace(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    match tokens.peek() {
+        Some(t) if t.is_symbol() && t.as_str(input) == "begin" => {
+            tokens.next();
+            handle_block(open, tokens, input)
+        }
+        Some(t) if t.is_symbol() && t.as_str(input) == "impl" => {
+            tokens.next();
+            handle_impl(open, tokens, input)
+        }
+        _ => handle_block(open, tokens, input),
+    }
+}
+
+fn handle_block(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
     let mut body = Vec::new();
-    while let Some(expr) = parse_expr(tokens, input)? {
+    parse_list(open, tokens, input, |_, expr| {
         body.push(expr);
-    }
-    Ok(Ast::Block(body))
+        Ok(())
+    })?;
+    Ok(Ast::Block(body, tokens.span_from(open)))
 }
 
-fn handle_if(tokens: &mut Tokens, input: &str) -> Result<Ast> {
+/// Error for an expression that isn't allowed where a value is expected
+fn not_a_value(expr: &Ast) -> ParserError {
+    ParserError::new(ParserErrorKind::Value, expr.span())
+        .expected("an expression")
+        .found(expr.describe())
+}
+
+/// Error for a list that ended before all of its required parts were given
+fn missing(tokens: &Tokens, expected: &'static str) -> ParserError {
+    // We only get here after reading a closer
+    ParserError::new(ParserErrorKind::Closer, tokens.last().unwrap().index())
+        .expected(expected)
+        .found("a closer")
+}
+
+fn handle_if(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
     let predicate = if let Some(expr) = parse_expr(tokens, input)? {
-        match expr {
-            Ast::Identifier(_) | Ast::Primitive(_) | Ast::Application(_) | Ast::If { .. } | Ast::Block(_) => expr,
-            _ => return Err(ParserError::Value),
+        if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
         }
     } else {
-        return Err(ParserError::Closer);
+        return Err(missing(tokens, "a predicate"));
     };
 
     let consequent = if let Some(expr) = parse_expr(tokens, input)? {
-        match expr {
-            Ast::Identifier(_) | Ast::Primitive(_) | Ast::Application(_) | Ast::If { .. } | Ast::Block(_) => expr,
-            _ => return Err(ParserError::Value),
+        if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
         }
     } else {
-        return Err(ParserError::Closer);
+        return Err(missing(tokens, "a consequent"));
     };
 
     let alternative = if let Some(t) = tokens.peek() {
         if t.closerp() {
             None
         } else {
-            let expr = parse_expr(tokens, input)?.unwrap();
-            match expr {
-                Ast::Identifier(_) | Ast::Primitive(_) | Ast::Application(_) | Ast::If { .. } | Ast::Block(_) => Some(Box::new(expr)),
-                _ => return Err(ParserError::Value),
+            match parse_expr(tokens, input)? {
+                Some(expr) => if expr.valuep() {
+                    Some(Box::new(expr))
+                } else {
+                    return Err(not_a_value(&expr));
+                },
+                // Only reachable if the peeked token was a closer
+                None => return Err(missing(tokens, "an alternative")),
             }
         }
     } else {
-        return Err(ParserError::EOI);
+        return Err(tokens.eoi("an alternative or a closer"));
     };
 
-    handle_closer(tokens)?;
+    handle_closer(open, tokens, input)?;
     Ok(Ast::If {
         predicate: Box::new(predicate),
         consequent: Box::new(consequent),
         alternative: alternative,
+        span: tokens.span_from(open),
     })
 }
 
-fn handle_define(tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    let name = next!(t, tokens, {
-        if t.is_symbol() {
-            get_symbol(t, input)
-        } else {
-            return Err(ParserError::Token);
-        }
-    });
-
-    let value = if let Some(expr) = parse_expr(tokens, input)? {
-        match expr {
-            Ast::Identifier(_) | Ast::Primitive(_) | Ast::Application(_) => expr,
-            _ => return Err(ParserError::Value),
-        }
-    } else {
-        return Err(ParserError::Closer);
-    };
-
-    handle_closer(tokens)?;
-
-    Ok(Ast::Define{
-        name: name,
-        ty: value.ty(),
-        value: Box::new(value),
-    })
-}
-
-fn handle_defn(tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    next!(token, tokens, {
-        if !token.openerp() {
-            return Err(ParserError::Token);
-        }
-    });
-
-    let name = next!(token, tokens, {
-        if token.is_symbol() {
-            get_symbol(token, input)
-        } else {
-            return Err(ParserError::Token);
-        }
-    });
-
-    // Beginning of argument list
-    next!(token, tokens, {
-        if !token.openerp() {
-            return Err(ParserError::Token);
+fn handle_let(open: Token, sequential: bool, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let list = next!(token, tokens, "a binding list", {
+        if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, "a binding list"));
         }
+        token
     });
 
-    let mut args = Vec::new();
-    // Each argument is of the form `(ident type)`
+    let mut bindings = Vec::new();
+    // Each binding is of the form `[ident type value]` or `[ident value]`
     loop {
-        // Read the argument opener
-        next!(token, tokens, {
-            // A closer here denotes the end of the argument list.
+        let binding_open = next!(token, tokens, "a binding", {
             if token.closerp() {
+                match_closer(list, token, tokens, input);
                 break;
-            } else if !token.openerp() {
-                return Err(ParserError::Token);
+            } else if !(token.is_ls_bracket() || token.is_l_paren()) {
+                return Err(unexpected(ParserErrorKind::Token, token, input, "a binding"));
             }
+            token
         });
 
-        let arg_name = next!(token, tokens, {
+        let name = next!(token, tokens, "a variable name", {
             if token.is_symbol() {
                 get_symbol(token, input)
             } else {
-                return Err(ParserError::Token);
+                return Err(unexpected(ParserErrorKind::Token, token, input, "a variable name"));
             }
         });
 
-        let arg_type = read_type(tokens, input)?;
+        // A type is only present if it is followed by a value
+        let start = tokens.position;
+        let typed = tokens.peek().is_some_and(|t| !t.closerp())
+            && tokens.recover(start, input)
+            && tokens.peek().is_some_and(|t| !t.closerp());
+        tokens.position = start;
 
-        // Read the argument closer
-        handle_closer(tokens)?;
+        let (ty, ty_span) = if typed {
+            let (ty, span) = read_type(tokens, input)?;
+            (ty, Some(span))
+        } else {
+            (Type::Hole, None)
+        };
+
+        let value = match parse_expr(tokens, input)? {
+            Some(expr) => if expr.valuep() {
+                expr
+            } else {
+                return Err(not_a_value(&expr));
+            },
+            None => return Err(missing(tokens, "a value")),
+        };
 
-        args.push(Arg::new(arg_name, arg_type));
+        handle_closer(binding_open, tokens, input)?;
+        bindings.push(Binding {
+            name,
+            ty,
+            value,
+            span: tokens.span_from(binding_open),
+            ty_span,
+        });
     }
 
-    let ret_ty = if let Some(t) = tokens.peek() {
-        if t.closerp() {
-            Type::Empty
+    let mut body = Vec::new();
+    parse_list(open, tokens, input, |_, expr| {
+        body.push(expr);
+        Ok(())
+    })?;
+
+    Ok(Ast::Let {
+        bindings,
+        body,
+        sequential,
+        span: tokens.span_from(open),
+    })
+}
+
+fn handle_while(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let predicate = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            expr
         } else {
-            read_type(tokens, input)?
-        }
-    } else {
-        return Err(ParserError::EOI);
+            return Err(not_a_value(&expr));
+        },
+        None => return Err(missing(tokens, "a predicate")),
     };
 
-    // End of function preamble
-    handle_closer(tokens)?;
+    let body = parse_loop_body(open, tokens, input)?;
+    Ok(Ast::While {
+        predicate: Box::new(predicate),
+        body,
+        span: tokens.span_from(open),
+    })
+}
+
+fn handle_loop(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let body = parse_loop_body(open, tokens, input)?;
+    Ok(Ast::Loop(body, tokens.span_from(open)))
+}
 
+fn parse_loop_body(open: Token, tokens: &mut Tokens, input: &str) -> Result<Vec<Ast>> {
     let mut body = Vec::new();
-    while let Some(expr) = parse_expr(tokens, input)? {
-        if expr.is_identifier() || expr.is_primitive() {
-            if !tokens.peek().map(|t| t.closerp()).unwrap_or(false) {
-                return Err(ParserError::NonfinalValue);
-            }
-        } else if expr.is_asm() {
-            if body.len() != 0 || !tokens.peek().map(|t| t.closerp()).unwrap_or(false) {
-                return Err(ParserError::Asm);
-            }
-        }
+    tokens.loops += 1;
+    let result = parse_list(open, tokens, input, |_, expr| {
         body.push(expr);
-    }
+        Ok(())
+    });
+    tokens.loops -= 1;
+    result.map(|_| body)
+}
 
-    if let Some(l) = body.last() {
-        if (l.is_define() || l.is_defn() || l.is_include()) && ret_ty != Type::Empty {
-            return Err(ParserError::ReturnType);
+fn handle_break(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let value = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            handle_closer(open, tokens, input)?;
+            Some(Box::new(expr))
+        } else {
+            return Err(not_a_value(&expr));
+        },
+        None => {
+            match_closer(open, tokens.last().unwrap(), tokens, input);
+            None
         }
-    } else if ret_ty != Type::Empty {
-        return Err(ParserError::ReturnType);
+    };
+
+    let span = tokens.span_from(open);
+    if tokens.loops == 0 {
+        return Err(ParserError::new(ParserErrorKind::Loop, span));
     }
+    Ok(Ast::Break(value, span))
+}
 
-    let ty = Type::Arrow(args.iter().map(|arg| arg.ty.clone()).collect(), Box::new(ret_ty.clone()));
-    Ok(Ast::Defn {
+fn handle_continue(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    handle_closer(open, tokens, input)?;
+    let span = tokens.span_from(open);
+    if tokens.loops == 0 {
+        return Err(ParserError::new(ParserErrorKind::Loop, span));
+    }
+    Ok(Ast::Continue(span))
+}
+
+fn handle_cond(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let mut clauses = Vec::new();
+    let mut alternative: Option<Vec<Ast>> = None;
+    loop {
+        let clause_open = next!(token, tokens, "a clause", {
+            if token.closerp() {
+                match_closer(open, token, tokens, input);
+                break;
+            } else if !token.is_l_paren() {
+                return Err(unexpected(ParserErrorKind::Token, token, input, "a clause"));
+            }
+            token
+        });
+
+        if alternative.is_some() {
+            return Err(ParserError::new(ParserErrorKind::Else, clause_open.index()));
+        }
+
+        let is_else = tokens.peek().is_some_and(|t| t.is_symbol() && t.as_str(input) == "else");
+        let test = if is_else {
+            tokens.next();
+            None
+        } else {
+            match parse_expr(tokens, input)? {
+                Some(expr) => if expr.valuep() {
+                    Some(expr)
+                } else {
+                    return Err(not_a_value(&expr));
+                },
+                None => return Err(missing(tokens, "a test")),
+            }
+        };
+
+        let mut body = Vec::new();
+        parse_list(clause_open, tokens, input, |_, expr| {
+            body.push(expr);
+            Ok(())
+        })?;
+
+        match test {
+            Some(test) => clauses.push(Clause {
+                test,
+                body,
+                span: tokens.span_from(clause_open),
+            }),
+            None => alternative = Some(body),
+        }
+    }
+
+    if clauses.is_empty() {
+        return Err(ParserError::new(ParserErrorKind::Closer, tokens.span_from(open))
+                   .expected("a clause with a test")
+                   .found("a closer"));
+    }
+
+    Ok(Ast::Cond {
+        clauses,
+        alternative,
+        span: tokens.span_from(open),
+    })
+}
+
+fn handle_match(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let value = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
+        },
+        None => return Err(missing(tokens, "a value to match")),
+    };
+
+    let mut arms = Vec::new();
+    loop {
+        let arm_open = next!(token, tokens, "an arm", {
+            if token.closerp() {
+                match_closer(open, token, tokens, input);
+                break;
+            } else if !token.is_l_paren() {
+                return Err(unexpected(ParserErrorKind::Token, token, input, "an arm"));
+            }
+            token
+        });
+
+        let pattern = read_pattern(tokens, input)?;
+        let mut body = Vec::new();
+        parse_list(arm_open, tokens, input, |_, expr| {
+            body.push(expr);
+            Ok(())
+        })?;
+        arms.push(Arm {
+            pattern,
+            body,
+            span: tokens.span_from(arm_open),
+        });
+    }
+
+    Ok(Ast::Match {
+        value: Box::new(value),
+        arms,
+        span: tokens.span_from(open),
+    })
+}
+
+/// Reads `_`, a name to bind or `(Variant pattern...)`.
+fn read_pattern(tokens: &mut Tokens, input: &str) -> Result<Pattern> {
+    next!(token, tokens, "a pattern", {
+        if token.is_symbol() {
+            Ok(match token.as_str(input) {
+                "_" => Pattern::Wildcard(token.index()),
+                _ => Pattern::Binding(get_symbol(token, input), token.index()),
+            })
+        } else if token.is_l_paren() {
+            let name = next!(t, tokens, "a variant name", {
+                if t.is_symbol() {
+                    get_symbol(t, input)
+                } else {
+                    return Err(unexpected(ParserErrorKind::Token, t, input, "a variant name"));
+                }
+            });
+
+            let mut fields = Vec::new();
+            while tokens.peek().is_some_and(|t| !t.closerp()) {
+                fields.push(read_pattern(tokens, input)?);
+            }
+            handle_closer(token, tokens, input)?;
+            Ok(Pattern::Variant {
+                name,
+                fields,
+                span: tokens.span_from(token),
+            })
+        } else {
+            Err(unexpected(ParserErrorKind::Token, token, input, "a pattern"))
+        }
+    })
+}
+
+/// `(define NAME value)` or `(define (NAME type) value)`, where the type is otherwise taken from
+/// the value.
+///
+/// In an impl block both forms define methods instead: `(define (NAME type) value)` is a method
+/// without arguments returning `value`, and `(define (NAME self args...) body...)` is the same as
+/// `(defn (NAME (self args...)) body...)`.
+fn handle_define(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let (name, annotation) = next!(t, tokens, "a name", {
+        if t.is_symbol() {
+            (get_symbol(t, input), None)
+        } else if t.is_l_paren() {
+            let name = next!(name, tokens, "a name", {
+                if name.is_symbol() {
+                    get_symbol(name, input)
+                } else {
+                    return Err(unexpected(ParserErrorKind::Token, name, input, "a name"));
+                }
+            });
+            if tokens.methods && tokens.peek().is_some_and(|s| s.is_symbol() && s.as_str(input) == "self") {
+                let args = read_args(t, "an argument", tokens, input)?;
+                return read_defn_body(open, name, Vec::new(), args, Type::Empty, None, tokens, input);
+            }
+            let (ty, span) = read_type(tokens, input)?;
+            handle_closer(t, tokens, input)?;
+            if tokens.methods {
+                return read_defn_body(open, name, Vec::new(), Vec::new(), ty, Some(span), tokens, input);
+            }
+            (name, Some(ty))
+        } else {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a name"));
+        }
+    });
+
+    let value = if let Some(expr) = parse_expr(tokens, input)? {
+        if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
+        }
+    } else {
+        return Err(missing(tokens, "a value"));
+    };
+
+    handle_closer(open, tokens, input)?;
+
+    Ok(Ast::Define{
         name: name,
+        ty: annotation.unwrap_or_else(|| value.ty()),
+        value: Box::new(value),
+        span: tokens.span_from(open),
+    })
+}
+
+fn handle_defn(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let signature = next!(token, tokens, "a function signature", {
+        if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, "a function signature"));
+        }
+        token
+    });
+
+    let (name, params) = read_generic_name("a function name", tokens, input)?;
+    with_type_params(&params, tokens, |tokens| handle_defn_rest(open, signature, name, params.clone(), tokens, input))
+}
+
+fn handle_defn_rest(open: Token, signature: Token, name: Symbol, params: Vec<Symbol>, tokens: &mut Tokens,
+                    input: &str) -> Result<Ast>
+{
+    // Beginning of argument list
+    let arg_list = next!(token, tokens, "an argument list", {
+        if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, "an argument list"));
+        }
+        token
+    });
+
+    let args = read_args(arg_list, "an argument", tokens, input)?;
+
+    let (ret_ty, ret_span) = if let Some(t) = tokens.peek() {
+        if t.closerp() {
+            (Type::Empty, None)
+        } else {
+            let (ty, span) = read_type(tokens, input)?;
+            (ty, Some(span))
+        }
+    } else {
+        return Err(tokens.eoi("a return type or a closer"));
+    };
+
+    // End of function preamble
+    handle_closer(signature, tokens, input)?;
+    read_defn_body(open, name, params, args, ret_ty, ret_span, tokens, input)
+}
+
+/// Reads the body of a function up to the closer of `open`, once its signature has been read.
+#[allow(clippy::too_many_arguments)]
+fn read_defn_body(open: Token, name: Symbol, params: Vec<Symbol>, args: Vec<Arg>, ret_ty: Type,
+                  ret_span: Option<Index>, tokens: &mut Tokens, input: &str) -> Result<Ast>
+{
+    let mut body = Vec::new();
+    // Loops outside of the function can't be exited from inside it
+    let loops = std::mem::replace(&mut tokens.loops, 0);
+    let methods = std::mem::replace(&mut tokens.methods, false);
+    let result = parse_list(open, tokens, input, |tokens, expr| {
+        if expr.is_identifier() || expr.is_primitive() {
+            if !tokens.peek().map(|t| t.closerp()).unwrap_or(false) {
+                return Err(ParserError::new(ParserErrorKind::NonfinalValue, expr.span()));
+            }
+        } else if expr.is_asm() {
+            if body.len() != 0 || !tokens.peek().map(|t| t.closerp()).unwrap_or(false) {
+                return Err(ParserError::new(ParserErrorKind::Asm, expr.span()));
+            }
+        }
+        body.push(expr);
+        Ok(())
+    });
+    tokens.loops = loops;
+    tokens.methods = methods;
+    result?;
+
+    let span = tokens.span_from(open);
+    if let Some(l) = body.last() {
+        if (l.is_define() || l.is_defn() || l.is_include()) && ret_ty != Type::Empty {
+            return Err(ParserError::new(ParserErrorKind::ReturnType, l.span())
+                       .expected("a value")
+                       .found(l.describe()));
+        }
+    } else if ret_ty != Type::Empty {
+        return Err(ParserError::new(ParserErrorKind::ReturnType, ret_span.unwrap_or(span))
+                   .expected("a value")
+                   .found("an empty body"));
+    }
+
+    let ty = Type::Arrow(args.iter().map(|arg| arg.ty.clone()).collect(), Box::new(ret_ty.clone()));
+    Ok(Ast::Defn {
+        name: name,
+        params,
         ty: ty,
         args: args,
         body: body,
+        ret_span: ret_span,
+        span: span,
+    })
+}
+
+fn handle_lambda(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let arg_list = next!(token, tokens, "an argument list", {
+        if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, "an argument list"));
+        }
+        token
+    });
+    let args = read_args(arg_list, "an argument", tokens, input)?;
+
+    let mut body = Vec::new();
+    // Same as for functions, the body can't exit loops outside of it
+    let loops = std::mem::replace(&mut tokens.loops, 0);
+    let result = parse_list(open, tokens, input, |_, expr| {
+        if expr.is_asm() {
+            return Err(ParserError::new(ParserErrorKind::Asm, expr.span()));
+        }
+        body.push(expr);
+        Ok(())
+    });
+    tokens.loops = loops;
+    result?;
+
+    Ok(Ast::Lambda {
+        args,
+        body,
+        span: tokens.span_from(open),
     })
 }
 
-fn read_type(tokens: &mut Tokens, input: &str) -> Result<Type> {
-    next!(token, tokens, {
+/// Reads arguments of the form `[ident type]` up to the closer of `list`.
+fn read_args(list: Token, expected: &'static str, tokens: &mut Tokens, input: &str) -> Result<Vec<Arg>> {
+    let mut args = Vec::new();
+    loop {
+        // Read the argument opener
+        let arg_open = next!(token, tokens, expected, {
+            // A closer here denotes the end of the list.
+            if token.closerp() {
+                match_closer(list, token, tokens, input);
+                return Ok(args);
+            } else if token.is_symbol() && token.as_str(input) == "self" && args.is_empty() {
+                // Methods take the value they're called on by reference
+                if let Some(ref ty) = tokens.self_ty {
+                    let ty = Type::Ptr(Box::new(ty.clone()));
+                    args.push(Arg::new(get_symbol(token, input), ty, token.index(), token.index()));
+                    continue;
+                } else {
+                    return Err(ParserError::new(ParserErrorKind::Token, token.index())
+                               .expected(expected)
+                               .found("`self` outside of an impl block"));
+                }
+            } else if !(token.is_ls_bracket() || token.is_l_paren()) {
+                return Err(unexpected(ParserErrorKind::Token, token, input, expected));
+            }
+            token
+        });
+
+        let arg_name = next!(token, tokens, "a name", {
+            if token.is_symbol() {
+                get_symbol(token, input)
+            } else {
+                return Err(unexpected(ParserErrorKind::Token, token, input, "a name"));
+            }
+        });
+
+        let (arg_type, ty_span) = read_type(tokens, input)?;
+
+        // Read the argument closer
+        handle_closer(arg_open, tokens, input)?;
+
+        args.push(Arg::new(arg_name, arg_type, tokens.span_from(arg_open), ty_span));
+    }
+}
+
+/// Reads either `name` or `(name T ...)`, where the `T`s are type parameters.
+fn read_generic_name(expected: &'static str, tokens: &mut Tokens, input: &str) -> Result<(Symbol, Vec<Symbol>)> {
+    next!(token, tokens, expected, {
         if token.is_symbol() {
-            Ok(Type::from_token(token, input, None))
-        } else if token.openerp() {
-            next!(token, tokens, {
-                let outer_ty = if token.is_symbol() {
-                    token
+            return Ok((get_symbol(token, input), Vec::new()));
+        } else if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, expected));
+        }
+
+        let name = next!(t, tokens, expected, {
+            if t.is_symbol() {
+                get_symbol(t, input)
+            } else {
+                return Err(unexpected(ParserErrorKind::Token, t, input, expected));
+            }
+        });
+
+        let mut params = Vec::new();
+        loop {
+            next!(t, tokens, "a type parameter", {
+                if t.is_symbol() {
+                    params.push(get_symbol(t, input));
+                } else if t.closerp() && !params.is_empty() {
+                    match_closer(token, t, tokens, input);
+                    return Ok((name, params));
                 } else {
-                    return Err(ParserError::Token);
+                    return Err(unexpected(ParserErrorKind::Token, t, input, "a type parameter"));
+                }
+            });
+        }
+    })
+}
+
+/// Runs `f` with `params` in scope, so that types with their names are read as type variables.
+fn with_type_params<F>(params: &[Symbol], tokens: &mut Tokens, f: F) -> Result<Ast>
+    where F: FnOnce(&mut Tokens) -> Result<Ast>
+{
+    let scope = tokens.type_params.len();
+    tokens.type_params.extend_from_slice(params);
+    let result = f(tokens);
+    tokens.type_params.truncate(scope);
+    result
+}
+
+fn handle_struct(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let (name, params) = read_generic_name("a struct name", tokens, input)?;
+    with_type_params(&params, tokens, |tokens| {
+        let fields = read_args(open, "a field", tokens, input)?;
+        Ok(Ast::Struct {
+            name,
+            params: params.clone(),
+            fields,
+            span: tokens.span_from(open),
+        })
+    })
+}
+
+/// `(enum Name variant...)`, where each variant is either a name or `(Name type...)`.
+fn handle_enum(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let (name, params) = read_generic_name("an enum name", tokens, input)?;
+    with_type_params(&params, tokens, |tokens| {
+        let mut variants = Vec::new();
+        loop {
+            next!(token, tokens, "a variant", {
+                if token.closerp() {
+                    match_closer(open, token, tokens, input);
+                    break;
+                } else if token.is_symbol() {
+                    variants.push(Variant {
+                        name: get_symbol(token, input),
+                        fields: Vec::new(),
+                        span: token.index(),
+                    });
+                } else if token.is_l_paren() {
+                    let name = next!(t, tokens, "a variant name", {
+                        if t.is_symbol() {
+                            get_symbol(t, input)
+                        } else {
+                            return Err(unexpected(ParserErrorKind::Token, t, input, "a variant name"));
+                        }
+                    });
+
+                    let mut fields = Vec::new();
+                    while tokens.peek().is_some_and(|t| !t.closerp()) {
+                        fields.push(read_type(tokens, input)?.0);
+                    }
+                    handle_closer(token, tokens, input)?;
+                    variants.push(Variant {
+                        name,
+                        fields,
+                        span: tokens.span_from(token),
+                    });
+                } else {
+                    return Err(unexpected(ParserErrorKind::Token, token, input, "a variant"));
+                }
+            });
+        }
+
+        Ok(Ast::Enum {
+            name,
+            params: params.clone(),
+            variants,
+            span: tokens.span_from(open),
+        })
+    })
+}
+
+fn handle_impl(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let (name, params) = read_generic_name("a struct name", tokens, input)?;
+    let self_ty = Type::Struct(name, params.iter().map(|p| Type::Var(*p)).collect());
+    let outer = tokens.self_ty.replace(self_ty);
+    let outer_methods = std::mem::replace(&mut tokens.methods, true);
+    let result = with_type_params(&params, tokens, |tokens| {
+        let mut methods = Vec::new();
+        parse_list(open, tokens, input, |_, expr| {
+            if expr.is_defn() {
+                methods.push(expr);
+                Ok(())
+            } else {
+                Err(ParserError::new(ParserErrorKind::Item, expr.span())
+                    .expected("a method")
+                    .found(expr.describe()))
+            }
+        })?;
+
+        Ok(Ast::Impl {
+            name,
+            params: params.clone(),
+            methods,
+            span: tokens.span_from(open),
+        })
+    });
+    tokens.self_ty = outer;
+    tokens.methods = outer_methods;
+    result
+}
+
+/// `(define-syntax name (syntax-rules (literals...) ((_ pattern...) template)...))`
+fn handle_define_syntax(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let name = next!(t, tokens, "a macro name", {
+        if t.is_symbol() {
+            get_symbol(t, input)
+        } else {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a macro name"));
+        }
+    });
+
+    let rules_open = next!(t, tokens, "`(syntax-rules`", {
+        if !t.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "`(syntax-rules`"));
+        }
+        t
+    });
+    next!(t, tokens, "`syntax-rules`", {
+        if !t.is_symbol() || t.as_str(input) != "syntax-rules" {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "`syntax-rules`"));
+        }
+    });
+
+    let literals_open = next!(t, tokens, "a list of literals", {
+        if !t.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a list of literals"));
+        }
+        t
+    });
+    let mut literals = Vec::new();
+    loop {
+        next!(t, tokens, "a literal", {
+            if t.closerp() {
+                match_closer(literals_open, t, tokens, input);
+                break;
+            } else if t.is_symbol() {
+                literals.push(get_symbol(t, input));
+            } else {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "a literal"));
+            }
+        });
+    }
+
+    let mut rules = Vec::new();
+    loop {
+        let rule_open = next!(t, tokens, "a rule", {
+            if t.closerp() {
+                match_closer(rules_open, t, tokens, input);
+                break;
+            } else if !t.is_l_paren() {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "a rule"));
+            }
+            t
+        });
+        let pattern_open = next!(t, tokens, "a pattern", {
+            if !t.is_l_paren() {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "a pattern"));
+            }
+            t
+        });
+        // The head of the pattern stands for the macro itself and is ignored
+        next!(t, tokens, "`_` or the macro name", {
+            if !t.is_symbol() {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "`_` or the macro name"));
+            }
+        });
+        let pattern = read_macro_list(pattern_open, &literals, tokens, input)?;
+        let template = read_template(tokens, input)?;
+        handle_closer(rule_open, tokens, input)?;
+        rules.push(Rule {
+            pattern,
+            template,
+            span: tokens.span_from(rule_open),
+        });
+    }
+    handle_closer(open, tokens, input)?;
+
+    Ok(Ast::Macro {
+        name,
+        literals,
+        rules,
+        hygienic: true,
+        span: tokens.span_from(open),
+    })
+}
+
+/// `(defmacro (name pattern...) template)`, a macro with a single rule and no hygiene. The
+/// template is substituted as written. Procedural macros, whose body is evaluated at compile time
+/// to build the expansion, aren't supported yet.
+fn handle_defmacro(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let pattern_open = next!(t, tokens, "a macro signature", {
+        if !t.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a macro signature"));
+        }
+        t
+    });
+    let name = next!(t, tokens, "a macro name", {
+        if t.is_symbol() {
+            get_symbol(t, input)
+        } else {
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a macro name"));
+        }
+    });
+    let pattern = read_macro_list(pattern_open, &[], tokens, input)?;
+    let template = read_template(tokens, input)?;
+    handle_closer(open, tokens, input)?;
+
+    let span = tokens.span_from(open);
+    Ok(Ast::Macro {
+        name,
+        literals: Vec::new(),
+        rules: vec![Rule { pattern, template, span }],
+        hygienic: false,
+        span,
+    })
+}
+
+/// Reads the patterns of a list up to the closer of `open`, where one of them may be followed by
+/// `...`.
+fn read_macro_list(open: Token, literals: &[Symbol], tokens: &mut Tokens, input: &str) -> Result<MacroPattern> {
+    let mut elements = Vec::new();
+    let mut ellipsis = None;
+    loop {
+        match tokens.peek() {
+            Some(t) if t.closerp() => {
+                tokens.next();
+                match_closer(open, t, tokens, input);
+                break;
+            }
+            Some(t) if t.is_symbol() && t.as_str(input) == "..." => {
+                tokens.next();
+                if elements.is_empty() || ellipsis.is_some() {
+                    return Err(unexpected(ParserErrorKind::Ellipsis, t, input, "a pattern"));
+                }
+                ellipsis = Some(elements.len() - 1);
+            }
+            _ => elements.push(read_macro_pattern(literals, tokens, input)?),
+        }
+    }
+
+    Ok(MacroPattern::List {
+        elements,
+        ellipsis,
+        span: tokens.span_from(open),
+    })
+}
+
+fn read_macro_pattern(literals: &[Symbol], tokens: &mut Tokens, input: &str) -> Result<MacroPattern> {
+    next!(token, tokens, "a pattern", {
+        if token.is_symbol() {
+            let name = get_symbol(token, input);
+            Ok(if token.as_str(input) == "_" {
+                MacroPattern::Wildcard(token.index())
+            } else if literals.contains(&name) {
+                MacroPattern::Literal(name, token.index())
+            } else {
+                MacroPattern::Var(name, token.index())
+            })
+        } else if token.is_l_paren() {
+            read_macro_list(token, literals, tokens, input)
+        } else {
+            Err(unexpected(ParserErrorKind::Token, token, input, "a pattern"))
+        }
+    })
+}
+
+/// Reads the template of a rule, in which `...` is an identifier.
+fn read_template(tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    // Whether a `break` in the template is inside a loop depends on where the macro is used
+    let loops = std::mem::replace(&mut tokens.loops, 1);
+    let template = parse_expr(tokens, input);
+    tokens.loops = loops;
+    match template? {
+        Some(template) => Ok(template),
+        None => Err(missing(tokens, "a template")),
+    }
+}
+
+fn handle_set(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let target = read_place(tokens, input)?;
+
+    let value = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
+        },
+        None => return Err(missing(tokens, "a value")),
+    };
+
+    handle_closer(open, tokens, input)?;
+    Ok(Ast::Set {
+        target: Box::new(target),
+        value: Box::new(value),
+        span: tokens.span_from(open),
+    })
+}
+
+/// Reads a variable, field, index expression or dereference.
+fn read_place(tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    match parse_expr(tokens, input)? {
+        Some(expr) => if expr.placep() {
+            Ok(expr)
+        } else {
+            Err(ParserError::new(ParserErrorKind::Value, expr.span())
+                .expected("a variable, field, index expression or dereference")
+                .found(expr.describe()))
+        },
+        None => Err(missing(tokens, "a variable, field, index expression or dereference")),
+    }
+}
+
+fn handle_deref(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let pointer = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
+        },
+        None => return Err(missing(tokens, "a pointer")),
+    };
+
+    handle_closer(open, tokens, input)?;
+    Ok(Ast::Deref(Box::new(pointer), tokens.span_from(open)))
+}
+
+fn handle_addr_of(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let place = read_place(tokens, input)?;
+    handle_closer(open, tokens, input)?;
+    Ok(Ast::AddrOf(Box::new(place), tokens.span_from(open)))
+}
+
+fn handle_cast(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let ty = read_type(tokens, input)?.0;
+    let value = match parse_expr(tokens, input)? {
+        Some(expr) => if expr.valuep() {
+            expr
+        } else {
+            return Err(not_a_value(&expr));
+        },
+        None => return Err(missing(tokens, "a value to cast")),
+    };
+
+    handle_closer(open, tokens, input)?;
+    Ok(Ast::Cast {
+        ty,
+        value: Box::new(value),
+        span: tokens.span_from(open),
+    })
+}
+
+/// Reads the rest of `(fn (args...) ret)`, the type of functions. The return type can be left out
+/// for functions that return nothing.
+fn read_fn_type(open: Token, tokens: &mut Tokens, input: &str) -> Result<(Type, Index)> {
+    let arg_list = next!(token, tokens, "a list of argument types", {
+        if !token.is_l_paren() {
+            return Err(unexpected(ParserErrorKind::Token, token, input, "a list of argument types"));
+        }
+        token
+    });
+
+    let mut args = Vec::new();
+    while tokens.peek().is_some_and(|t| !t.closerp()) {
+        args.push(read_type(tokens, input)?.0);
+    }
+    handle_closer(arg_list, tokens, input)?;
+
+    let ret = if tokens.peek().is_some_and(|t| !t.closerp()) {
+        read_type(tokens, input)?.0
+    } else {
+        Type::Empty
+    };
+    handle_closer(open, tokens, input)?;
+    Ok((Type::Arrow(args, Box::new(ret)), tokens.span_from(open)))
+}
+
+/// Reads a type, returning it along with its span.
+fn read_type(tokens: &mut Tokens, input: &str) -> Result<(Type, Index)> {
+    next!(token, tokens, "a type", {
+        if token.is_symbol() && is_angle_open(token, input) {
+            let (ty, span, unmatched) = read_angle_type(token, tokens, input)?;
+            if unmatched > 0 {
+                return Err(ParserError::new(ParserErrorKind::Type, span)
+                           .expected("a type")
+                           .found("an unmatched `>`"));
+            }
+            Ok((ty, span))
+        } else if token.is_symbol() {
+            Ok((named_type(token.as_str(input), token.index(), tokens)?, token.index()))
+        } else if token.is_l_paren() {
+            next!(outer, tokens, "a type", {
+                let outer_ty = if outer.is_symbol() {
+                    outer
+                } else {
+                    return Err(unexpected(ParserErrorKind::Token, outer, input, "a type"));
                 };
 
-                let inner_ty = read_type(tokens, input)?;
-                handle_closer(tokens)?;
-                Ok(Type::from_token(outer_ty, input, Some(inner_ty)))
+                if outer.as_str(input) == "fn" {
+                    return read_fn_type(token, tokens, input);
+                }
+
+                let mut args = vec![read_type(tokens, input)?.0];
+                while tokens.peek().is_some_and(|t| !t.closerp()) {
+                    args.push(read_type(tokens, input)?.0);
+                }
+                handle_closer(token, tokens, input)?;
+                Ok((Type::from_token(outer_ty, input, args)?, tokens.span_from(token)))
             })
         } else {
-            Err(ParserError::Token)
+            Err(unexpected(ParserErrorKind::Token, token, input, "a type"))
         }
     })
 }
 
+fn named_type(name: &str, index: Index, tokens: &Tokens) -> Result<Type> {
+    let symbol = intern(name);
+    if tokens.type_params.contains(&symbol) {
+        Ok(Type::Var(symbol))
+    } else {
+        Type::from_name(name, index, Vec::new())
+    }
+}
+
+/// Whether `token` starts a type application written as `<ptr u8>`. `<` isn't a delimiter, since
+/// it is also a function name, so this is only recognized where a type is expected.
+fn is_angle_open(token: Token, input: &str) -> bool {
+    let s = token.as_str(input);
+    s.len() > 1 && s.starts_with('<') && !s.ends_with('>')
+}
+
+/// Reads the arguments of `<name args...>` after the token `<name`. The closing `>`s are part of
+/// the last argument's token, as in `<Option <ptr T>>`, so this also returns how many of them
+/// belong to enclosing applications.
+fn read_angle_type(open: Token, tokens: &mut Tokens, input: &str) -> Result<(Type, Index, usize)> {
+    let name = &open.as_str(input)[1..];
+    let mut args = Vec::new();
+    loop {
+        let closers = match tokens.peek() {
+            Some(t) if t.is_symbol() && is_angle_open(t, input) => {
+                tokens.next();
+                let (ty, _, closers) = read_angle_type(t, tokens, input)?;
+                args.push(ty);
+                closers
+            }
+            Some(t) if t.is_symbol() && t.as_str(input).ends_with('>') => {
+                tokens.next();
+                let s = t.as_str(input);
+                let arg = s.trim_end_matches('>');
+                if !arg.is_empty() {
+                    args.push(named_type(arg, t.index(), tokens)?);
+                }
+                s.len() - arg.len()
+            }
+            Some(_) => {
+                args.push(read_type(tokens, input)?.0);
+                0
+            }
+            None => return Err(tokens.eoi("a type or `>`")),
+        };
+        if closers > 0 {
+            let ty = Type::from_name(name, open.index(), args)?;
+            return Ok((ty, tokens.span_from(open), closers - 1));
+        }
+    }
+}
+
 // Application
-fn handle_application(t: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    let mut application = Vec::new();
-    application.push(Ast::Identifier(get_symbol(t, input)));
-    while let Some(expr) = parse_expr(tokens, input)? {
-        if expr.is_identifier() || expr.is_primitive() || expr.is_application() {
+fn handle_application(open: Token, callee: Ast, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let mut application = vec![callee];
+    parse_list(open, tokens, input, |_, expr| {
+        if expr.valuep() {
             application.push(expr);
+            Ok(())
         } else {
-            return Err(ParserError::Value);
+            Err(not_a_value(&expr))
         }
-    }
+    })?;
 
-    Ok(Ast::Application(application))
+    Ok(Ast::Application(application, tokens.span_from(open)))
 }
 
-fn handle_include(tokens: &mut Tokens, input: &str) -> Result<Ast> {
-    let include = next!(t, tokens, {
+fn handle_include(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let path = next!(t, tokens, "a file to include", {
         if t.is_symbol() {
             get_symbol(t, input)
         } else {
-            return Err(ParserError::Token);
+            return Err(unexpected(ParserErrorKind::Token, t, input, "a file to include"));
         }
     });
 
-    handle_closer(tokens)?;
-    Ok(Ast::Include(include))
+    let namespace = match tokens.peek() {
+        Some(t) if t.is_symbol() && t.as_str(input) == "as" => {
+            tokens.next();
+            next!(t, tokens, "a namespace", {
+                if t.is_symbol() {
+                    Some(get_symbol(t, input))
+                } else {
+                    return Err(unexpected(ParserErrorKind::Token, t, input, "a namespace"));
+                }
+            })
+        }
+        _ => None,
+    };
+
+    handle_closer(open, tokens, input)?;
+    Ok(Ast::Include {
+        path,
+        namespace,
+        span: tokens.span_from(open),
+    })
+}
+
+fn handle_export(open: Token, tokens: &mut Tokens, input: &str) -> Result<Ast> {
+    let mut names = Vec::new();
+    loop {
+        next!(t, tokens, "a name to export", {
+            if t.closerp() {
+                match_closer(open, t, tokens, input);
+                break;
+            } else if t.is_symbol() {
+                names.push(get_symbol(t, input));
+            } else {
+                return Err(unexpected(ParserErrorKind::Token, t, input, "a name to export"));
+            }
+        });
+    }
+    Ok(Ast::Export(names, tokens.span_from(open)))
 }
 
-fn handle_closer(tokens: &mut Tokens) -> Result<()> {
-    next!(t, tokens, {
+/// Reads the closer for `open`.
+fn handle_closer(open: Token, tokens: &mut Tokens, input: &str) -> Result<()> {
+    next!(t, tokens, closer_for(open), {
         if !t.closerp() {
-            Err(ParserError::Closer)
+            Err(unexpected(ParserErrorKind::Closer, t, input, closer_for(open)))
         } else {
+            match_closer(open, t, tokens, input);
             Ok(())
         }
     })
 }
 
+/// Records an error if `close` doesn't match the delimiter kind of `open`. The list is still
+/// treated as closed so that parsing can carry on.
+fn match_closer(open: Token, close: Token, tokens: &mut Tokens, input: &str) {
+    if !open.opener_match(close) {
+        tokens.errors.push(ParserError::new(ParserErrorKind::Delimiter, close.index())
+                           .expected(closer_for(open))
+                           .found(describe(close, input)));
+    }
+}
+
+fn closer_for(open: Token) -> &'static str {
+    match open {
+        Token::LBrace(_) => "`}`",
+        Token::LSBracket(_) => "`]`",
+        _ => "`)`",
+    }
+}
+
 struct Tokens {
     tokens: Vec<Token>,
     position: usize,
+    /// Errors that have been recovered from
+    errors: Vec<ParserError>,
+    /// Number of loops enclosing the current expression
+    loops: usize,
+    /// Type parameters of the enclosing generic items
+    type_params: Vec<Symbol>,
+    /// Type of `self` inside an impl block
+    self_ty: Option<Type>,
+    /// Whether the items of an impl block are being read, where `define` defines methods
+    methods: bool,
 }
 
 impl Tokens {
-    fn new(tokens: Vec<Token>) -> Self {
+    /// Comments carry no meaning for the parser so they are dropped up front.
+    fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|t| !t.commentp());
         Tokens {
             tokens: tokens,
             position: 0,
+            errors: Vec::new(),
+            loops: 0,
+            type_params: Vec::new(),
+            self_ty: None,
+            methods: false,
+        }
+    }
+
+    /// Skips over the expression beginning at `start`. Returns false if the expression was never
+    /// closed, in which case we stop at the end of input or at an opener at the start of a line,
+    /// as that most likely begins the next item.
+    fn recover(&mut self, start: usize, input: &str) -> bool {
+        self.position = start;
+        let mut depth = 0;
+        while let Some(t) = self.next() {
+            if t.openerp() {
+                if depth > 0 && input[..t.index().start()].ends_with('\n') {
+                    self.position -= 1;
+                    return false;
+                }
+                depth += 1;
+            } else if t.closerp() {
+                depth -= 1;
+            } else if t.is_pound() && depth == 0 {
+                // Literals such as `#t` span two tokens
+                if self.peek().is_some_and(|t| t.is_symbol()) {
+                    self.next();
+                }
+            }
+
+            if depth <= 0 {
+                return true;
+            }
         }
+        false
     }
 
     fn next(&mut self) -> Option<Token> {
@@ -542,4 +2213,26 @@ impl Tokens {
             None
         }
     }
+
+    /// The most recently consumed token
+    fn last(&self) -> Option<Token> {
+        if self.position > 0 {
+            Some(self.tokens[self.position-1])
+        } else {
+            None
+        }
+    }
+
+    /// Span from the start of `open` to the end of the most recently consumed token
+    fn span_from(&self, open: Token) -> Index {
+        open.index().to(self.last().unwrap_or(open).index())
+    }
+
+    /// Error for running out of tokens, located at the final token of the input
+    fn eoi(&self, expected: &'static str) -> ParserError {
+        let span = self.tokens.last().map_or(Index::default(), |t| t.index());
+        ParserError::new(ParserErrorKind::EOI, span)
+            .expected(expected)
+            .found("end of input")
+    }
 }
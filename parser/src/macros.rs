@@ -0,0 +1,522 @@
+use super::{Ast, Binding, MacroPattern, Rule};
+use error::{MacroError, MacroErrorKind};
+use rewrite::{args_binders, pattern_binders, walk, Rename, Result, Rewrite};
+
+use string_interner::{get_symbol_uninterned, get_value, Symbol};
+use tokenizer::{ExpansionId, Index, SourceMap};
+
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::rc::Rc;
+
+/// How many macro uses can be expanded inside each other before giving up
+const MAX_DEPTH: usize = 128;
+
+/// Removes the macro definitions from `ast` and replaces every use of a macro with its expansion.
+/// Uses are expanded outside in, so the arguments are passed to a macro unexpanded and the
+/// expansion is expanded again afterwards. Macros can be used anywhere in the file, including
+/// before their definition.
+///
+/// Every expansion is registered with `sources`, and the spans of the code a template produces
+/// are marked with it, so errors found in that code later on can be traced back through the uses
+/// of macros that led to it. Only `syntax-rules` and `defmacro` templates are supported, there are
+/// no procedural macros that run code at compile time.
+pub fn expand(ast: Vec<Ast>, sources: &mut SourceMap) -> Result<Vec<Ast>> {
+    let mut macros = HashMap::new();
+    let mut items = Vec::new();
+    for item in ast {
+        if let Ast::Macro { name, literals, rules, hygienic, span } = item {
+            let def = Macro { literals, rules, hygienic };
+            if macros.insert(name, Rc::new(def)).is_some() {
+                return Err(MacroError::new(MacroErrorKind::Redefined, span));
+            }
+        } else {
+            items.push(item);
+        }
+    }
+
+    let mut expander = Expander {
+        macros,
+        sources,
+        depth: 0,
+        free: HashMap::new(),
+    };
+    let mut expanded = Vec::new();
+    for item in items {
+        expander.item(item, &mut expanded)?;
+    }
+    let items = expanded;
+
+    let written: HashSet<Symbol> = expander.free.values().cloned().collect();
+    let structs = items.iter()
+        .filter_map(|item| match item {
+            Ast::Struct { name, .. } => Some(*name),
+            _ => None,
+        })
+        .collect();
+    let mut resolve = Resolve {
+        free: expander.free,
+        written,
+        structs,
+        scope: Vec::new(),
+    };
+    items.into_iter().map(|item| resolve.item(item)).collect()
+}
+
+struct Macro {
+    literals: Vec<Symbol>,
+    rules: Vec<Rule>,
+    hygienic: bool,
+}
+
+/// What a pattern variable matched
+#[derive(Clone, Debug)]
+enum Fragment {
+    One(Ast),
+    /// One fragment for every element matched by the pattern followed by `...`
+    Many(Vec<Fragment>),
+}
+
+fn is_ellipsis(ast: &Ast) -> bool {
+    matches!(ast, Ast::Identifier(s, _) if get_value(*s).is_some_and(|s| s == "..."))
+}
+
+struct Expander<'a> {
+    macros: HashMap<Symbol, Rc<Macro>>,
+    sources: &'a mut SourceMap,
+    /// Number of uses of macros being expanded inside each other
+    depth: usize,
+    /// Fresh name given to each free identifier of a hygienic template, and the name it was
+    /// written with
+    free: HashMap<Symbol, Symbol>,
+}
+
+impl<'a> Expander<'a> {
+    /// Expands the use of `name` with the arguments `args` once.
+    fn expand_use(&mut self, name: Symbol, args: &[Ast], span: Index) -> Result<Ast> {
+        let def = self.macros[&name].clone();
+        if self.depth == MAX_DEPTH {
+            return Err(MacroError::new(MacroErrorKind::Depth, span));
+        }
+
+        let mut matched = None;
+        for rule in &def.rules {
+            let mut bindings = HashMap::new();
+            let (elements, ellipsis) = match &rule.pattern {
+                MacroPattern::List { elements, ellipsis, .. } => (elements, *ellipsis),
+                _ => unreachable!(),
+            };
+            if match_list(elements, ellipsis, args, &def.literals, &mut bindings) {
+                matched = Some((rule, bindings));
+                break;
+            }
+        }
+        let (rule, bindings) = matched.ok_or_else(|| MacroError::new(MacroErrorKind::NoMatch, span))?;
+
+        let expansion = self.sources.add_expansion(get_value(name).unwrap_or_default(), span);
+        self.depth += 1;
+        let result = self.instantiate(rule, bindings, def.hygienic, expansion, span)
+            .and_then(|expansion| self.expr(expansion));
+        self.depth -= 1;
+        result
+    }
+
+    /// Expands a top level `item` into `items`. A macro used there can expand to a block whose
+    /// items are all spliced in, but it can't expand to anything else that isn't an item.
+    fn item(&mut self, item: Ast, items: &mut Vec<Ast>) -> Result<()> {
+        let span = item.span();
+        if splice(self.expr(item)?, items) {
+            Ok(())
+        } else {
+            Err(MacroError::new(MacroErrorKind::Item, span))
+        }
+    }
+
+    /// Fills in the template of `rule` with what the pattern variables matched, after marking the
+    /// spans of the template with `expansion`. If the macro is hygienic, the names bound by the
+    /// template itself are renamed first, and so are the free identifiers in it so that `Resolve`
+    /// can tell them apart from those at the call site.
+    fn instantiate(&mut self, rule: &Rule, bindings: HashMap<Symbol, Fragment>, hygienic: bool,
+                   expansion: ExpansionId, span: Index) -> Result<Ast>
+    {
+        let mut template = Mark(expansion).expr(rule.template.clone())?;
+        if hygienic {
+            let mut names = Names::default();
+            names.expr(template.clone())?;
+            let fresh = |name| get_symbol_uninterned(get_value(name).unwrap_or_default());
+            let mut renames: HashMap<_, _> = names.binders.into_iter()
+                .filter(|name| !bindings.contains_key(name))
+                .map(|name| (name, fresh(name)))
+                .collect();
+            for name in names.identifiers {
+                // Macros are only ever defined at the top level, so their names can't be captured
+                let free = !bindings.contains_key(&name) && !self.macros.contains_key(&name)
+                    && get_value(name).is_some_and(|s| s != "...");
+                if free && !renames.contains_key(&name) {
+                    let renamed = fresh(name);
+                    self.free.insert(renamed, name);
+                    renames.insert(name, renamed);
+                }
+            }
+            template = Rename { renames }.expr(template)?;
+        }
+        Substitute { bindings, span }.expr(template)
+    }
+}
+
+impl<'a> Rewrite for Expander<'a> {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        match ast {
+            Ast::Application(ref elements, span) => match elements.first() {
+                Some(&Ast::Identifier(name, _)) if self.macros.contains_key(&name) => {
+                    self.expand_use(name, &elements[1..], span)
+                }
+                _ => walk(self, ast),
+            },
+            Ast::Macro { span, .. } => Err(MacroError::new(MacroErrorKind::Nested, span)),
+            _ => walk(self, ast),
+        }
+    }
+}
+
+/// Adds `item` to `items`, or the items in it if it's a block. Returns false if something that
+/// isn't an item was found.
+fn splice(item: Ast, items: &mut Vec<Ast>) -> bool {
+    match item {
+        Ast::Block(block, _) => block.into_iter().all(|item| splice(item, items)),
+        Ast::Include { .. } | Ast::Export(..) | Ast::Define { .. } | Ast::Defn { .. } | Ast::Intrinsic { .. }
+        | Ast::Struct { .. } | Ast::Enum { .. } | Ast::Impl { .. } => {
+            items.push(item);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn match_pattern(pattern: &MacroPattern, ast: &Ast, literals: &[Symbol],
+                 bindings: &mut HashMap<Symbol, Fragment>) -> bool
+{
+    match pattern {
+        MacroPattern::Wildcard(_) => true,
+        MacroPattern::Var(name, _) => {
+            bindings.insert(*name, Fragment::One(ast.clone()));
+            true
+        }
+        MacroPattern::Literal(name, _) => matches!(ast, Ast::Identifier(s, _) if s == name),
+        MacroPattern::List { elements, ellipsis, .. } => match ast {
+            Ast::Application(items, _) => match_list(elements, *ellipsis, items, literals, bindings),
+            _ => false,
+        },
+    }
+}
+
+/// Matches `items` against `elements`, where the element at `ellipsis` matches any number of
+/// items.
+fn match_list(elements: &[MacroPattern], ellipsis: Option<usize>, items: &[Ast], literals: &[Symbol],
+              bindings: &mut HashMap<Symbol, Fragment>) -> bool
+{
+    let mut match_all = |elements: &[MacroPattern], items: &[Ast]| {
+        elements.iter().zip(items).all(|(p, ast)| match_pattern(p, ast, literals, bindings))
+    };
+
+    let i = match ellipsis {
+        Some(i) => i,
+        None => return elements.len() == items.len() && match_all(elements, items),
+    };
+    let after = elements.len() - i - 1;
+    if items.len() < elements.len() - 1 {
+        return false;
+    }
+    let rest = items.len() - after;
+    if !match_all(&elements[..i], &items[..i]) || !match_all(&elements[i + 1..], &items[rest..]) {
+        return false;
+    }
+
+    let mut vars = Vec::new();
+    pattern_vars(&elements[i], &mut vars);
+    let mut repeated = vec![Vec::new(); vars.len()];
+    for item in &items[i..rest] {
+        let mut inner = HashMap::new();
+        if !match_pattern(&elements[i], item, literals, &mut inner) {
+            return false;
+        }
+        for (var, fragments) in vars.iter().zip(&mut repeated) {
+            fragments.push(inner.remove(var).unwrap());
+        }
+    }
+    for (var, fragments) in vars.into_iter().zip(repeated) {
+        bindings.insert(var, Fragment::Many(fragments));
+    }
+    true
+}
+
+fn pattern_vars(pattern: &MacroPattern, vars: &mut Vec<Symbol>) {
+    match pattern {
+        MacroPattern::Var(name, _) => vars.push(*name),
+        MacroPattern::List { elements, .. } => elements.iter().for_each(|p| pattern_vars(p, vars)),
+        MacroPattern::Wildcard(_) | MacroPattern::Literal(..) => (),
+    }
+}
+
+/// Collects the identifiers used and the names bound in a template
+#[derive(Default)]
+struct Names {
+    identifiers: Vec<Symbol>,
+    binders: Vec<Symbol>,
+}
+
+impl Rewrite for Names {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        if let Ast::Identifier(name, _) = ast {
+            self.identifiers.push(name);
+        }
+        walk(self, ast)
+    }
+
+    fn binder(&mut self, name: Symbol, _span: Index) -> Result<Symbol> {
+        self.binders.push(name);
+        Ok(name)
+    }
+}
+
+/// Marks every span in a template as produced by an expansion
+struct Mark(ExpansionId);
+
+impl Rewrite for Mark {
+    fn span(&mut self, span: Index) -> Index {
+        span.expanded(self.0)
+    }
+}
+
+/// Replaces the pattern variables in a template with what they matched
+struct Substitute {
+    bindings: HashMap<Symbol, Fragment>,
+    /// Use of the macro, where errors are reported
+    span: Index,
+}
+
+impl Rewrite for Substitute {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        match ast {
+            // Such as `(x ...)` with nothing matched by `x`, which isn't an expression
+            Ast::Application(..) => match walk(self, ast)? {
+                Ast::Application(ref elements, _) if elements.is_empty() => {
+                    Err(MacroError::new(MacroErrorKind::Empty, self.span))
+                }
+                ast => Ok(ast),
+            },
+            Ast::Identifier(name, span) => match self.bindings.get(&name) {
+                Some(Fragment::One(ast)) => Ok(ast.clone()),
+                Some(Fragment::Many(_)) => Err(MacroError::new(MacroErrorKind::Ellipsis, span)),
+                // `...` anywhere but after an element of a list is left alone and reported as
+                // an unknown variable by the type checker
+                None => Ok(ast),
+            },
+            _ => walk(self, ast),
+        }
+    }
+
+    /// Repeats each element followed by `...` once for every expression matched by the
+    /// repeated pattern variables in it.
+    fn seq(&mut self, seq: Vec<Ast>) -> Result<Vec<Ast>> {
+        let mut result = Vec::with_capacity(seq.len());
+        let mut seq = seq.into_iter().peekable();
+        while let Some(ast) = seq.next() {
+            if !seq.peek().is_some_and(is_ellipsis) {
+                result.push(self.expr(ast)?);
+                continue;
+            }
+            let ellipsis = seq.next().unwrap().span();
+
+            let mut names = Names::default();
+            names.expr(ast.clone())?;
+            let mut repeated = Vec::new();
+            for name in names.identifiers.into_iter().chain(names.binders) {
+                if let Some(Fragment::Many(fragments)) = self.bindings.get(&name) {
+                    if !repeated.iter().any(|(n, _)| *n == name) {
+                        repeated.push((name, fragments.clone()));
+                    }
+                }
+            }
+            let n = match repeated.first() {
+                Some((_, fragments)) => fragments.len(),
+                None => return Err(MacroError::new(MacroErrorKind::Ellipsis, ellipsis)),
+            };
+            if repeated.iter().any(|(_, fragments)| fragments.len() != n) {
+                return Err(MacroError::new(MacroErrorKind::Lengths, ellipsis));
+            }
+
+            for i in 0..n {
+                let mut bindings = self.bindings.clone();
+                for (name, fragments) in &repeated {
+                    bindings.insert(*name, fragments[i].clone());
+                }
+                result.push(Substitute { bindings, span: self.span }.expr(ast.clone())?);
+            }
+        }
+        Ok(result)
+    }
+
+    fn binder(&mut self, name: Symbol, span: Index) -> Result<Symbol> {
+        match self.bindings.get(&name) {
+            Some(Fragment::One(Ast::Identifier(name, _))) => Ok(*name),
+            Some(_) => Err(MacroError::new(MacroErrorKind::Binder, span)),
+            None => Ok(name),
+        }
+    }
+}
+
+/// Gives the free identifiers of hygienic templates back the names they were written with, once
+/// every macro has been expanded. Those names refer to the items of the top level, where macros
+/// are defined, so local variables at the call site which would capture them are renamed instead.
+struct Resolve {
+    free: HashMap<Symbol, Symbol>,
+    /// Names the free identifiers were written with
+    written: HashSet<Symbol>,
+    /// Structs, whose constructors name fields rather than variables
+    structs: Vec<Symbol>,
+    /// Local variables in scope along with their new names, innermost last
+    scope: Vec<(Symbol, Symbol)>,
+}
+
+impl Resolve {
+    fn name(&self, name: Symbol) -> Symbol {
+        match self.scope.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, local)) => *local,
+            None => *self.free.get(&name).unwrap_or(&name),
+        }
+    }
+
+    /// Runs `f`, after which the variables it bound go out of scope again
+    fn scoped<T, F>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut Self) -> Result<T>
+    {
+        let len = self.scope.len();
+        let result = f(self);
+        self.scope.truncate(len);
+        result
+    }
+
+    /// Names defined at the top level aren't local variables, so they keep their names.
+    fn item(&mut self, item: Ast) -> Result<Ast> {
+        match item {
+            Ast::Define { name, ty, value, span } => Ok(Ast::Define { name, ty, value: Box::new(self.expr(*value)?), span }),
+            Ast::Defn { name, params, ty, args, body, ret_span, span } => {
+                let (args, body) = self.function(args, body)?;
+                Ok(Ast::Defn { name, params, ty, args, body, ret_span, span })
+            }
+            Ast::Intrinsic { .. } => Ok(item),
+            item => self.expr(item),
+        }
+    }
+
+    fn function(&mut self, args: Vec<super::Arg>, body: Vec<Ast>) -> Result<(Vec<super::Arg>, Vec<Ast>)> {
+        self.scoped(|r| Ok((args_binders(r, args)?, r.seq(body)?)))
+    }
+
+    /// `(Point (x 1))` gives the field `x` whatever variables are in scope.
+    fn constructor(&mut self, elements: Vec<Ast>, span: Index) -> Result<Ast> {
+        let mut elements = elements.into_iter();
+        let name = self.expr(elements.next().unwrap())?;
+        let fields = elements
+            .map(|field| match field {
+                Ast::Application(mut pair, span) if pair.len() == 2 && pair[0].is_identifier() => {
+                    let value = self.expr(pair.pop().unwrap())?;
+                    let field = match pair.pop().unwrap() {
+                        Ast::Identifier(field, span) => Ast::Identifier(*self.free.get(&field).unwrap_or(&field), span),
+                        _ => unreachable!(),
+                    };
+                    Ok(Ast::Application(vec![field, value], span))
+                }
+                field => self.expr(field),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Ast::Application(iter::once(name).chain(fields).collect(), span))
+    }
+}
+
+impl Rewrite for Resolve {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        use Ast::*;
+        Ok(match ast {
+            Identifier(name, span) => Identifier(self.name(name), span),
+            Application(elements, span) => match elements.first() {
+                Some(&Identifier(name, _)) if self.structs.contains(&self.name(name)) => self.constructor(elements, span)?,
+                _ => Application(self.seq(elements)?, span),
+            },
+            // The value of a local constant can't see the constant itself, unlike the body of a
+            // local function
+            Define { name, ty, value, span } => {
+                let value = Box::new(self.expr(*value)?);
+                Define { name: self.binder(name, span)?, ty, value, span }
+            }
+            Defn { name, params, ty, args, body, ret_span, span } => {
+                let name = self.binder(name, span)?;
+                let (args, body) = self.function(args, body)?;
+                Defn { name, params, ty, args, body, ret_span, span }
+            }
+            Intrinsic { op, name, params, ty, args, span } => {
+                Intrinsic { op, name: self.binder(name, span)?, params, ty, args, span }
+            }
+            Impl { name, params, methods, span } => {
+                let methods = methods.into_iter()
+                    .map(|method| match method {
+                        Defn { name, params, ty, args, body, ret_span, span } => {
+                            let (args, body) = self.function(args, body)?;
+                            Ok(Defn { name, params, ty, args, body, ret_span, span })
+                        }
+                        method => self.expr(method),
+                    })
+                    .collect::<Result<_>>()?;
+                Impl { name, params, methods, span }
+            }
+            Lambda { args, body, span } => {
+                let (args, body) = self.function(args, body)?;
+                Lambda { args, body, span }
+            }
+            // The values of a plain `let` can't see any of its bindings
+            Let { bindings, body, sequential, span } => self.scoped(|r| {
+                let bindings = if sequential {
+                    bindings.into_iter()
+                        .map(|b| {
+                            let value = r.expr(b.value)?;
+                            Ok(Binding { name: r.binder(b.name, b.span)?, value, ..b })
+                        })
+                        .collect::<Result<Vec<_>>>()?
+                } else {
+                    let values = bindings.iter().map(|b| r.expr(b.value.clone())).collect::<Result<Vec<_>>>()?;
+                    bindings.into_iter().zip(values)
+                        .map(|(b, value)| Ok(Binding { name: r.binder(b.name, b.span)?, value, ..b }))
+                        .collect::<Result<_>>()?
+                };
+                Ok(Let { bindings, body: r.seq(body)?, sequential, span })
+            })?,
+            Match { value, arms, span } => {
+                let value = Box::new(self.expr(*value)?);
+                let arms = arms.into_iter()
+                    .map(|arm| self.scoped(|r| {
+                        let pattern = pattern_binders(r, arm.pattern)?;
+                        Ok(super::Arm { pattern, body: r.seq(arm.body)?, span: arm.span })
+                    }))
+                    .collect::<Result<_>>()?;
+                Match { value, arms, span }
+            }
+            ast => walk(self, ast)?,
+        })
+    }
+
+    /// Definitions in a body are visible until its end
+    fn seq(&mut self, seq: Vec<Ast>) -> Result<Vec<Ast>> {
+        self.scoped(|r| seq.into_iter().map(|ast| r.expr(ast)).collect())
+    }
+
+    fn binder(&mut self, name: Symbol, _span: Index) -> Result<Symbol> {
+        let local = if self.written.contains(&name) {
+            get_symbol_uninterned(get_value(name).unwrap_or_default())
+        } else {
+            name
+        };
+        self.scope.push((name, local));
+        Ok(local)
+    }
+}
@@ -0,0 +1,180 @@
+use super::{Arg, Arm, Ast, Binding, Clause, Pattern, Variant};
+use error::MacroError;
+
+use string_interner::Symbol;
+use tokenizer::Index;
+
+use std::collections::HashMap;
+
+/// Only expanding macros can fail
+pub type Result<T> = std::result::Result<T, MacroError>;
+
+/// Rebuilds a tree bottom up. Every method defaults to leaving its node as it is apart from
+/// rewriting the children.
+pub trait Rewrite {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        walk(self, ast)
+    }
+
+    /// Rewrites a sequence of expressions, such as the arguments of an application or a body
+    fn seq(&mut self, seq: Vec<Ast>) -> Result<Vec<Ast>> {
+        seq.into_iter().map(|ast| self.expr(ast)).collect()
+    }
+
+    /// Rewrites a name that a definition, argument, `let` or pattern binds
+    fn binder(&mut self, name: Symbol, _span: Index) -> Result<Symbol> {
+        Ok(name)
+    }
+
+    /// Rewrites the span of a node or of a part of one, such as an argument or a `let` binding
+    fn span(&mut self, span: Index) -> Index {
+        span
+    }
+}
+
+/// Rewrites the children of `ast` with `r`.
+pub fn walk<R: Rewrite + ?Sized>(r: &mut R, ast: Ast) -> Result<Ast> {
+    use Ast::*;
+    let boxed = |r: &mut R, ast: Box<Ast>| r.expr(*ast).map(Box::new);
+    Ok(match ast {
+        Include { path, namespace, span } => Include { path, namespace, span: r.span(span) },
+        Export(names, span) => Export(names, r.span(span)),
+        Define { name, ty, value, span } => {
+            let name = r.binder(name, span)?;
+            Define { name, ty, value: boxed(r, value)?, span: r.span(span) }
+        }
+        Defn { name, params, ty, args, body, ret_span, span } => {
+            let name = r.binder(name, span)?;
+            let args = args_binders(r, args)?;
+            let ret_span = ret_span.map(|s| r.span(s));
+            Defn { name, params, ty, args, body: r.seq(body)?, ret_span, span: r.span(span) }
+        }
+        Struct { name, params, fields, span } => {
+            let fields = fields.into_iter().map(|field| arg_spans(r, field)).collect();
+            Struct { name, params, fields, span: r.span(span) }
+        }
+        Enum { name, params, variants, span } => {
+            let variants = variants.into_iter()
+                .map(|v| Variant { span: r.span(v.span), ..v })
+                .collect();
+            Enum { name, params, variants, span: r.span(span) }
+        }
+        Impl { name, params, methods, span } => {
+            // Methods are looked up through their struct, so their names aren't bound like those
+            // of functions
+            let methods = methods.into_iter()
+                .map(|method| match method {
+                    Defn { name, params, ty, args, body, ret_span, span } => {
+                        let args = args_binders(r, args)?;
+                        let ret_span = ret_span.map(|s| r.span(s));
+                        Ok(Defn { name, params, ty, args, body: r.seq(body)?, ret_span, span: r.span(span) })
+                    }
+                    _ => r.expr(method),
+                })
+                .collect::<Result<_>>()?;
+            Impl { name, params, methods, span: r.span(span) }
+        }
+        If { predicate, consequent, alternative, span } => If {
+            predicate: boxed(r, predicate)?,
+            consequent: boxed(r, consequent)?,
+            alternative: alternative.map(|a| boxed(r, a)).transpose()?,
+            span: r.span(span),
+        },
+        Cond { clauses, alternative, span } => {
+            let clauses = clauses.into_iter()
+                .map(|c| Ok(Clause { test: r.expr(c.test)?, body: r.seq(c.body)?, span: r.span(c.span) }))
+                .collect::<Result<_>>()?;
+            Cond { clauses, alternative: alternative.map(|a| r.seq(a)).transpose()?, span: r.span(span) }
+        }
+        Match { value, arms, span } => {
+            let value = boxed(r, value)?;
+            let arms = arms.into_iter()
+                .map(|arm| {
+                    let pattern = pattern_binders(r, arm.pattern)?;
+                    Ok(Arm { pattern, body: r.seq(arm.body)?, span: r.span(arm.span) })
+                })
+                .collect::<Result<_>>()?;
+            Match { value, arms, span: r.span(span) }
+        }
+        Let { bindings, body, sequential, span } => {
+            let bindings = bindings.into_iter()
+                .map(|b| {
+                    let name = r.binder(b.name, b.span)?;
+                    let value = r.expr(b.value)?;
+                    let ty_span = b.ty_span.map(|s| r.span(s));
+                    Ok(Binding { name, value, span: r.span(b.span), ty_span, ..b })
+                })
+                .collect::<Result<_>>()?;
+            Let { bindings, body: r.seq(body)?, sequential, span: r.span(span) }
+        }
+        While { predicate, body, span } => While { predicate: boxed(r, predicate)?, body: r.seq(body)?, span: r.span(span) },
+        Loop(body, span) => Loop(r.seq(body)?, r.span(span)),
+        Break(value, span) => Break(value.map(|v| boxed(r, v)).transpose()?, r.span(span)),
+        Continue(span) => Continue(r.span(span)),
+        Block(body, span) => Block(r.seq(body)?, r.span(span)),
+        Intrinsic { op, name, params, ty, args, span } => {
+            let name = r.binder(name, span)?;
+            let args = args_binders(r, args)?;
+            Intrinsic { op, name, params, ty, args, span: r.span(span) }
+        }
+        Application(elements, span) => Application(r.seq(elements)?, r.span(span)),
+        Field { value, field, span } => Field { value: boxed(r, value)?, field, span: r.span(span) },
+        Set { target, value, span } => Set { target: boxed(r, target)?, value: boxed(r, value)?, span: r.span(span) },
+        Subscript { value, index, span } => Subscript { value: boxed(r, value)?, index: boxed(r, index)?, span: r.span(span) },
+        Deref(value, span) => Deref(boxed(r, value)?, r.span(span)),
+        AddrOf(value, span) => AddrOf(boxed(r, value)?, r.span(span)),
+        Cast { ty, value, span } => Cast { ty, value: boxed(r, value)?, span: r.span(span) },
+        Lambda { args, body, span } => {
+            let args = args_binders(r, args)?;
+            Lambda { args, body: r.seq(body)?, span: r.span(span) }
+        }
+        Primitive(primitive, span) => Primitive(primitive, r.span(span)),
+        Asm(tokens, span) => Asm(tokens, r.span(span)),
+        Identifier(name, span) => Identifier(name, r.span(span)),
+        // Macros never reach code that reports errors, they are removed by `expand`
+        Macro { .. } => ast,
+    })
+}
+
+fn arg_spans<R: Rewrite + ?Sized>(r: &mut R, arg: Arg) -> Arg {
+    Arg { span: r.span(arg.span), ty_span: r.span(arg.ty_span), ..arg }
+}
+
+pub fn args_binders<R: Rewrite + ?Sized>(r: &mut R, args: Vec<Arg>) -> Result<Vec<Arg>> {
+    args.into_iter()
+        .map(|arg| {
+            let name = r.binder(arg.name, arg.span)?;
+            Ok(arg_spans(r, Arg { name, ..arg }))
+        })
+        .collect()
+}
+
+pub fn pattern_binders<R: Rewrite + ?Sized>(r: &mut R, pattern: Pattern) -> Result<Pattern> {
+    Ok(match pattern {
+        Pattern::Binding(name, span) => Pattern::Binding(r.binder(name, span)?, r.span(span)),
+        Pattern::Variant { name, fields, span } => Pattern::Variant {
+            name,
+            fields: fields.into_iter().map(|p| pattern_binders(r, p)).collect::<Result<_>>()?,
+            span: r.span(span),
+        },
+        Pattern::Wildcard(span) => Pattern::Wildcard(r.span(span)),
+    })
+}
+
+/// Renames variables, both where they are bound and where they are used
+pub struct Rename {
+    pub renames: HashMap<Symbol, Symbol>,
+}
+
+impl Rewrite for Rename {
+    fn expr(&mut self, ast: Ast) -> Result<Ast> {
+        match ast {
+            Ast::Identifier(name, span) => Ok(Ast::Identifier(*self.renames.get(&name).unwrap_or(&name), span)),
+            _ => walk(self, ast),
+        }
+    }
+
+    fn binder(&mut self, name: Symbol, _span: Index) -> Result<Symbol> {
+        Ok(*self.renames.get(&name).unwrap_or(&name))
+    }
+}
@@ -0,0 +1,42 @@
+extern crate parser;
+
+use parser::{SyntaxNode, SyntaxTree};
+
+fn round_trip(input: &str) -> SyntaxTree {
+    let tree = SyntaxTree::parse(input, Default::default());
+    assert_eq!(tree.to_source(input), input);
+    tree
+}
+
+#[test]
+fn libraries() {
+    round_trip(include_str!("../../libs/unix/lib.inc"));
+    round_trip(include_str!("../../libs/core/intrinsics.inc"));
+    round_trip(include_str!("../../libs/collections/arraybuf.inc"));
+    round_trip(include_str!("../../bootstrap/sasm/sasm.sasm"));
+}
+
+#[test]
+fn trivia() {
+    let tree = round_trip("  ; leading\n(defn (main ()) #| block |#\n\t(exit 0))  \n");
+    assert_eq!(tree.nodes.len(), 1);
+    assert_eq!(tree.trailing.len(), 1);
+    match &tree.nodes[0] {
+        SyntaxNode::List { open, children, close } => {
+            assert_eq!(open.leading.len(), 2);
+            assert_eq!(children.len(), 3);
+            assert!(close.is_some());
+        }
+        _ => panic!("expected a list"),
+    }
+}
+
+#[test]
+fn malformed() {
+    round_trip("(a (b c");
+    round_trip(") a ]");
+    round_trip("(print \"unterminated");
+    round_trip("(λ x) #'ab' 256u8 |# (y)");
+    let tree = round_trip("(a #| never closed");
+    assert!(!tree.errors.is_empty());
+}
@@ -0,0 +1,127 @@
+extern crate parser;
+extern crate string_interner;
+extern crate tokenizer;
+
+use parser::{Ast, LoadError, ParserErrorKind};
+use string_interner::{get_symbol, get_value, Symbol};
+use tokenizer::SourceMap;
+
+use std::fs;
+use std::path::Path;
+
+/// Writes `files` to a fresh directory named after `test` and loads the first of them, with
+/// `libs` in that directory as the search path.
+fn load(test: &str, files: &[(&str, &str)]) -> Result<Vec<Ast>, Vec<LoadError>> {
+    let dir = std::env::temp_dir().join(format!("include-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (name, source) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+
+    let mut sources = SourceMap::new();
+    let path = dir.join(files[0].0);
+    let file = sources.add_file(path.display().to_string(), files[0].1.into());
+    let result = parser::load(file, &mut sources, &[dir.join("libs")]);
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+/// Loads `input` from a file outside the repository, with the standard library as search path
+fn load_with_libs(test: &str, input: &str) -> Result<Vec<Ast>, Vec<LoadError>> {
+    let dir = std::env::temp_dir().join(format!("include-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.inc");
+    fs::write(&path, input).unwrap();
+
+    let mut sources = SourceMap::new();
+    let file = sources.add_file(path.display().to_string(), input.into());
+    let libs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../libs");
+    let result = parser::load(file, &mut sources, &[libs]);
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+fn errors(test: &str, files: &[(&str, &str)]) -> Vec<ParserErrorKind> {
+    load(test, files).unwrap_err().into_iter()
+        .map(|e| match e {
+            LoadError::Parse(e) => e.kind,
+            LoadError::Tokenize(e) => panic!("unexpected tokenizer error {:?}", e),
+        })
+        .collect()
+}
+
+fn defined(ast: &[Ast]) -> Vec<Symbol> {
+    ast.iter()
+        .filter_map(|item| match item {
+            Ast::Defn { name, .. } => Some(*name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn standard_library() {
+    // The standard library can be named with or without its directory
+    for input in ["(include libs/unix/lib.inc)", "(include unix/lib.inc)"] {
+        let ast = load_with_libs("standard-library", input).unwrap();
+        let names = defined(&ast);
+        assert!(names.contains(&get_symbol("print".into())), "{:?}", names);
+    }
+}
+
+#[test]
+fn includes() {
+    let ast = load("includes", &[
+        ("main.inc", "(include util.inc)
+                      (include lib/math.inc as math)
+                      (include collections/list.inc)
+                      (include util.inc)
+                      (defn (main ()) (math::square 2) (helper))"),
+        ("util.inc", "(defn (helper ()))"),
+        ("lib/math.inc", "(export square)
+                          (defn (square ([x i32]) i32) (mul x x))
+                          (defn (mul ([a i32] [b i32]) i32) a)"),
+        ("libs/collections/list.inc", "(include ../../util.inc) (defn (list ()))"),
+    ]).unwrap();
+
+    // Included files come first and are only loaded once
+    let names = defined(&ast);
+    let values: Vec<_> = names.iter().map(|s| get_value(*s).unwrap()).collect();
+    assert_eq!(values, ["helper", "math::square", "mul", "list", "main"]);
+
+    // Names that aren't exported can't be referred to from other files
+    let mul = names[2];
+    assert_ne!(mul, get_symbol("mul".into()));
+    match &ast[1] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Application(call, _) => assert!(matches!(call[0], Ast::Identifier(s, _) if s == mul)),
+            ast => panic!("expected an application, found {:?}", ast),
+        },
+        ast => panic!("expected a defn, found {:?}", ast),
+    }
+}
+
+#[test]
+fn include_errors() {
+    assert_eq!(errors("missing", &[("main.inc", "(include nothing.inc)")]), [ParserErrorKind::Include]);
+    assert_eq!(errors("cycle", &[
+        ("a.inc", "(include b.inc)"),
+        ("b.inc", "(include a.inc)"),
+    ]), [ParserErrorKind::Cycle]);
+    assert_eq!(errors("namespace", &[
+        ("main.inc", "(include a.inc as a) (include a.inc)"),
+        ("a.inc", "(defn (f ()))"),
+    ]), [ParserErrorKind::Namespace]);
+    assert_eq!(errors("export", &[
+        ("main.inc", "(include a.inc)"),
+        ("a.inc", "(export f g) (defn (f ()))"),
+    ]), [ParserErrorKind::Export]);
+
+    // Errors in included files are reported along with those of the including file
+    assert_eq!(errors("nested", &[
+        ("main.inc", "(include a.inc) (defn f)"),
+        ("a.inc", "(defn (f ()) (1))"),
+    ]).len(), 2);
+}
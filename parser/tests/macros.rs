@@ -0,0 +1,244 @@
+extern crate parser;
+extern crate string_interner;
+extern crate tokenizer;
+
+use parser::{Ast, MacroError, MacroErrorKind, ParserErrorKind};
+use string_interner::{get_symbol, get_value};
+use tokenizer::{SourceMap, Tokenizer};
+
+fn expand(input: &str) -> Result<Vec<Ast>, MacroError> {
+    expand_with(input, &mut SourceMap::new())
+}
+
+fn expand_with(input: &str, sources: &mut SourceMap) -> Result<Vec<Ast>, MacroError> {
+    let tokens = Tokenizer::tokenize(input).unwrap();
+    parser::expand(parser::parse(tokens, input).unwrap(), sources)
+}
+
+/// Body of the only function in `input` after expansion
+fn body(input: &str) -> Vec<Ast> {
+    let ast = expand(input).unwrap();
+    assert_eq!(ast.len(), 1, "{:?}", ast);
+    match ast.into_iter().next() {
+        Some(Ast::Defn { body, .. }) => body,
+        ast => panic!("expected a defn, found {:?}", ast),
+    }
+}
+
+fn name(ast: &Ast) -> String {
+    match ast {
+        Ast::Identifier(s, _) => get_value(*s).unwrap(),
+        _ => panic!("expected an identifier, found {:?}", ast),
+    }
+}
+
+#[test]
+fn syntax_rules() {
+    let library = include_str!("../../libs/core/macros.inc");
+    let input = format!("{}(defn (f ()) (when (p) (g 1) (g 2)) (unless (p)))", library);
+    let body = body(&input);
+    match &body[0] {
+        Ast::If { consequent, alternative: None, .. } => match &**consequent {
+            Ast::Block(body, _) => assert_eq!(body.len(), 2),
+            ast => panic!("expected a block, found {:?}", ast),
+        },
+        ast => panic!("expected an if, found {:?}", ast),
+    }
+    assert!(body[1].is_if());
+
+    // Literals only match themselves, and the first matching rule is used
+    let body = self::body("
+        (define-syntax choose
+          (syntax-rules (else)
+            ((_ c a else b) (if c a b))
+            ((_ c a) (if c a))))
+        (defn (f ()) (choose #t 1 else 2) (choose #t 1))");
+    assert!(matches!(&body[0], Ast::If { alternative: Some(_), .. }));
+    assert!(matches!(&body[1], Ast::If { alternative: None, .. }));
+    assert_eq!(expand("
+        (define-syntax choose (syntax-rules (else) ((_ c a else b) (if c a b))))
+        (defn (f ()) (choose #t 1 2 3))").unwrap_err().kind, MacroErrorKind::NoMatch);
+
+    // Recursive macros and nested ellipses
+    let body = self::body("
+        (define-syntax all
+          (syntax-rules ()
+            ((_) #t)
+            ((_ e rest ...) (if e (all rest ...) #f))))
+        (define-syntax calls
+          (syntax-rules ()
+            ((_ (f a ...) ...) {begin (f a ... 0) ...})))
+        (defn (f ()) (all a b c) (calls (g 1 2) (h)))");
+    match &body[0] {
+        Ast::If { consequent, .. } => assert!(consequent.is_if()),
+        ast => panic!("expected an if, found {:?}", ast),
+    }
+    match &body[1] {
+        Ast::Block(calls, _) => match (&calls[0], &calls[1]) {
+            (Ast::Application(g, _), Ast::Application(h, _)) => {
+                assert_eq!(g.len(), 4);
+                assert_eq!(name(&g[0]), "g");
+                assert_eq!(h.len(), 2);
+            }
+            _ => panic!("expected applications"),
+        },
+        ast => panic!("expected a block, found {:?}", ast),
+    }
+}
+
+#[test]
+fn hygiene() {
+    let swap = "
+        (define-syntax swap!
+          (syntax-rules ()
+            ((_ a b) (let ([tmp a]) (set! a b) (set! b tmp)))))";
+
+    // The `tmp` bound by the template is a different variable from the one at the call site
+    let body = body(&format!("{}(defn (f ()) (swap! tmp x))", swap));
+    match &body[0] {
+        Ast::Let { bindings, body, .. } => {
+            let tmp = bindings[0].name;
+            assert_eq!(get_value(tmp).unwrap(), "tmp");
+            match (&bindings[0].value, &body[0]) {
+                (Ast::Identifier(user, _), Ast::Set { value, .. }) => {
+                    assert_ne!(*user, tmp);
+                    assert_eq!(name(value), "x");
+                }
+                _ => panic!("expected the arguments to be substituted"),
+            }
+            match &body[1] {
+                Ast::Set { value, .. } => assert!(matches!(**value, Ast::Identifier(s, _) if s == tmp)),
+                ast => panic!("expected a set!, found {:?}", ast),
+            }
+        }
+        ast => panic!("expected a let, found {:?}", ast),
+    }
+
+    // Names bound through pattern variables are the ones from the call site
+    let body = self::body("
+        (define-syntax bind (syntax-rules () ((_ name value body) (let ([name value]) body))))
+        (defn (f ()) (bind x 1 x))");
+    match &body[0] {
+        Ast::Let { bindings, body, .. } => match &body[0] {
+            Ast::Identifier(x, _) => assert_eq!(bindings[0].name, *x),
+            ast => panic!("expected an identifier, found {:?}", ast),
+        },
+        ast => panic!("expected a let, found {:?}", ast),
+    }
+
+    // defmacro isn't hygienic, so `it` is visible to the body
+    let body = self::body("
+        (defmacro (with-it value body) (let ([it value]) body))
+        (defn (f ()) (with-it 1 it))");
+    match &body[0] {
+        Ast::Let { bindings, body, .. } => match &body[0] {
+            Ast::Identifier(it, _) => assert_eq!(bindings[0].name, *it),
+            ast => panic!("expected an identifier, found {:?}", ast),
+        },
+        ast => panic!("expected a let, found {:?}", ast),
+    }
+}
+
+#[test]
+fn free_identifiers() {
+    // The template's `helper` is the function of the top level, so the argument named `helper` at
+    // the call site is renamed instead of capturing it
+    let ast = expand("
+        (defn (helper ([x i32]) i32) x)
+        (define-syntax twice (syntax-rules () ((_ x) (helper (helper x)))))
+        (defn (f ([helper i32]) i32) (twice helper))").unwrap();
+    match &ast[1] {
+        Ast::Defn { args, body, .. } => match &body[0] {
+            Ast::Application(outer, _) => match &outer[1] {
+                Ast::Application(inner, _) => {
+                    let helper = get_symbol("helper".into());
+                    assert!(matches!(outer[0], Ast::Identifier(s, _) if s == helper));
+                    assert!(matches!(inner[0], Ast::Identifier(s, _) if s == helper));
+                    assert_ne!(args[0].name, helper);
+                    assert!(matches!(inner[1], Ast::Identifier(s, _) if s == args[0].name));
+                }
+                ast => panic!("expected an application, found {:?}", ast),
+            },
+            ast => panic!("expected an application, found {:?}", ast),
+        },
+        ast => panic!("expected a defn, found {:?}", ast),
+    }
+
+    // Locals which no template refers to keep their names
+    let body = self::body("
+        (define-syntax 1+ (syntax-rules () ((_ x) (+ x 1))))
+        (defn (f ([x i32]) i32) (let ([y (1+ x)]) y))");
+    match &body[0] {
+        Ast::Let { bindings, body, .. } => {
+            assert_eq!(get_value(bindings[0].name).unwrap(), "y");
+            assert_eq!(name(&body[0]), "y");
+            assert!(matches!(body[0], Ast::Identifier(s, _) if s == bindings[0].name));
+        }
+        ast => panic!("expected a let, found {:?}", ast),
+    }
+}
+
+#[test]
+fn items() {
+    // A macro used at the top level can expand to several items
+    let ast = expand("
+        (define-syntax constants
+          (syntax-rules ()
+            ((_ (name value) ...) {begin (define (name i32) value) ...})))
+        (constants (a 1) (b 2))
+        (defn (f () i32) a)").unwrap();
+    assert_eq!(ast.len(), 3);
+    assert!(ast[..2].iter().all(|item| item.is_define()));
+
+    let input = "(defmacro (m) (g 1)) (m)";
+    let e = expand(input).unwrap_err();
+    assert_eq!(e.kind, MacroErrorKind::Item);
+    assert_eq!(&input[e.span.start()..e.span.end()], "(m)");
+    assert_eq!(expand("(g 1)").unwrap_err().kind, MacroErrorKind::Item);
+}
+
+#[test]
+fn errors() {
+    // The span leads from the expansion that failed back to the use in the source
+    let input = "
+        (defmacro (outer x) (inner x))
+        (defmacro (inner x y) y)
+        (defn (f ()) (outer 1))";
+    let mut sources = SourceMap::new();
+    let e = expand_with(input, &mut sources).unwrap_err();
+    assert_eq!(e.kind, MacroErrorKind::NoMatch);
+    assert_eq!(&input[e.span.start()..e.span.end()], "(inner x)");
+    let trace = sources.expansions(e.span);
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].0, "outer");
+    assert_eq!(&input[trace[0].1.start()..trace[0].1.end()], "(outer 1)");
+
+    let mut sources = SourceMap::new();
+    let e = expand_with("(defmacro (forever x) (forever x)) (defn (f ()) (forever 1))", &mut sources).unwrap_err();
+    assert_eq!(e.kind, MacroErrorKind::Depth);
+    assert_eq!(sources.expansions(e.span).len(), 128);
+
+    let kind = |input| expand(input).unwrap_err().kind;
+    assert_eq!(kind("(defmacro (m) 1) (defmacro (m) 2)"), MacroErrorKind::Redefined);
+    assert_eq!(kind("(defn (f ()) (defmacro (m) 1))"), MacroErrorKind::Nested);
+    assert_eq!(kind("(defmacro (m x ...) x) (defn (f ()) (m 1))"), MacroErrorKind::Ellipsis);
+    assert_eq!(kind("(defmacro (m x) (g x ...)) (defn (f ()) (m 1))"), MacroErrorKind::Ellipsis);
+    assert_eq!(kind("(defmacro (m (x ...) (y ...)) (g (h x y) ...)) (defn (f ()) (m (g 1 2) (h 3)))"),
+               MacroErrorKind::Lengths);
+    assert_eq!(kind("(defmacro (m x) (let ([x 1]) x)) (defn (f ()) (m (g)))"), MacroErrorKind::Binder);
+
+    // An ellipsis repeated no times can leave nothing to call
+    let input = "(define-syntax m (syntax-rules () ((_ x ...) (x ...)))) (defn (f ()) (m))";
+    let e = expand(input).unwrap_err();
+    assert_eq!(e.kind, MacroErrorKind::Empty);
+    assert_eq!(&input[e.span.start()..e.span.end()], "(m)");
+    assert!(expand("(define-syntax m (syntax-rules () ((_ x ...) (g x ...)))) (defn (f ()) (m))").is_ok());
+
+    let parse_error = |input: &str| {
+        let tokens = Tokenizer::tokenize(input).unwrap();
+        parser::parse(tokens, input).unwrap_err()[0].kind
+    };
+    assert_eq!(parse_error("(defmacro (m ... x) x)"), ParserErrorKind::Ellipsis);
+    assert_eq!(parse_error("(defmacro (m x ... y ...) x)"), ParserErrorKind::Ellipsis);
+    assert_eq!(parse_error("(define-syntax m (rules))"), ParserErrorKind::Token);
+}
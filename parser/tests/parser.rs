@@ -0,0 +1,486 @@
+extern crate parser;
+extern crate tokenizer;
+
+use parser::{Ast, ParserError, ParserErrorKind};
+use tokenizer::Tokenizer;
+
+fn parse(input: &str) -> Result<Vec<Ast>, Vec<ParserError>> {
+    let tokens = Tokenizer::tokenize(input).unwrap();
+    parser::parse(tokens, input)
+}
+
+fn error(input: &str) -> ParserErrorKind {
+    let errors = parse(input).unwrap_err();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    errors[0].kind
+}
+
+fn errors(input: &str) -> (Vec<Ast>, Vec<ParserErrorKind>) {
+    let tokens = Tokenizer::tokenize(input).unwrap();
+    let (ast, errors) = parser::parse_partial(tokens, input);
+    (ast, errors.into_iter().map(|e| e.kind).collect())
+}
+
+#[test]
+fn comments() {
+    let ast = parse("(defn (f () i32) ; trailing\n 1 #| block |#)\n; end").unwrap();
+    assert_eq!(ast.len(), 1);
+    let ast = parse("(defn (f ()) (if #t (f) ; no alternative\n))").unwrap();
+    assert_eq!(ast.len(), 1);
+}
+
+#[test]
+fn malformed_input() {
+    assert_eq!(error("(define x #x)"), ParserErrorKind::Token);
+    assert_eq!(error("(define x # 5)"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ((x ptr))))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f ((x (u8 i32)))))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f ()) (#foo 1))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (# 1))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (1 2))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) ())"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (g 1"), ParserErrorKind::EOI);
+    assert_eq!(error("(defn (f ()) (if"), ParserErrorKind::EOI);
+}
+
+#[test]
+fn error_spans() {
+    let input = "(define x #foo)";
+    let e = parse(input).unwrap_err().remove(0);
+    assert_eq!(&input[e.span.start()..e.span.end()], "#foo");
+    assert_eq!(e.to_string(), "Unexpected token, expected `#t` or `#f`, found `#foo`");
+}
+
+#[test]
+fn recovery() {
+    use parser::ParserErrorKind::*;
+
+    // Every bad expression in a list is reported and only the clean item is kept
+    let (ast, kinds) = errors("(defn (f ()) (g #x (as)) (h 1 (define y 2)))\n(defn (ok ()) (g))");
+    assert_eq!(ast.len(), 1);
+    assert_eq!(kinds, vec![Token, Token, Value]);
+
+    // Bad fixed-shape forms are skipped as a whole
+    let (ast, kinds) = errors("(define x 1 2)\n) (if #t 1)\n(define y 1)");
+    assert_eq!(ast.len(), 1);
+    assert_eq!(kinds, vec![Closer, Closer, Item]);
+
+    // An unclosed item ends where the next one begins
+    let (ast, kinds) = errors("(defn (f ())\n  (g (h 1)\n(defn (ok ()) (g))\n(define y #q)");
+    assert_eq!(ast.len(), 1);
+    assert_eq!(kinds, vec![Closer, Token]);
+
+    let (ast, kinds) = errors("(define x 1)\n(defn (f ()) (g 1");
+    assert_eq!(ast.len(), 1);
+    assert_eq!(kinds, vec![EOI]);
+}
+
+#[test]
+fn delimiters() {
+    use parser::ParserErrorKind::*;
+
+    let ast = parse("(defn (f ([a i32] (b i32)) i32) {begin (g a) b})\n(defn (g ()) {(g) (g)})").unwrap();
+    match &ast[0] {
+        Ast::Defn { args, body, .. } => {
+            assert_eq!(args.len(), 2);
+            assert!(body[0].is_block());
+        }
+        _ => panic!("expected a defn"),
+    }
+
+    let input = "(defn (f ()) (begin (g) ])";
+    let e = parse(input).unwrap_err().remove(0);
+    assert_eq!(e.kind, Delimiter);
+    assert_eq!(&input[e.span.start()..e.span.end()], "]");
+    assert_eq!(e.to_string(), "Mismatched closing delimiter, expected `)`, found `]`");
+
+    assert_eq!(error("(defn (f ([a i32)) i32) a)"), Delimiter);
+    assert_eq!(error("(defn (f ()) (#asm (mov rax (i32 1])))"), Delimiter);
+    assert_eq!(error("(defn (f ()) [g 1])"), Bracket);
+    assert_eq!(error("(defn [f ()] 1)"), Token);
+    assert_eq!(error("(defn (f ([a (ptr u8}])))"), Delimiter);
+}
+
+#[test]
+fn let_bindings() {
+    let ast = parse("(defn (f ()) (let* ([x i32 1] [y (ptr u8) (g)] [z (g x)]) z))").unwrap();
+    match &ast[0] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Let { bindings, sequential, .. } => {
+                assert!(sequential);
+                assert_eq!(bindings[0].ty, parser::Type::I32);
+                assert!(bindings[1].ty.is_ptr());
+                assert!(bindings[2].ty.is_hole());
+                assert!(bindings[2].ty_span.is_none());
+            }
+            _ => panic!("expected a let"),
+        },
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(defn (f ()) (let ([x]) x))"), ParserErrorKind::Closer);
+    assert_eq!(error("(defn (f ()) (let ([x (define y 1)]) x))"), ParserErrorKind::Value);
+    assert_eq!(error("(defn (f ()) (let [x 1] x))"), ParserErrorKind::Token);
+}
+
+#[test]
+fn define_values() {
+    // Anything that a `let` binding accepts
+    let ast = parse("(define (MASK u8) (as u8 255))\n(define x (if #t 1 2))\n(define id (lambda ([x i32]) x))\n\
+                     (define y (let ([a 1]) a))\n(define z {begin (g) 1})\n(define p (deref q))").unwrap();
+    let values: Vec<_> = ast.iter()
+        .map(|item| match item {
+            Ast::Define { value, .. } => &**value,
+            _ => panic!("expected a define"),
+        })
+        .collect();
+    assert!(values[0].is_cast() && values[1].is_if() && values[2].is_lambda());
+    assert!(values[3].is_let() && values[4].is_block() && values[5].is_deref());
+
+    assert_eq!(error("(define x (define y 1))"), ParserErrorKind::Value);
+    assert_eq!(error("(define x (struct Point [x i32]))"), ParserErrorKind::Value);
+}
+
+#[test]
+fn loops() {
+    let ast = parse("(defn (f ()) (while #t (g) (continue)) (loop (break (g))))").unwrap();
+    match &ast[0] {
+        Ast::Defn { body, .. } => {
+            assert!(body[0].is_while());
+            match &body[1] {
+                Ast::Loop(body, _) => assert!(body[0].is_break()),
+                _ => panic!("expected a loop"),
+            }
+        }
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(defn (f ()) (break))"), ParserErrorKind::Loop);
+    assert_eq!(error("(defn (f ()) (if #t (continue)))"), ParserErrorKind::Loop);
+    assert_eq!(error("(defn (f ()) (loop (defn (g ()) (break))))"), ParserErrorKind::Loop);
+    assert_eq!(error("(defn (f ()) (loop (break 1 2)))"), ParserErrorKind::Closer);
+    assert_eq!(error("(defn (f ()) (while))"), ParserErrorKind::Closer);
+}
+
+#[test]
+fn cond() {
+    let ast = parse("(defn (f ()) (cond ((g) 1) ((h) (g) 2) (else)))").unwrap();
+    match &ast[0] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Cond { clauses, alternative, .. } => {
+                assert_eq!(clauses.len(), 2);
+                assert_eq!(clauses[1].body.len(), 2);
+                assert_eq!(alternative.as_ref().map(|a| a.len()), Some(0));
+            }
+            _ => panic!("expected a cond"),
+        },
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(defn (f ()) (cond (else 1) ((g) 2)))"), ParserErrorKind::Else);
+    assert_eq!(error("(defn (f ()) (cond))"), ParserErrorKind::Closer);
+    assert_eq!(error("(defn (f ()) (cond [(g) 1]))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (cond ((g) 1]))"), ParserErrorKind::Delimiter);
+}
+
+#[test]
+fn structs() {
+    let input = "(struct Point [x i32] (y (ptr Point)))\n(defn (f ([p Point])) (set! p.y.x (g p.x)))";
+    let ast = parse(input).unwrap();
+    match &ast[0] {
+        Ast::Struct { fields, .. } => {
+            assert_eq!(fields.len(), 2);
+            assert!(fields[0].ty.is_i32());
+            assert!(fields[1].ty.is_ptr());
+        }
+        _ => panic!("expected a struct"),
+    }
+    match &ast[1] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Set { target, .. } => match &**target {
+                Ast::Field { value, span, .. } => {
+                    assert!(value.is_field());
+                    assert_eq!(&input[span.start()..span.end()], "p.y.x");
+                    assert_eq!(&input[value.span().start()..value.span().end()], "p.y");
+                }
+                _ => panic!("expected a field"),
+            },
+            _ => panic!("expected a set!"),
+        },
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(struct Point [x])"), ParserErrorKind::Token);
+    assert_eq!(error("(struct (Point) [x i32])"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (set! (g) 1))"), ParserErrorKind::Value);
+}
+
+#[test]
+fn generics() {
+    use parser::Type;
+
+    let input = "(struct (Pair A B) [a A] [b (ptr B)])\n\
+                 {impl (Pair A B)\n  (defn (first (self) A) self.a)\n  (defn ((swap C) (self [c C]) (Pair B C)) (g))}";
+    let ast = parse(input).unwrap();
+    match &ast[0] {
+        Ast::Struct { params, fields, .. } => {
+            assert_eq!(params.len(), 2);
+            assert!(fields[0].ty.is_var());
+            assert!(fields[1].ty.is_ptr());
+        }
+        _ => panic!("expected a struct"),
+    }
+    match &ast[1] {
+        Ast::Impl { params, methods, .. } => {
+            assert_eq!(params.len(), 2);
+            match &methods[1] {
+                Ast::Defn { params, args, ty, .. } => {
+                    assert_eq!(params.len(), 1);
+                    match &args[0].ty {
+                        Type::Ptr(ty) => assert!(ty.is_struct()),
+                        _ => panic!("expected a pointer to self"),
+                    }
+                    assert!(args[1].ty.is_var());
+                    match ty {
+                        Type::Arrow(_, ret) => match &**ret {
+                            Type::Struct(_, args) => assert!(args.iter().all(|t| t.is_var())),
+                            _ => panic!("expected a struct return type"),
+                        },
+                        _ => panic!("expected a function type"),
+                    }
+                }
+                _ => panic!("expected a method"),
+            }
+        }
+        _ => panic!("expected an impl"),
+    }
+
+    assert_eq!(error("(defn (f ([x (i32 u8)])))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f ([x (ptr u8 u8)])))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f (self)))"), ParserErrorKind::Token);
+    assert_eq!(error("(impl Point (define x 1))"), ParserErrorKind::Item);
+}
+
+#[test]
+fn define_methods() {
+    use parser::Type;
+
+    // The impl block of libs/collections/arraybuf.inc
+    let ast = parse("
+        (struct (Buf T) (length usize) (memory (Option (NonNullPtr T))))
+        {impl (Buf T)
+            (define (new (Buf T))
+                (Buf (length 0) (None)))
+            (define (push self (value T))
+                (define (x i32) 1)
+                (set length (1+ length)))}").unwrap();
+    match &ast[0] {
+        Ast::Struct { fields, .. } => match &fields[1].ty {
+            Type::Struct(_, args) => assert!(matches!(&args[..], [Type::Struct(_, inner)] if inner[0].is_var())),
+            ty => panic!("expected a type application, found {:?}", ty),
+        },
+        ast => panic!("expected a struct, found {:?}", ast),
+    }
+    match &ast[1] {
+        Ast::Impl { methods, .. } => match &methods[..] {
+            [Ast::Defn { args: new_args, ty: new_ty, body: new_body, .. },
+             Ast::Defn { args, ty, body, .. }] => {
+                assert!(new_args.is_empty());
+                assert!(matches!(new_ty, Type::Arrow(_, ret) if ret.is_struct()));
+                assert_eq!(new_body.len(), 1);
+                assert_eq!(args.len(), 2);
+                assert!(args[0].ty.is_ptr());
+                assert!(matches!(ty, Type::Arrow(_, ret) if **ret == Type::Empty));
+                // Only the items of the impl block define methods
+                assert!(body[0].is_define());
+            }
+            methods => panic!("expected two methods, found {:?}", methods),
+        },
+        ast => panic!("expected an impl, found {:?}", ast),
+    }
+}
+
+#[test]
+fn literals() {
+    use parser::{CompilePrimitive, Type};
+
+    let values = |input: &str| match &parse(input).unwrap()[0] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Application(v, _) => v[1..].to_vec(),
+            _ => panic!("expected an application"),
+        },
+        _ => panic!("expected a function"),
+    };
+    match &values("(defn (f ()) (g #'a' #'\\n' 1.5 -2e3 (as u8 #'\\xff')))")[..] {
+        [Ast::Primitive(CompilePrimitive::Char(a), _), Ast::Primitive(CompilePrimitive::Char(n), _),
+         Ast::Primitive(CompilePrimitive::Float(f), _), Ast::Primitive(CompilePrimitive::Float(g), _),
+         Ast::Cast { ty, value, .. }] => {
+            assert_eq!((*a, *n), ('a' as u32, '\n' as u32));
+            assert_eq!((*f, *g), (1.5, -2000.0));
+            assert_eq!(*ty, Type::U8);
+            assert_eq!(value.ty(), Type::Char);
+        }
+        v => panic!("unexpected values {:?}", v),
+    }
+
+    assert_eq!(error("(define x (as u8))"), ParserErrorKind::Closer);
+    assert_eq!(error("(defn (f ()) (as 1 2))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (as u8 1 2))"), ParserErrorKind::Closer);
+}
+
+#[test]
+fn angle_types() {
+    let arg_types = |input: &str| match &parse(input).unwrap()[0] {
+        Ast::Defn { args, .. } => args.iter().map(|arg| arg.ty.clone()).collect::<Vec<_>>(),
+        _ => panic!("expected a function"),
+    };
+
+    // `<name args...>` is the same type application as `(name args...)`
+    assert_eq!(arg_types("(defn (f ([s <ptr u8>] [o <Option <ptr (Pair u8 T)>>] [v <Pair <ptr u8> i32 >])))"),
+               arg_types("(defn (f ([s (ptr u8)] [o (Option (ptr (Pair u8 T)))] [v (Pair (ptr u8) i32)])))"));
+    assert_eq!(arg_types("(defn ((f T) ([p <ptr <ptr T>>])))"), arg_types("(defn ((f T) ([p (ptr (ptr T))])))"));
+    // `<` on its own is still a name
+    parse("(defn (f ([a i32]) bool) (< a 1))").unwrap();
+
+    assert_eq!(error("(defn (f ([s <ptr u8>>])))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f ([s <ptr u8 u8>])))"), ParserErrorKind::Type);
+    assert_eq!(error("(defn (f ([s <ptr u8])))"), ParserErrorKind::Token);
+
+    match &parse("(define (STDOUT i32) 1)
+(define (LIMIT <ptr u8>) STDOUT)
+(define N 2)").unwrap()[..] {
+        [Ast::Define { ty: a, .. }, Ast::Define { ty: b, .. }, Ast::Define { ty: c, .. }] => {
+            assert_eq!(*a, parser::Type::I32);
+            assert!(b.is_ptr());
+            assert_eq!(*c, parser::Type::I32);
+        }
+        ast => panic!("expected three definitions, found {:?}", ast),
+    }
+    assert_eq!(error("(define (N) 1)"), ParserErrorKind::Token);
+}
+
+#[test]
+fn enums() {
+    use parser::Pattern;
+
+    let input = "(enum (Option T) None (Some T))\n\
+                 (defn (f ([o (Option i32)]) i32) (match o ((Some (Pair x _)) x) (_ 0)))";
+    let ast = parse(input).unwrap();
+    match &ast[0] {
+        Ast::Enum { params, variants, .. } => {
+            assert_eq!(params.len(), 1);
+            assert!(variants[0].fields.is_empty());
+            assert!(variants[1].fields[0].is_var());
+        }
+        _ => panic!("expected an enum"),
+    }
+    match &ast[1] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Match { value, arms, .. } => {
+                assert!(value.is_identifier());
+                assert_eq!(arms.len(), 2);
+                match &arms[0].pattern {
+                    Pattern::Variant { fields, span, .. } => {
+                        assert_eq!(&input[span.start()..span.end()], "(Some (Pair x _))");
+                        match &fields[0] {
+                            Pattern::Variant { fields, .. } => {
+                                assert!(fields[0].is_binding());
+                                assert!(fields[1].is_wildcard());
+                            }
+                            _ => panic!("expected a nested pattern"),
+                        }
+                    }
+                    _ => panic!("expected a variant pattern"),
+                }
+                assert!(arms[1].pattern.is_wildcard());
+            }
+            _ => panic!("expected a match"),
+        },
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(enum E (1))"), ParserErrorKind::Token);
+    assert_eq!(error("(enum E [A i32])"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (match (g) (1 2)))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (match (g) ((Some x y 2))))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ()) (match))"), ParserErrorKind::Closer);
+}
+
+#[test]
+fn memory() {
+    let input = "(defn (f ([p (ptr u8)])) (set p[(g 1)][0] (deref (addr-of p.x))))";
+    let ast = parse(input).unwrap();
+    match &ast[0] {
+        Ast::Defn { body, .. } => {
+            match &body[0] {
+                Ast::Set { target, value, .. } => {
+                    match &**target {
+                        Ast::Subscript { value, index, span } => {
+                            assert_eq!(&input[span.start()..span.end()], "p[(g 1)][0]");
+                            assert!(value.is_subscript());
+                            assert!(index.is_primitive());
+                        }
+                        _ => panic!("expected a subscript"),
+                    }
+                    match &**value {
+                        Ast::Deref(pointer, _) => match &**pointer {
+                            Ast::AddrOf(place, _) => assert!(place.is_field()),
+                            _ => panic!("expected an addr-of"),
+                        },
+                        _ => panic!("expected a deref"),
+                    }
+                }
+                _ => panic!("expected a set"),
+            }
+        }
+        _ => panic!("expected a defn"),
+    }
+
+    // Brackets after a space aren't an index
+    assert_eq!(error("(defn (f ()) (g p [1]))"), ParserErrorKind::Bracket);
+    assert_eq!(error("(defn (f ()) (addr-of (g)))"), ParserErrorKind::Value);
+    assert_eq!(error("(defn (f ()) (set (deref p) 1 2))"), ParserErrorKind::Closer);
+    assert_eq!(error("(defn (f ()) (g p[1)))"), ParserErrorKind::Delimiter);
+}
+
+#[test]
+fn lambdas() {
+    use parser::Type;
+
+    let input = "(defn (f ([g (fn (i32 (ptr u8)) bool)] [h (fn ())]) (fn (i32) i32)) (lambda ([x i32]) (loop (break x))))";
+    let ast = parse(input).unwrap();
+    match &ast[0] {
+        Ast::Defn { args, ty, body, .. } => {
+            assert_eq!(args[0].ty, Type::Arrow(vec![Type::I32, Type::Ptr(Box::new(Type::U8))], Box::new(Type::Bool)));
+            assert_eq!(args[1].ty, Type::Arrow(vec![], Box::new(Type::Empty)));
+            assert_eq!(ty.arrow_split().1, Type::Arrow(vec![Type::I32], Box::new(Type::I32)));
+            match &body[0] {
+                Ast::Lambda { args, body, .. } => {
+                    assert_eq!(args.len(), 1);
+                    assert!(body[0].is_loop());
+                }
+                _ => panic!("expected a lambda"),
+            }
+        }
+        _ => panic!("expected a defn"),
+    }
+
+    // Anything that evaluates to a function can be called
+    let ast = parse("(defn (f ()) ((g 1) 2) ({begin g} 3) ((lambda ())))").unwrap();
+    match &ast[0] {
+        Ast::Defn { body, .. } => match (&body[0], &body[1], &body[2]) {
+            (Ast::Application(a, _), Ast::Application(b, _), Ast::Application(c, _)) => {
+                assert!(a[0].is_application());
+                assert!(b[0].is_block());
+                assert!(c[0].is_lambda());
+            }
+            _ => panic!("expected applications"),
+        },
+        _ => panic!("expected a defn"),
+    }
+
+    assert_eq!(error("(defn (f ()) (loop (lambda () (break))))"), ParserErrorKind::Loop);
+    assert_eq!(error("(defn (f ()) ((define x 1) 2))"), ParserErrorKind::Value);
+    assert_eq!(error("(defn (f ()) (lambda x x))"), ParserErrorKind::Token);
+    assert_eq!(error("(defn (f ([g (fn i32 i32)])))"), ParserErrorKind::Token);
+}
@@ -109,12 +109,16 @@ impl Assembler {
     r!(xor, 0x4, 0x00);
     r!(or, 0x6, 0x00);
     r!(and, 0x7, 0x00);
-    r!(sll, 0x7, 0x00);
+    r!(sll, 0x1, 0x00);
     r!(srl, 0x5, 0x00);
     r!(sra, 0x5, 0x20);
     r!(slt, 0x2, 0x00);
     r!(sltu, 0x3, 0x00);
     r!(mul, 0x0, 0x01);
+    r!(div, 0x4, 0x01);
+    r!(divu, 0x5, 0x01);
+    r!(rem, 0x6, 0x01);
+    r!(remu, 0x7, 0x01);
 
     #[inline]
     fn i(&mut self, rd: Register, rs: Register, funct3: u32, imm: i32, opcode: u32) {
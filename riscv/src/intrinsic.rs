@@ -0,0 +1,77 @@
+use {Assembler, Register};
+
+use parser::{IntrinsicOp, Type};
+
+impl Assembler {
+    /// Performs the intrinsic `op` on operands of type `ty` held in `rs1` and `rs2`, leaving the
+    /// result in `rd`. `rs2` is ignored by unary operations.
+    ///
+    /// Values are kept in registers sign or zero extended to 64 bits according to their type, so
+    /// results that can overflow a narrower type are extended again.
+    pub fn intrinsic(&mut self, op: IntrinsicOp, ty: &Type, rd: Register, rs1: Register, rs2: Register) {
+        if matches!(ty, Type::F32 | Type::F64) {
+            // Floats live in the registers of the F and D extensions
+            unimplemented!("float intrinsics");
+        }
+        let signed = matches!(ty, Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::Isize);
+        match op {
+            IntrinsicOp::Add => self.add(rd, rs1, rs2),
+            IntrinsicOp::Sub => self.sub(rd, rs1, rs2),
+            IntrinsicOp::Mul => self.mul(rd, rs1, rs2),
+            IntrinsicOp::Div if signed => self.div(rd, rs1, rs2),
+            IntrinsicOp::Div => self.divu(rd, rs1, rs2),
+            IntrinsicOp::Rem if signed => self.rem(rd, rs1, rs2),
+            IntrinsicOp::Rem => self.remu(rd, rs1, rs2),
+            IntrinsicOp::And => self.and(rd, rs1, rs2),
+            IntrinsicOp::Or => self.or(rd, rs1, rs2),
+            IntrinsicOp::Xor => self.xor(rd, rs1, rs2),
+            IntrinsicOp::Shl => self.sll(rd, rs1, rs2),
+            IntrinsicOp::Shr if signed => self.sra(rd, rs1, rs2),
+            IntrinsicOp::Shr => self.srl(rd, rs1, rs2),
+            // Bools are 0 or 1, so only the lowest bit is flipped
+            IntrinsicOp::Not if *ty == Type::Bool => self.xori(rd, rs1, 1),
+            IntrinsicOp::Not => self.xori(rd, rs1, -1),
+            IntrinsicOp::Eq => {
+                self.sub(rd, rs1, rs2);
+                self.sltiu(rd, rd, 1);
+            }
+            IntrinsicOp::Ne => {
+                self.sub(rd, rs1, rs2);
+                self.sltu(rd, Register::X0, rd);
+            }
+            IntrinsicOp::Lt => self.slt_signed(signed, rd, rs1, rs2),
+            IntrinsicOp::Gt => self.slt_signed(signed, rd, rs2, rs1),
+            IntrinsicOp::Le => {
+                self.slt_signed(signed, rd, rs2, rs1);
+                self.xori(rd, rd, 1);
+            }
+            IntrinsicOp::Ge => {
+                self.slt_signed(signed, rd, rs1, rs2);
+                self.xori(rd, rd, 1);
+            }
+        }
+
+        let bits = match ty {
+            Type::U8 | Type::I8 => 8,
+            Type::U16 | Type::I16 => 16,
+            Type::U32 | Type::I32 => 32,
+            _ => return,
+        };
+        if !op.comparisonp() {
+            self.slli(rd, rd, 64 - bits);
+            if signed {
+                self.srai(rd, rd, 64 - bits);
+            } else {
+                self.srli(rd, rd, 64 - bits);
+            }
+        }
+    }
+
+    fn slt_signed(&mut self, signed: bool, rd: Register, rs1: Register, rs2: Register) {
+        if signed {
+            self.slt(rd, rs1, rs2);
+        } else {
+            self.sltu(rd, rs1, rs2);
+        }
+    }
+}
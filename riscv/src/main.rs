@@ -1,5 +1,6 @@
 extern crate byteorder;
 extern crate elf;
+extern crate parser;
 extern crate tokenizer;
 
 #[macro_use]
@@ -7,26 +8,35 @@ mod macros;
 
 mod assembler;
 mod emitter;
+mod intrinsic;
 mod register;
 
 use emitter::Emitter;
 
-use tokenizer::Token;
+use tokenizer::{FileId, SourceMap, Token};
 
 use std::env;
 use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
+use std::process;
 
 pub use assembler::Assembler;
 pub use register::Register;
 
 fn main() {
     let input_file = env::args().nth(1).unwrap();
-    let input = fs::read_to_string(&input_file).unwrap();
-    let tokens = tokenizer::Tokenizer::tokenize(&input).unwrap();
-    let (program, data, rewrites) = Asm::new(tokens, &input);
+    let input = match fs::read_to_string(&input_file) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("error: unable to read {}: {}", input_file, e);
+            process::exit(1);
+        }
+    };
+    let mut sources = SourceMap::new();
+    let file = sources.add_file(input_file.clone(), input);
+    let (program, data, rewrites) = Asm::new(sources, file);
     let e = elf::Elf::new(elf::ISA::Riscv, program, data, rewrites);
     let mut output_file = input_file.split(".").next().unwrap();
     if output_file == input_file {
@@ -45,6 +55,7 @@ fn main() {
 
 struct Asm {
     asm: Assembler,
+    sources: SourceMap,
     data: Vec<Vec<u8>>,
     rewrites: HashMap<usize, usize>,
     constants: HashMap<String, i32>,
@@ -115,9 +126,10 @@ macro_rules! b {
 }
 
 impl Asm {
-    fn new(tokens: Vec<Token>, input: &str) -> (Vec<u8>, Vec<Vec<u8>>, HashMap<usize, usize>) {
+    fn new(sources: SourceMap, file: FileId) -> (Vec<u8>, Vec<Vec<u8>>, HashMap<usize, usize>) {
         let mut asm = Asm {
             asm: Assembler::new(),
+            sources,
             data: Vec::new(),
             rewrites: HashMap::new(),
             constants: HashMap::new(),
@@ -125,11 +137,25 @@ impl Asm {
             register_aliases: HashMap::new(),
         };
 
-        asm.assemble(tokens, input);
+        asm.assemble(file);
 
         (asm.asm.finish(), asm.data, asm.rewrites)
     }
-    fn assemble(&mut self, tokens: Vec<Token>, input: &str) {
+
+    fn assemble(&mut self, file: FileId) {
+        let tokens = match self.sources.tokenize(file) {
+            Ok(tokens) => tokens,
+            Err(errors) => {
+                for e in &errors {
+                    eprint!("{}", self.sources.diagnostic(e.index, &e.to_string()));
+                }
+                process::exit(1);
+            }
+        };
+        // The source map keeps ownership of the input, copy it out so `self` stays mutable.
+        let input = self.sources.source(file).to_string();
+        let input = &*input;
+
         let mut tokens = tokens.iter().filter(|t| !t.commentp());
         while let Some(t) = tokens.next() {
             match t {
@@ -144,22 +170,32 @@ impl Asm {
                         self.handle_opcode(s, &mut tokens, input);
                     }
                 } else {
-                    unreachable!();
+                    panic!("{}: expected an opcode", self.sources.token_location(*t));
                 },
-                _ => unreachable!(),
+                t => panic!("{}: unexpected token", self.sources.token_location(*t)),
             }
         }
     }
 
     fn handle_include<'b,  I: Iterator<Item = &'b Token>>(&mut self, tokens: &mut I, input: &str) {
-        let filename = tokens.next().unwrap();
-        assert!(filename.is_string());
-        let filename = filename.as_str(input);
+        let t = tokens.next().unwrap();
+        if !t.is_string() {
+            eprint!("{}", self.sources.diagnostic(t.index(), "expected the name of the file to include"));
+            process::exit(1);
+        }
+        let filename = t.as_str(input);
         let filename = &filename[1..filename.len()-1];
         assert!(tokens.next().unwrap().closerp());
-        let include_input = fs::read_to_string(filename).unwrap();
-        let include_tokens = tokenizer::Tokenizer::tokenize(&include_input).unwrap();
-        self.assemble(include_tokens, &include_input);
+        let include_input = match fs::read_to_string(filename) {
+            Ok(input) => input,
+            Err(e) => {
+                let message = format!("unable to include {}: {}", filename, e);
+                eprint!("{}", self.sources.diagnostic(t.index(), &message));
+                process::exit(1);
+            }
+        };
+        let file = self.sources.add_file(filename.into(), include_input);
+        self.assemble(file);
     }
 
     fn handle_define<'b,  I: Iterator<Item = &'b Token>>(&mut self, tokens: &mut I, input: &str) {
@@ -175,47 +211,15 @@ impl Asm {
                 self.register_aliases.insert(var, r);
             }
             s @ Token::Integer(_) => {
-                let i = s.as_str(input).parse().unwrap();
+                let i = self.read_integer(s, input);
                 self.constants.insert(var, i);
             }
             s @ Token::Char(_) => {
-                let s = s.as_str(input);
-                let s = &s[2..(s.len() - 1)].as_bytes();
-                let i = match s[0] {
-                    b'\\' => match s[1] {
-                        b'\\' | b'\'' => s[1] as i32,
-                        b'r' => b'\r' as i32,
-                        b'n' => b'\n' as i32,
-                        b't' => b'\t' as i32,
-                        b'0' => b'\0' as i32,
-                        _ => unreachable!(),
-                    },
-                    _ => s[0] as i32,
-                };
+                let i = self.read_char(s, input);
                 self.constants.insert(var, i);
             }
             s @ Token::String(_) => {
-                let s = s.as_str(input);
-                let s = s[1..s.len()-1].as_bytes();
-                let mut v = Vec::with_capacity(s.len());
-                let mut i = 0;
-                while i < s.len() {
-                    match s[i] {
-                        b'\\' => {
-                            v.push(match s[i+1] {
-                                b'"' | b'\\' => s[i+1],
-                                b'r' => b'\r',
-                                b'n' => b'\n',
-                                b't' => b'\t',
-                                b'0' => b'\0',
-                                _ => unreachable!(),
-                            });
-                            i += 1;
-                        }
-                        _ => v.push(s[i]),
-                    }
-                    i += 1;
-                }
+                let v = self.read_string(s, input);
                 self.globals.insert(var, (self.data.len(), v.len()));
                 self.data.push(v);
             }
@@ -225,8 +229,11 @@ impl Asm {
                 loop {
                     match tokens.next().unwrap() {
                         s @ Token::Integer(_) => {
-                            let i = s.as_str(input).parse().unwrap();
-                            v.push(i);
+                            let i = s.integer(input).unwrap();
+                            if !i.fits(i8::MIN as i128, u8::MAX as i128) {
+                                panic!("{}: byte out of range", self.sources.token_location(*s));
+                            }
+                            v.push(i.value as u8);
                         }
                         t if t.closerp() => break,
                         t if t.commentp() => continue,
@@ -288,7 +295,7 @@ impl Asm {
 
                         if let Some(r) = r {
                             match tokens.next().unwrap() {
-                                i @ Token::Integer(_) => (r, i.as_str(input).parse().unwrap()),
+                                i @ Token::Integer(_) => (r, self.read_integer(i, input)),
                                 i @ Token::Symbol(_) => (r, *self.constants.get(i.as_str(input)).unwrap()),
                                 _ => unreachable!(),
                             }
@@ -296,7 +303,7 @@ impl Asm {
                             (self.unwrap_register(tokens, input), i.unwrap())
                         }
                     }
-                    i @ Token::Integer(_) => (self.unwrap_register(tokens, input), i.as_str(input).parse().unwrap()),
+                    i @ Token::Integer(_) => (self.unwrap_register(tokens, input), self.read_integer(i, input)),
                     _ => unreachable!(),
                 };
                 assert!(tokens.next().unwrap().closerp());
@@ -312,6 +319,30 @@ impl Asm {
     }
 
 
+    /// Decodes an integer token. Values up to `u32::MAX` are accepted and reinterpreted as signed
+    /// so that masks such as `0xffff_f000` can be written naturally.
+    fn read_integer(&self, t: &Token, input: &str) -> i32 {
+        let i = t.integer(input).unwrap();
+        if !i.fits(i32::MIN as i128, u32::MAX as i128) {
+            panic!("{}: integer does not fit in 32 bits", self.sources.token_location(*t));
+        }
+        i.value as i32
+    }
+
+    fn read_string(&self, t: &Token, input: &str) -> Vec<u8> {
+        match t.string_bytes(input) {
+            Ok(v) => v,
+            Err(e) => panic!("{}: {}", self.sources.location(e.index), e),
+        }
+    }
+
+    fn read_char(&self, t: &Token, input: &str) -> i32 {
+        match t.char_value(input) {
+            Ok(c) => c as i32,
+            Err(e) => panic!("{}: {}", self.sources.location(e.index), e),
+        }
+    }
+
     fn read_imm<'b, I: Iterator<Item = &'b Token>>(&self, tokens: &mut I, input: &str) -> i32 {
         match tokens.next().unwrap() {
             t if t.openerp() => {
@@ -324,46 +355,14 @@ impl Asm {
                         let s = s.as_str(input);
                         self.globals.get(s).unwrap().1
                     },
-                    s @ Token::String(_) => {
-                        let s = s.as_str(input).as_bytes();
-                        let mut j = 0;
-                        let mut i = 1;
-                        while i < s.len()-1 {
-                            match s[i] {
-                                b'\\' => match s[i+1] {
-                                    b'"' | b'\\' | b'r' | b'n' | b't' | b'0' => {
-                                        j += 1;
-                                        i += 1;
-                                    }
-                                    _ => unreachable!(),
-                                },
-                                _ => j += 1,
-                            }
-                            i += 1;
-                        }
-                        j
-                    },
+                    s @ Token::String(_) => self.read_string(s, input).len(),
                     _ => unreachable!(),
                 };
                 assert!(tokens.next().unwrap().closerp());
                 i as i32
             }
-            s @ Token::Integer(_) => s.as_str(input).parse::<i32>().unwrap(),
-            s @ Token::Char(_) => {
-                let s = s.as_str(input);
-                let s = &s[2..(s.len() - 1)].as_bytes();
-                match s[0] {
-                    b'\\' => match s[1] {
-                        b'"' | b'\\' | b'\'' => s[1] as i32,
-                        b'r' => b'\r' as i32,
-                        b'n' => b'\n' as i32,
-                        b't' => b'\t' as i32,
-                        b'0' => b'\0' as i32,
-                        _ => unreachable!(),
-                    },
-                    _ => s[0] as i32,
-                }
-            }
+            s @ Token::Integer(_) => self.read_integer(s, input),
+            s @ Token::Char(_) => self.read_char(s, input),
             s @ Token::Symbol(_) => {
                 let s = s.as_str(input);
                 *self.constants.get(s).unwrap()
@@ -385,6 +384,10 @@ impl Asm {
             "slt" => r!(slt, self, tokens, input),
             "sltu" => r!(sltu, self, tokens, input),
             "mul" => r!(mul, self, tokens, input),
+            "div" => r!(div, self, tokens, input),
+            "divu" => r!(divu, self, tokens, input),
+            "rem" => r!(rem, self, tokens, input),
+            "remu" => r!(remu, self, tokens, input),
 
             "addi" => i!(addi, self, tokens, input),
             "subi" => i!(subi, self, tokens, input),
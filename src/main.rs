@@ -5,6 +5,12 @@ extern crate string_interner;
 extern crate tokenizer;
 extern crate type_checker;
 
+use asm_syntax::{Constant, Immediate};
+use tokenizer::{Index, IntegerLiteral, SourceMap};
+
+use std::path::Path;
+use std::process;
+
 fn main() {
     /*
     let tokens = tokenizer::Tokenizer::tokenize(input).unwrap();
@@ -83,16 +89,41 @@ fn main() {
         (#asm (mov rax (i32 0))
               (syscall)))
     "#;
-    let tokens = tokenizer::Tokenizer::tokenize(input).unwrap();
-    let ast = parser::parse(tokens, input).unwrap();
-    type_checker::type_check(&ast).unwrap();
-    compile(ast, input);
+    let mut sources = SourceMap::new();
+    let file = sources.add_file("<input>".into(), input.into());
+    // The standard library is the one next to the compiler's sources, wherever it is run from
+    let search_paths = [Path::new(env!("CARGO_MANIFEST_DIR")).join("libs")];
+    let ast = match parser::load(file, &mut sources, &search_paths) {
+        Ok(ast) => ast,
+        Err(errors) => {
+            for e in errors {
+                eprint!("{}", sources.diagnostic(e.span(), &e.to_string()));
+            }
+            process::exit(1);
+        }
+    };
+    // Diagnostics for code produced by macros are followed by the uses of the macros
+    let ast = match parser::expand(ast, &mut sources) {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprint!("{}", sources.diagnostic(e.span, &e.to_string()));
+            process::exit(1);
+        }
+    };
+    if let Err(e) = type_checker::type_check(&ast) {
+        match e.span {
+            Some(span) => eprint!("{}", sources.diagnostic(span, &e.to_string())),
+            None => eprintln!("error: {}", e),
+        }
+        process::exit(1);
+    }
+    compile(ast, input, &sources);
     //let instructions = compile(ast);
     //let code = amd64::assemble(instructions).unwrap();
     println!("Ok");
 }
 
-fn compile(mut ast: Vec<Ast>, input: &str) {
+fn compile(mut ast: Vec<Ast>, input: &str, sources: &SourceMap) {
     // If we do this we cover definitions of constants prior to compiling procedures
     ast.sort_unstable_by(|a, b|
         if a.is_define() {
@@ -103,12 +134,11 @@ fn compile(mut ast: Vec<Ast>, input: &str) {
 
     for a in ast {
         match a {
-            Ast::Include(_) => todo!(),
             Ast::Define { name, value, .. } => match *value {
-                Ast::Primitive(p) => {
+                Ast::Primitive(p, span) => {
                     ir.push(Lir::Constant(name, match p {
                         CompilePrimitive::Bool(b) => Primitive::Bool(b),
-                        CompilePrimitive::Integer(i) => Primitive::I32(i),
+                        CompilePrimitive::Integer(i) => Primitive::Integer(immediate(i, Constant::I32, span, sources)),
                         CompilePrimitive::String(s) => Primitive::String(s),
                     }));
                 }
@@ -128,6 +158,15 @@ fn compile_defn(name: Symbol, args: Vec<Arg>, body: Vec<Ast>, input: &str) -> Li
     Lir::Fun(name, _)
 }
 
+/// Immediate of the width the suffix of the literal `i` names, or `default` if it has none. Exits
+/// with a diagnostic if the value doesn't fit rather than truncating it.
+fn immediate(i: IntegerLiteral, default: Constant, span: Index, sources: &SourceMap) -> Immediate {
+    Immediate::from_integer(i, default).unwrap_or_else(|| {
+        eprint!("{}", sources.diagnostic(span, "Integer literal does not fit in its type"));
+        process::exit(1)
+    })
+}
+
 /*
 use asm_syntax::{Immediate, Instruction, Operand};
 use parser::{Arg, Ast, CompilePrimitive};
@@ -135,7 +174,7 @@ use string_interner::{get_symbol, Symbol};
 
 use std::collections::HashMap;
 
-fn compile(mut ast: Vec<Ast>, input: &str) {
+fn compile(mut ast: Vec<Ast>, input: &str, sources: &SourceMap) {
     //let mut instructions = Vec::new();
     let mut constants = HashMap::new();
     let mut code = HashMap::new();
@@ -155,7 +194,7 @@ fn compile(mut ast: Vec<Ast>, input: &str) {
                 Ast::Primitive(_) | Ast::Identifier(_) => { constants.insert(name, *value); }
                 _ => unimplemented!(),
             },
-            Ast::Defn { name, args, body, .. } => compile_defn(name, args, body, input, &mut constants, &mut code),
+            Ast::Defn { name, args, body, .. } => compile_defn(name, args, body, input, sources, &mut constants, &mut code),
             _ => unreachable!(),
         }
     }
@@ -163,7 +202,7 @@ fn compile(mut ast: Vec<Ast>, input: &str) {
     //instructions
 }
 
-fn compile_defn(name: Symbol, args: Vec<Arg>, body: Vec<Ast>, input: &str,
+fn compile_defn(name: Symbol, args: Vec<Arg>, body: Vec<Ast>, input: &str, sources: &SourceMap,
                 constants: &mut HashMap<Symbol, Ast>,
                 code: &mut HashMap<Symbol, Vec<Instruction>>)
 {
@@ -211,12 +250,11 @@ fn compile_defn(name: Symbol, args: Vec<Arg>, body: Vec<Ast>, input: &str,
                             }
 
                         }
-                        Ast::Primitive(p) => match p {
+                        Ast::Primitive(p, span) => match p {
                             CompilePrimitive::Integer(n) =>
                                 instructions.push(Instruction::new(get_symbol("mov"),
                                     vec![Operand::Register(get_register(i)),
-                                    // TODO: have other types
-                                         Operand::Constant(Immediate::U64(*n as u64))])),
+                                         Operand::Constant(immediate(*n, Constant::U64, *span, sources))])),
                             CompilePrimitive::Bool(b) =>
                                 instructions.push(Instruction::new(get_symbol("mov"),
                                     vec![Operand::Register(get_register(i)),
@@ -1,16 +1,60 @@
+use super::Index;
+
 use std::fmt::{self, Display, Formatter};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub enum TokenizeError {
-    Char,
-    EOF,
+pub struct TokenizeError {
+    pub kind: TokenizeErrorKind,
+    /// Span of the offending input
+    pub index: Index,
+}
+
+impl TokenizeError {
+    pub fn new(kind: TokenizeErrorKind, index: Index) -> Self {
+        TokenizeError {
+            kind,
+            index,
+        }
+    }
 }
 
 impl Display for TokenizeError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            TokenizeError::Char => write!(f, "Invalid character in char"),
-            TokenizeError::EOF => write!(f, "Unexpected end of input"),
-        }
+        self.kind.fmt(f)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TokenizeErrorKind {
+    /// Char literal that is empty or holds more than one character
+    InvalidChar,
+    UnterminatedChar,
+    UnterminatedString,
+    UnterminatedBlockComment,
+    /// `|symbol` without a closing bar
+    UnterminatedSymbol,
+    BadEscape,
+    /// `|#` outside of a block comment
+    StrayDelimiter,
+    /// Integer literal that doesn't fit in its type
+    IntegerRange,
+    /// Input ended directly after a `\`
+    EOF,
+}
+
+impl Display for TokenizeErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use self::TokenizeErrorKind::*;
+        f.write_str(match self {
+            InvalidChar => "Char literal must contain exactly one character",
+            UnterminatedChar => "Unterminated char literal",
+            UnterminatedString => "Unterminated string literal",
+            UnterminatedBlockComment => "Unterminated block comment",
+            UnterminatedSymbol => "Unterminated `|` in symbol",
+            BadEscape => "Unknown escape sequence",
+            StrayDelimiter => "`|#` without a matching `#|`",
+            IntegerRange => "Integer literal is out of range for its type",
+            EOF => "Unexpected end of input",
+        })
     }
 }
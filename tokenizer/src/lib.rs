@@ -2,9 +2,17 @@
 extern crate derive_is_enum_variant;
 
 mod error;
+mod literal;
+mod number;
+mod source_map;
 mod token;
 mod tokenizer;
+mod trivia;
 
-pub use self::error::TokenizeError;
+pub use self::error::{TokenizeError, TokenizeErrorKind};
+pub use self::literal::{decode_char, decode_string};
+pub use self::number::{IntSuffix, IntegerLiteral};
+pub use self::source_map::{ExpansionId, FileId, Location, SourceFile, SourceMap};
 pub use self::token::{Index, Token};
 pub use self::tokenizer::Tokenizer;
+pub use self::trivia::{LosslessToken, LosslessTokens, Trivia};
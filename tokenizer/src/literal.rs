@@ -0,0 +1,130 @@
+use super::{Index, Token, TokenizeError, TokenizeErrorKind};
+
+use std::str::CharIndices;
+use std::iter::Peekable;
+
+/// A single decoded element of a string or char literal
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Unit {
+    /// Raw byte from a `\xNN` escape
+    Byte(u8),
+    Char(char),
+}
+
+/// Decodes the contents of a `Token::String` into bytes. Characters are UTF-8 encoded.
+pub fn decode_string(token: Token, input: &str) -> Result<Vec<u8>, TokenizeError> {
+    let mut bytes = Vec::new();
+    for unit in decode(token, input)? {
+        match unit {
+            Unit::Byte(b) => bytes.push(b),
+            Unit::Char(c) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decodes a `Token::Char` to its value, either a unicode scalar value or the byte given by a
+/// `\xNN` escape.
+pub fn decode_char(token: Token, input: &str) -> Result<u32, TokenizeError> {
+    let units = decode(token, input)?;
+    if units.len() != 1 {
+        return Err(TokenizeError::new(TokenizeErrorKind::InvalidChar, token.index()));
+    }
+
+    Ok(match units[0] {
+        Unit::Byte(b) => b as u32,
+        Unit::Char(c) => c as u32,
+    })
+}
+
+/// Decodes the body of a string or char literal, i.e. everything between the quotes.
+fn decode(token: Token, input: &str) -> Result<Vec<Unit>, TokenizeError> {
+    let index = token.index();
+    // Skip over `"` for strings and `#'` for chars
+    let prefix = if token.is_char() { 2 } else { 1 };
+    let start = index.start() + prefix;
+    let body = &input[start..index.end() - 1];
+
+    let mut units = Vec::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            units.push(Unit::Char(c));
+            continue;
+        }
+
+        let unit = match chars.next() {
+            Some((_, 'n')) => Some(Unit::Char('\n')),
+            Some((_, 't')) => Some(Unit::Char('\t')),
+            Some((_, 'r')) => Some(Unit::Char('\r')),
+            Some((_, '0')) => Some(Unit::Char('\0')),
+            Some((_, c @ '\\')) | Some((_, c @ '"')) | Some((_, c @ '\'')) => Some(Unit::Char(c)),
+            Some((_, 'x')) => hex_escape(&mut chars).map(Unit::Byte),
+            Some((_, 'u')) => unicode_escape(&mut chars).map(Unit::Char),
+            // A `\` at the end of a line joins it with the next, skipping leading whitespace.
+            Some((_, '\n')) => {
+                skip_whitespace(&mut chars);
+                continue;
+            }
+            Some((_, '\r')) if chars.peek().map(|&(_, c)| c) == Some('\n') => {
+                skip_whitespace(&mut chars);
+                continue;
+            }
+            _ => None,
+        };
+
+        match unit {
+            Some(unit) => units.push(unit),
+            None => {
+                let end = chars.peek().map_or(body.len(), |&(j, _)| j);
+                let index = Index::new(index.file(), start + i + 1, start + end);
+                return Err(TokenizeError::new(TokenizeErrorKind::BadEscape, index));
+            }
+        }
+    }
+
+    Ok(units)
+}
+
+/// Reads the two hex digits of a `\xNN` escape.
+fn hex_escape(chars: &mut Peekable<CharIndices>) -> Option<u8> {
+    let hi = chars.next()?.1.to_digit(16)?;
+    let lo = chars.next()?.1.to_digit(16)?;
+    Some((hi * 16 + lo) as u8)
+}
+
+/// Reads the `{...}` of a `\u{...}` escape, which holds 1 to 6 hex digits.
+fn unicode_escape(chars: &mut Peekable<CharIndices>) -> Option<char> {
+    if chars.next()?.1 != '{' {
+        return None;
+    }
+
+    let mut value = 0;
+    let mut digits = 0;
+    loop {
+        match chars.next()?.1 {
+            '}' if digits > 0 => break,
+            c => {
+                value = value * 16 + c.to_digit(16)?;
+                digits += 1;
+                if digits > 6 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    std::char::from_u32(value)
+}
+
+fn skip_whitespace(chars: &mut Peekable<CharIndices>) {
+    while let Some(&(_, c)) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
@@ -0,0 +1,173 @@
+/// Type suffix of an integer literal, e.g. the `u8` in `42u8`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IntSuffix {
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+}
+
+impl IntSuffix {
+    pub fn from_str(input: &str) -> Option<Self> {
+        use self::IntSuffix::*;
+        Some(match input {
+            "u8" => U8,
+            "u16" => U16,
+            "u32" => U32,
+            "u64" => U64,
+            "usize" => Usize,
+            "i8" => I8,
+            "i16" => I16,
+            "i32" => I32,
+            "i64" => I64,
+            "isize" => Isize,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        use self::IntSuffix::*;
+        match self {
+            U8 => "u8",
+            U16 => "u16",
+            U32 => "u32",
+            U64 => "u64",
+            Usize => "usize",
+            I8 => "i8",
+            I16 => "i16",
+            I32 => "i32",
+            I64 => "i64",
+            Isize => "isize",
+        }
+    }
+
+    pub fn signedp(self) -> bool {
+        use self::IntSuffix::*;
+        match self {
+            I8 | I16 | I32 | I64 | Isize => true,
+            _ => false,
+        }
+    }
+
+    /// Smallest value representable by the suffixed type. Pointer sized types are 64 bits.
+    pub fn min(self) -> i128 {
+        use self::IntSuffix::*;
+        match self {
+            U8 | U16 | U32 | U64 | Usize => 0,
+            I8 => i8::MIN as i128,
+            I16 => i16::MIN as i128,
+            I32 => i32::MIN as i128,
+            I64 | Isize => i64::MIN as i128,
+        }
+    }
+
+    /// Largest value representable by the suffixed type. Pointer sized types are 64 bits.
+    pub fn max(self) -> i128 {
+        use self::IntSuffix::*;
+        match self {
+            U8 => u8::MAX as i128,
+            U16 => u16::MAX as i128,
+            U32 => u32::MAX as i128,
+            U64 | Usize => u64::MAX as i128,
+            I8 => i8::MAX as i128,
+            I16 => i16::MAX as i128,
+            I32 => i32::MAX as i128,
+            I64 | Isize => i64::MAX as i128,
+        }
+    }
+}
+
+/// Decoded value of an integer token.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IntegerLiteral {
+    pub value: i128,
+    pub suffix: Option<IntSuffix>,
+}
+
+impl IntegerLiteral {
+    /// Decodes integer literal syntax: an optional sign, an optional `0x`, `0b` or `0o` radix
+    /// prefix, digits which may be separated by `_`, and an optional type suffix.
+    ///
+    /// Returns `None` if `input` is not an integer literal. The value is not range checked, see
+    /// `in_range`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (negative, input) = match input.as_bytes().first() {
+            Some(b'-') => (true, &input[1..]),
+            Some(b'+') => (false, &input[1..]),
+            _ => (false, input),
+        };
+
+        let (radix, input) = if input.starts_with("0x") {
+            (16, &input[2..])
+        } else if input.starts_with("0b") {
+            (2, &input[2..])
+        } else if input.starts_with("0o") {
+            (8, &input[2..])
+        } else {
+            (10, input)
+        };
+
+        let mut value: u128 = 0;
+        let mut digits = 0;
+        let mut overflow = false;
+        let mut end = input.len();
+        for (i, c) in input.char_indices() {
+            if c == '_' && digits > 0 {
+                continue;
+            }
+
+            if let Some(d) = c.to_digit(radix) {
+                digits += 1;
+                match value.checked_mul(radix as u128).and_then(|v| v.checked_add(d as u128)) {
+                    Some(v) => value = v,
+                    None => overflow = true,
+                }
+            } else {
+                end = i;
+                break;
+            }
+        }
+
+        if digits == 0 {
+            return None;
+        }
+
+        let suffix = if end == input.len() {
+            None
+        } else {
+            Some(IntSuffix::from_str(&input[end..])?)
+        };
+
+        // Anything too large for an i128 is out of range for every type, so we clamp it.
+        let value = if overflow || value > i128::MAX as u128 {
+            i128::MAX
+        } else {
+            value as i128
+        };
+
+        Some(IntegerLiteral {
+            value: if negative { -value } else { value },
+            suffix,
+        })
+    }
+
+    /// Checks that the value fits in its suffixed type. Unsuffixed literals may hold any value
+    /// that fits in either an `i64` or a `u64`.
+    pub fn in_range(&self) -> bool {
+        match self.suffix {
+            Some(s) => s.min() <= self.value && self.value <= s.max(),
+            None => i64::MIN as i128 <= self.value && self.value <= u64::MAX as i128,
+        }
+    }
+
+    /// Checks that the value fits in the range `min..=max`.
+    pub fn fits(&self, min: i128, max: i128) -> bool {
+        min <= self.value && self.value <= max
+    }
+}
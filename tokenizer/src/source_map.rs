@@ -0,0 +1,214 @@
+use super::{Index, Token, Tokenizer, TokenizeError};
+
+use std::fmt::{self, Display, Formatter};
+
+/// Identifies a file registered with a `SourceMap`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FileId(usize);
+
+impl FileId {
+    pub fn new(id: usize) -> Self {
+        FileId(id)
+    }
+
+    pub fn id(self) -> usize {
+        self.0
+    }
+}
+
+/// Identifies a macro expansion registered with a `SourceMap`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ExpansionId(usize);
+
+pub struct SourceFile {
+    name: String,
+    source: String,
+    /// Byte offset of the beginning of every line in `source`
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(name: String, source: String) -> Self {
+        let mut line_starts = vec![0];
+        for (i, b) in source.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+
+        SourceFile {
+            name,
+            source,
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the zero based line containing the byte offset `pos`.
+    fn line_index(&self, pos: usize) -> usize {
+        match self.line_starts.binary_search(&pos) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    /// Returns the text of the zero based line `line`, without the trailing newline.
+    fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line];
+        let end = self.line_starts.get(line + 1).map_or(self.source.len(), |&e| e);
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// A resolved position within a source file. Lines and columns start at 1, columns are counted in
+/// characters rather than bytes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    pub column: usize,
+    /// The full text of the line the position is on
+    pub line_text: &'a str,
+}
+
+impl<'a> Display for Location<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Keeps track of every file that makes up a program so that `Index`es can be turned back into
+/// file, line and column information for error messages. It also records the macro expansions
+/// that produced code, so that errors in that code can say which uses of macros it came from.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    /// Name of the macro and its use for every expansion
+    expansions: Vec<(String, Index)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_file(&mut self, name: String, source: String) -> FileId {
+        self.files.push(SourceFile::new(name, source));
+        FileId(self.files.len() - 1)
+    }
+
+    /// Returns the id of the file registered under `name`, if any.
+    pub fn find_file(&self, name: &str) -> Option<FileId> {
+        self.files.iter().position(|f| f.name == name).map(FileId)
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0]
+    }
+
+    pub fn name(&self, id: FileId) -> &str {
+        self.file(id).name()
+    }
+
+    pub fn source(&self, id: FileId) -> &str {
+        self.file(id).source()
+    }
+
+    /// Registers the expansion of the macro `name` used at `span`. Spans of the code it produces
+    /// are marked with the returned id through `Index::expanded`.
+    pub fn add_expansion(&mut self, name: String, span: Index) -> ExpansionId {
+        self.expansions.push((name, span));
+        ExpansionId(self.expansions.len() - 1)
+    }
+
+    /// Names and uses of the macros whose expansions produced `index`, innermost first.
+    pub fn expansions(&self, index: Index) -> Vec<(&str, Index)> {
+        let mut trace = Vec::new();
+        let mut expansion = index.expansion();
+        while let Some(ExpansionId(id)) = expansion {
+            let (ref name, span) = self.expansions[id];
+            trace.push((name.as_str(), span));
+            expansion = span.expansion();
+        }
+        trace
+    }
+
+    pub fn tokenize(&self, id: FileId) -> Result<Vec<Token>, Vec<TokenizeError>> {
+        Tokenizer::tokenize_file(self.source(id), id)
+    }
+
+    /// Resolves the start of `index` to a file, line and column.
+    pub fn location(&self, index: Index) -> Location<'_> {
+        self.location_at(index.file(), index.start())
+    }
+
+    /// Resolves the end of `index` to a file, line and column.
+    pub fn end_location(&self, index: Index) -> Location<'_> {
+        self.location_at(index.file(), index.end())
+    }
+
+    /// Resolves the byte offset `pos` in `file` to a line and column.
+    pub fn location_at(&self, file: FileId, pos: usize) -> Location<'_> {
+        let file = self.file(file);
+        let pos = pos.min(file.source.len());
+        let line = file.line_index(pos);
+        let column = file.source[file.line_starts[line]..pos].chars().count() + 1;
+
+        Location {
+            file: &file.name,
+            line: line + 1,
+            column,
+            line_text: file.line_text(line),
+        }
+    }
+
+    pub fn token_location(&self, token: Token) -> Location<'_> {
+        self.location(token.index())
+    }
+
+    /// Formats `message` as an error pointing at `index`, showing the offending line with the
+    /// span underlined. If the code at `index` was produced by macros, a note follows for every
+    /// use of a macro that led to it.
+    pub fn diagnostic(&self, index: Index, message: &str) -> String {
+        let mut diagnostic = self.render("error", index, message);
+        for (name, span) in self.expansions(index) {
+            diagnostic += &self.note(span, &format!("in expansion of `{}`", name));
+        }
+        diagnostic
+    }
+
+    /// Same as `diagnostic`, for notes that give context to an error
+    pub fn note(&self, index: Index, message: &str) -> String {
+        self.render("note", index, message)
+    }
+
+    fn render(&self, level: &str, index: Index, message: &str) -> String {
+        let start = self.location(index);
+        let end = self.end_location(index);
+        // Spans covering several lines are only underlined to the end of their first line
+        let width = if start.line == end.line && end.column > start.column {
+            end.column - start.column
+        } else {
+            1.max(start.line_text.chars().count() + 1 - start.column)
+        };
+        let gutter = " ".repeat(start.line.to_string().len());
+
+        format!("{}: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}{}\n",
+                level, message,
+                gutter, start,
+                gutter,
+                start.line, start.line_text,
+                gutter, " ".repeat(start.column - 1), "^".repeat(width))
+    }
+
+    pub fn as_str(&self, index: Index) -> &str {
+        &self.source(index.file())[index.start()..index.end()]
+    }
+}
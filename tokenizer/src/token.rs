@@ -1,3 +1,5 @@
+use super::{literal, ExpansionId, FileId, IntegerLiteral, TokenizeError};
+
 #[derive(Copy, Clone, Debug, PartialEq, is_enum_variant)]
 pub enum Token {
     /// (
@@ -79,10 +81,28 @@ impl Token {
     }
 
     pub fn as_str<'a>(&self, input: &'a str) -> &'a str {
-        let Index { start, end } = self.index();
+        let Index { start, end, .. } = self.index();
         &input[start..end]
     }
 
+    /// Decodes a `String` token to bytes, see `decode_string`.
+    pub fn string_bytes(&self, input: &str) -> Result<Vec<u8>, TokenizeError> {
+        literal::decode_string(*self, input)
+    }
+
+    /// Decodes the value of a `Char` token, see `decode_char`.
+    pub fn char_value(&self, input: &str) -> Result<u32, TokenizeError> {
+        literal::decode_char(*self, input)
+    }
+
+    /// Decodes the value of an `Integer` token. Returns `None` for any other token.
+    pub fn integer(&self, input: &str) -> Option<IntegerLiteral> {
+        match self {
+            Token::Integer(_) => IntegerLiteral::parse(self.as_str(input)),
+            _ => None,
+        }
+    }
+
     // TODO: not sure this is the right place for this
     /*
     pub fn as_symbol(&self, input: &str, symbols: &mut DefaultStringInterner) -> Sym {
@@ -109,17 +129,55 @@ impl Token {
     */
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+/// Byte span of a token within the file identified by `file`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Index {
+    file: FileId,
     start: usize,
     end: usize,
+    /// Macro expansion the code was produced by, if any
+    expansion: Option<ExpansionId>,
 }
 
 impl Index {
-    pub fn new(start: usize, end: usize) -> Self {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
         Index {
+            file,
             start: start - 1,
             end,
+            expansion: None,
+        }
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn expansion(&self) -> Option<ExpansionId> {
+        self.expansion
+    }
+
+    /// Returns the same span, marked as produced by the macro expansion `expansion`.
+    pub fn expanded(&self, expansion: ExpansionId) -> Index {
+        Index {
+            expansion: Some(expansion),
+            ..*self
+        }
+    }
+
+    /// Returns the span from the start of `self` to the end of `other`.
+    pub fn to(&self, other: Index) -> Index {
+        Index {
+            end: other.end,
+            ..*self
         }
     }
 }
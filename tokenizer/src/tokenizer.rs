@@ -1,40 +1,60 @@
-use super::{Index, TokenizeError, Token};
+use super::{literal, FileId, Index, IntegerLiteral, TokenizeError, TokenizeErrorKind, Token};
 use super::Token as T;
+use super::TokenizeErrorKind as E;
 
 use std::iter::Peekable;
 use std::str::Chars;
 
-type TokenizeResult = Result<(), TokenizeError>;
-
 // TODO: consider implementing this as an iterator
 // This ought to be more efficient, especially if we use a buffered reader as well, but I'm not
 // sure how we can handle errors if we take this approach. We might have the Item be
 // Result<Token, TokenizeError>.
 pub struct Tokenizer<'a> {
+    file: FileId,
     position: usize,
     raw_input: &'a str,
     input: Peekable<Chars<'a>>,
     tokens: Vec<Token>,
+    errors: Vec<TokenizeError>,
 }
 
 macro_rules! pt {
     ($ty:expr, $s:ident) => {
-        $s.tokens.push($ty(Index::new($s.position, $s.position)))
+        $s.tokens.push($ty(Index::new($s.file, $s.position, $s.position)))
     };
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn tokenize(raw_input: &'a str) -> Result<Vec<Token>, TokenizeError> {
+    pub fn tokenize(raw_input: &'a str) -> Result<Vec<Token>, Vec<TokenizeError>> {
+        Tokenizer::tokenize_file(raw_input, FileId::default())
+    }
+
+    /// Tokenizes `raw_input`, tagging every token with `file` so that it can be located through a
+    /// `SourceMap`. Returns every error found in the input if there are any.
+    pub fn tokenize_file(raw_input: &'a str, file: FileId) -> Result<Vec<Token>, Vec<TokenizeError>> {
+        let (tokens, errors) = Tokenizer::tokenize_partial(raw_input, file);
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Tokenizes as much of `raw_input` as possible. Malformed tokens are skipped and reported,
+    /// after which tokenizing resumes at the next delimiter.
+    pub fn tokenize_partial(raw_input: &'a str, file: FileId) -> (Vec<Token>, Vec<TokenizeError>) {
         let input = raw_input.chars().peekable();
         let mut tokenizer = Tokenizer {
+            file,
             position: 0,
-            raw_input: raw_input,
-            input: input,
+            raw_input,
+            input,
             tokens: Vec::new(),
+            errors: Vec::new(),
         };
-        tokenizer._tokenize()?;
+        tokenizer._tokenize();
 
-        Ok(tokenizer.tokens)
+        (tokenizer.tokens, tokenizer.errors)
     }
 
     fn next(&mut self) -> Option<char> {
@@ -56,7 +76,12 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    fn _tokenize(&mut self) -> TokenizeResult {
+    /// Records an error spanning from `start` to the current position.
+    fn error(&mut self, kind: TokenizeErrorKind, start: usize) {
+        self.errors.push(TokenizeError::new(kind, Index::new(self.file, start, self.position)));
+    }
+
+    fn _tokenize(&mut self) {
         while let Some(c) = self.next() {
             match c {
                 '(' => pt!(T::LParen, self),
@@ -65,193 +90,209 @@ impl<'a> Tokenizer<'a> {
                 '}' => pt!(T::RBrace, self),
                 '[' => pt!(T::LSBracket, self),
                 ']' => pt!(T::RSBracket, self),
-                '"' => self.parse_string()?,
-                '|' => self.parse_identifier(self.position, true)?,
-                ';' => self.parse_comment()?,
+                '"' => self.parse_string(),
+                '|' if self.peek() == Some('#') => {
+                    let start = self.position;
+                    self.next();
+                    self.error(E::StrayDelimiter, start);
+                }
+                '|' => self.parse_identifier(self.position, true),
+                ';' => self.parse_comment(),
                 '#' => match self.peek() {
-                    Some('|') => self.parse_block_comment()?,
-                    Some('\'') => self.parse_char()?,
+                    Some('|') => self.parse_block_comment(),
+                    Some('\'') => self.parse_char(),
                     _ => pt!(T::Pound, self),
                 },
                 c if c.is_whitespace() => {}
-                '.' => self.parse_ambiguous()?,
-                '0' ..= '9' | '+' | '-' | 'i' | 'n' | 'f' | 'N' | 'a' => self.parse_ambiguous()?,
+                '.' => self.parse_ambiguous(),
+                '0' ..= '9' | '+' | '-' | 'i' | 'n' | 'f' | 'N' | 'a' => self.parse_ambiguous(),
                 _ => {
-                    let start = self.position;
+                    // `start` is one past the beginning of the token, which may not be true for
+                    // multibyte characters.
+                    let start = self.position - c.len_utf8() + 1;
                     if '\\' == c && self.next().is_none() {
-                        return Err(TokenizeError::EOF);
+                        return self.error(E::EOF, start);
                     }
-                    self.parse_identifier(start, false)?;
+                    self.parse_identifier(start, false);
                 }
             }
         }
-        Ok(())
     }
 
-    fn parse_ambiguous(&mut self) -> TokenizeResult {
+    /// Handles the delimiter `c` which ended the previous token.
+    fn delimiter(&mut self, c: char) {
+        match c {
+            '(' => pt!(T::LParen, self),
+            ')' => pt!(T::RParen, self),
+            '{' => pt!(T::LBrace, self),
+            '}' => pt!(T::RBrace, self),
+            '[' => pt!(T::LSBracket, self),
+            ']' => pt!(T::RSBracket, self),
+            '"' => self.parse_string(),
+            ';' => self.parse_comment(),
+            // Whitespace
+            _ => (),
+        }
+    }
+
+    fn parse_ambiguous(&mut self) {
         let start = self.position;
 
         while let Some(c) = self.next() {
             match c {
-                '0' ..= '9' | '+' | '-' | '/' | '.' | 'e' | 'i' | 'n' | 'f' | 'N' | 'a' => (),
-                '(' | '{' | '[' => {
-                    self.distinguish_ambiguous(start)?;
-                    match c {
-                        '(' => pt!(T::LParen, self),
-                        '{' => pt!(T::LBrace, self),
-                        '[' => pt!(T::LSBracket, self),
-                        _ => unreachable!(),
-                    }
-                    return Ok(());
+                // Letters and `_` may be part of a radix prefix, digit separator or type suffix
+                '0' ..= '9' | 'a' ..= 'z' | 'A' ..= 'Z' | '_' | '+' | '-' | '/' | '.' => (),
+                c if is_delimiter(c) => {
+                    self.distinguish_ambiguous(start, self.position - c.len_utf8());
+                    return self.delimiter(c);
                 }
-                ')' | '}' | ']' => {
-                    self.distinguish_ambiguous(start)?;
-                    match c {
-                        ')' => pt!(T::RParen, self),
-                        '}' => pt!(T::RBrace, self),
-                        ']' => pt!(T::RSBracket, self),
-                        _ => unreachable!(),
+                '\\' => {
+                    let escape = self.position;
+                    match self.next() {
+                        Some(_) => return self.parse_identifier(start, false),
+                        None => return self.error(E::EOF, escape),
                     }
-                    return Ok(());
                 }
-                _ if c.is_whitespace() => break,
-                '\\' => match self.next() {
-                    Some(_) => return self.parse_identifier(start, false),
-                    None => return Err(TokenizeError::EOF),
-                },
                 _ => return self.parse_identifier(start, c == '|'),
             }
         }
-        self.distinguish_ambiguous(start)
+        self.distinguish_ambiguous(start, self.position);
     }
 
-    fn distinguish_ambiguous(&mut self, start: usize) -> TokenizeResult {
-        let end = if self.raw_input.len() == self.position {
-            self.position
-        } else {
-            // TODO: might need char::len_utf8
-            self.position - 1
-        };
-
+    fn distinguish_ambiguous(&mut self, start: usize, end: usize) {
         let buf = &self.raw_input[start-1..end];
-        let index = Index::new(start, end);
+        let index = Index::new(self.file, start, end);
 
-        if intp(buf) {
-            self.tokens.push(T::Integer(index));
+        if let Some(integer) = IntegerLiteral::parse(buf) {
+            if integer.in_range() {
+                self.tokens.push(T::Integer(index));
+            } else {
+                self.errors.push(TokenizeError::new(E::IntegerRange, index));
+            }
         } else if floatp(buf) {
             self.tokens.push(T::Float(index));
         } else {
             self.tokens.push(T::Symbol(index));
         }
-        Ok(())
     }
 
-    fn parse_identifier(&mut self, start: usize, mut in_bar: bool) -> TokenizeResult {
+    fn parse_identifier(&mut self, start: usize, mut in_bar: bool) {
         while let Some(c) = self.next() {
             match c {
-                '\\' => match self.next() {
-                    Some(_) => (),
-                    None => return Err(TokenizeError::EOF),
-                },
+                '\\' => {
+                    let escape = self.position;
+                    if self.next().is_none() {
+                        return self.error(E::EOF, escape);
+                    }
+                }
                 '|' => in_bar = !in_bar,
                 c if is_delimiter(c) => if !in_bar {
-                    self.tokens.push(T::Symbol(Index::new(start, self.position - c.len_utf8())));
-                    return match c {
-                        c if c.is_whitespace() => Ok(()),
-                        '(' => Ok(pt!(T::LParen, self)),
-                        ')' => Ok(pt!(T::RParen, self)),
-                        '{' => Ok(pt!(T::LBrace, self)),
-                        '}' => Ok(pt!(T::RBrace, self)),
-                        '[' => Ok(pt!(T::LSBracket, self)),
-                        ']' => Ok(pt!(T::RSBracket, self)),
-                        '"' => self.parse_string(),
-                        ';' => self.parse_comment(),
-                        _ => panic!("Tokenizer error"),
-                    };
+                    self.tokens.push(T::Symbol(Index::new(self.file, start, self.position - c.len_utf8())));
+                    return self.delimiter(c);
                 },
                 _ => (),
             }
         }
-        self.tokens.push(T::Symbol(Index::new(start, self.position)));
-        Ok(())
+
+        if in_bar {
+            self.error(E::UnterminatedSymbol, start);
+        } else {
+            self.tokens.push(T::Symbol(Index::new(self.file, start, self.position)));
+        }
     }
 
-    pub fn parse_string(&mut self) -> TokenizeResult {
+    pub fn parse_string(&mut self) {
         let start = self.position;
         while let Some(c) = self.next() {
             match c {
-                '\\' => if let Some(c) = self.next() {
-                    match c {
-                        'n' | 't' => (),
-                        // TODO: handle other escapes
-                        _ => (),
-                    }
-                } else {
-                    return Err(TokenizeError::EOF);
+                '\\' => if self.next().is_none() {
+                    break;
                 },
                 '"' => {
-                    self.tokens.push(T::String(Index::new(start, self.position)));
-                    return Ok(());
+                    let token = T::String(Index::new(self.file, start, self.position));
+                    // Escapes are validated here, but the token is kept so that the rest of the
+                    // string isn't mistaken for code.
+                    if let Err(e) = literal::decode_string(token, self.raw_input) {
+                        self.errors.push(e);
+                    }
+                    self.tokens.push(token);
+                    return;
                 }
                 _ => (),
             }
         }
-        Err(TokenizeError::EOF)
+        self.error(E::UnterminatedString, start);
     }
 
-    pub fn parse_char(&mut self) -> TokenizeResult {
+    pub fn parse_char(&mut self) {
         let start = self.position;
         self.next();
         match self.next() {
             Some('\\') => match self.next() {
-                Some('"') | Some('\\') | Some('\'') | Some('r') | Some('n') | Some('t') | Some('0') => (),
-                Some(_) => return Err(TokenizeError::Char),
-                None => return Err(TokenizeError::EOF),
+                // `\u{...}` contains braces, which would otherwise end the literal
+                Some('u') if self.peek() == Some('{') => while let Some(c) = self.peek() {
+                    if c == '\'' || c.is_whitespace() {
+                        break;
+                    }
+                    self.next();
+                    if c == '}' {
+                        break;
+                    }
+                },
+                Some(_) => (),
+                None => return self.error(E::UnterminatedChar, start),
             },
-            Some('\'') => return Err(TokenizeError::Char),
+            Some('\'') => return self.error(E::InvalidChar, start),
             Some(_) => (),
-            None => return Err(TokenizeError::EOF),
+            None => return self.error(E::UnterminatedChar, start),
         }
 
-        match self.next() {
-            Some('\'') => {
-                self.tokens.push(T::Char(Index::new(start, self.position)));
-                Ok(())
+        // Anything else up to the closing quote is consumed so that the literal is reported as a
+        // whole.
+        while let Some(c) = self.peek() {
+            if c == '\'' {
+                self.next();
+                let token = T::Char(Index::new(self.file, start, self.position));
+                if let Err(e) = literal::decode_char(token, self.raw_input) {
+                    self.errors.push(e);
+                }
+                self.tokens.push(token);
+                return;
+            } else if is_delimiter(c) {
+                break;
             }
-            Some(_) => Err(TokenizeError::Char),
-            None => Err(TokenizeError::EOF),
+            self.next();
         }
+        self.error(E::UnterminatedChar, start);
     }
 
-    fn parse_block_comment(&mut self) -> TokenizeResult {
+    fn parse_block_comment(&mut self) {
         // TODO: maybe -1 here
         let start = self.position;
+        // Skip the opening `|` so that `#|#` isn't mistaken for a nested comment
+        self.next();
         let mut nesting = 1;
         while let Some(c) = self.next() {
             match c {
-                '|' => match self.next() {
-                    Some('#') => {
-                        nesting -= 1;
-                        if nesting == 0 {
-                            self.tokens.push(T::BlockComment(Index::new(start, self.position)));
-                            return Ok(());
-                        }
+                '|' if self.peek() == Some('#') => {
+                    self.next();
+                    nesting -= 1;
+                    if nesting == 0 {
+                        self.tokens.push(T::BlockComment(Index::new(self.file, start, self.position)));
+                        return;
                     }
-                    Some(_) => (),
-                    None => return Err(TokenizeError::EOF),
-                },
-                '#' => match self.next() {
-                    Some('|') => nesting += 1,
-                    Some(_) => (),
-                    None => return Err(TokenizeError::EOF),
-                },
+                }
+                '#' if self.peek() == Some('|') => {
+                    self.next();
+                    nesting += 1;
+                }
                 _ => (),
             }
         }
-        Err(TokenizeError::EOF)
+        self.error(E::UnterminatedBlockComment, start);
     }
 
-    fn parse_comment(&mut self) -> TokenizeResult {
+    fn parse_comment(&mut self) {
         let start = self.position;
         while let Some(c) = self.next() {
             match c {
@@ -264,8 +305,7 @@ impl<'a> Tokenizer<'a> {
                 _ => (),
             }
         }
-        self.tokens.push(T::Comment(Index::new(start, self.position)));
-        Ok(())
+        self.tokens.push(T::Comment(Index::new(self.file, start, self.position)));
     }
 }
 
@@ -279,28 +319,6 @@ fn is_delimiter(c: char) -> bool {
     }
 }
 
-fn intp(input: &str) -> bool {
-    let input = if input.as_bytes()[0] == b'+' || input.as_bytes()[0] == b'-' {
-        &input[1..]
-    } else {
-        input
-    };
-
-    // Needed in case `input` is just a sign
-    if input.len() == 0 {
-        return false;
-    }
-
-    for ch in input.chars() {
-        match ch {
-            '0' ..= '9' => (),
-            _ => return false,
-        }
-    }
-
-    true
-}
-
 fn floatp(input: &str) -> bool {
     let input = if input.as_bytes()[0] == b'+' || input.as_bytes()[0] == b'-' {
         &input[1..]
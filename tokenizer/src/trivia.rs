@@ -0,0 +1,117 @@
+use super::{FileId, Index, Token, TokenizeError, Tokenizer};
+
+/// Input which carries no meaning for the compiler, but which tools such as formatters must keep.
+#[derive(Copy, Clone, Debug, PartialEq, is_enum_variant)]
+pub enum Trivia {
+    Whitespace(Index),
+    Comment(Index),
+    BlockComment(Index),
+    /// Input that failed to tokenize
+    Error(Index),
+}
+
+impl Trivia {
+    pub fn index(&self) -> Index {
+        use self::Trivia::*;
+        match self {
+            Whitespace(i) => *i,
+            Comment(i) => *i,
+            BlockComment(i) => *i,
+            Error(i) => *i,
+        }
+    }
+
+    pub fn as_str<'a>(&self, input: &'a str) -> &'a str {
+        let i = self.index();
+        &input[i.start()..i.end()]
+    }
+}
+
+/// A token along with the trivia that precedes it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LosslessToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+}
+
+impl LosslessToken {
+    /// Appends the source text of the token and its trivia to `out`.
+    pub fn write(&self, input: &str, out: &mut String) {
+        for t in &self.leading {
+            out.push_str(t.as_str(input));
+        }
+        out.push_str(self.token.as_str(input));
+    }
+}
+
+/// Output of `Tokenizer::tokenize_lossless`. Concatenating the trivia and tokens in order
+/// reproduces the input exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LosslessTokens {
+    pub tokens: Vec<LosslessToken>,
+    /// Trivia after the last token
+    pub trailing: Vec<Trivia>,
+    pub errors: Vec<TokenizeError>,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Tokenizes `raw_input` without discarding anything. Whitespace and comments are attached to
+    /// the token that follows them, and input that fails to tokenize is kept as `Trivia::Error`.
+    pub fn tokenize_lossless(raw_input: &'a str, file: FileId) -> LosslessTokens {
+        let (tokens, errors) = Tokenizer::tokenize_partial(raw_input, file);
+
+        let mut lossless = Vec::new();
+        let mut leading = Vec::new();
+        let mut position = 0;
+        for token in tokens {
+            let index = token.index();
+            gap(raw_input, file, position, index.start(), &mut leading);
+            position = index.end();
+
+            match token {
+                Token::Comment(i) => leading.push(Trivia::Comment(i)),
+                Token::BlockComment(i) => leading.push(Trivia::BlockComment(i)),
+                _ => lossless.push(LosslessToken {
+                    leading: std::mem::replace(&mut leading, Vec::new()),
+                    token,
+                }),
+            }
+        }
+        gap(raw_input, file, position, raw_input.len(), &mut leading);
+
+        LosslessTokens {
+            tokens: lossless,
+            trailing: leading,
+            errors,
+        }
+    }
+}
+
+/// Splits the text between two tokens into runs of whitespace and anything the tokenizer
+/// skipped over.
+fn gap(input: &str, file: FileId, start: usize, end: usize, out: &mut Vec<Trivia>) {
+    let mut run_start = start;
+    let mut whitespace = true;
+    for (i, c) in input[start..end].char_indices() {
+        let i = start + i;
+        if c.is_whitespace() != whitespace && i != run_start {
+            out.push(trivia(file, run_start, i, whitespace));
+            run_start = i;
+        }
+        whitespace = c.is_whitespace();
+    }
+
+    if run_start != end {
+        out.push(trivia(file, run_start, end, whitespace));
+    }
+}
+
+fn trivia(file: FileId, start: usize, end: usize, whitespace: bool) -> Trivia {
+    // Index::new expects a start which is one past the beginning of the span
+    let index = Index::new(file, start + 1, end);
+    if whitespace {
+        Trivia::Whitespace(index)
+    } else {
+        Trivia::Error(index)
+    }
+}
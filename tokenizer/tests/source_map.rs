@@ -0,0 +1,49 @@
+extern crate tokenizer;
+
+use tokenizer::SourceMap;
+
+#[test]
+fn locations() {
+    let mut map = SourceMap::new();
+    let main = map.add_file("main.inc".into(), "(include lib.inc)\n\n  (exit 0)\n".into());
+    let lib = map.add_file("lib.inc".into(), "(defn (exit ([code i32]) !)\n    (λ x))".into());
+
+    let tokens = map.tokenize(main).unwrap();
+    let loc = map.token_location(tokens[4]);
+    assert_eq!((loc.file, loc.line, loc.column), ("main.inc", 3, 3));
+    assert_eq!(loc.line_text, "  (exit 0)");
+    assert_eq!(loc.to_string(), "main.inc:3:3");
+    assert_eq!(map.as_str(tokens[6].index()), "0");
+
+    let tokens = map.tokenize(lib).unwrap();
+    let x = tokens.iter().rev().nth(2).unwrap();
+    assert_eq!(map.as_str(x.index()), "x");
+    let loc = map.token_location(*x);
+    assert_eq!((loc.file, loc.line, loc.column), ("lib.inc", 2, 8));
+    assert_eq!(map.find_file("lib.inc"), Some(lib));
+}
+
+#[test]
+fn diagnostic() {
+    let mut map = SourceMap::new();
+    let file = map.add_file("main.inc".into(), "(defn (main ())\n    (exit \"0\"))\n".into());
+    let tokens = map.tokenize(file).unwrap();
+    let expected = "error: Incompatible types\n --> main.inc:2:11\n  |\n2 |     (exit \"0\"))\n  |           ^^^\n";
+    assert_eq!(map.diagnostic(tokens[9].index(), "Incompatible types"), expected);
+}
+
+#[test]
+fn expansions() {
+    let mut map = SourceMap::new();
+    let file = map.add_file("main.inc".into(), "(defmacro (m x) (+ x \"1\"))\n(m 2)\n".into());
+    let tokens: Vec<_> = map.tokenize(file).unwrap().iter().map(|t| t.index()).collect();
+    let find = |text| *tokens.iter().rev().find(|&&index| map.as_str(index) == text).unwrap();
+    let (string, open, close) = (find("\"1\""), find("("), find(")"));
+    let m = map.add_expansion("m".into(), open.to(close));
+    assert_eq!(map.expansions(string.expanded(m)), vec![("m", open.to(close))]);
+    assert!(map.expansions(string).is_empty());
+
+    let expected = "error: Incompatible types\n --> main.inc:1:22\n  |\n1 | (defmacro (m x) (+ x \"1\"))\n  |                      ^^^\n\
+                    note: in expansion of `m`\n --> main.inc:2:1\n  |\n2 | (m 2)\n  | ^^^^^\n";
+    assert_eq!(map.diagnostic(string.expanded(m), "Incompatible types"), expected);
+}
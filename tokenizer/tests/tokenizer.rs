@@ -1,6 +1,6 @@
 extern crate tokenizer;
 
-use tokenizer::{Token, Tokenizer};
+use tokenizer::{IntSuffix, Token, TokenizeErrorKind, Tokenizer};
 
 fn run(input: &str) -> Token {
     Tokenizer::tokenize(input).unwrap().remove(0)
@@ -76,3 +76,91 @@ fn symbol() {
     //assert_eq!(run("\\\n"), Token::Symbol("\n".into()));
     //assert_eq!(run("\\\n"), run("|\n|"));
 }
+
+fn errors(input: &str) -> Vec<TokenizeErrorKind> {
+    Tokenizer::tokenize(input).unwrap_err().into_iter().map(|e| e.kind).collect()
+}
+
+#[test]
+fn errors_are_collected() {
+    use tokenizer::TokenizeErrorKind::*;
+    assert_eq!(errors("(print \"abc)"), vec![UnterminatedString]);
+    assert_eq!(errors("#| (a #| b |#"), vec![UnterminatedBlockComment]);
+    assert_eq!(errors("\"a\\qb\" |# #'ab' #''"), vec![BadEscape, StrayDelimiter, InvalidChar, InvalidChar]);
+    assert_eq!(errors("|abc"), vec![UnterminatedSymbol]);
+    assert_eq!(errors("abc\\"), vec![EOF]);
+}
+
+#[test]
+fn recovery() {
+    let input = "(a #'xy b) \"\\q\" (c)";
+    let (tokens, errors) = Tokenizer::tokenize_partial(input, Default::default());
+    assert_eq!(errors.len(), 2);
+    assert_eq!(&input[errors[0].index.start()..errors[0].index.end()], "#'xy");
+    assert_eq!(&input[errors[1].index.start()..errors[1].index.end()], "\\q");
+    let tokens: Vec<_> = tokens.iter().map(|t| t.as_str(input)).collect();
+    assert_eq!(tokens, vec!["(", "a", "b", ")", "\"\\q\"", "(", "c", ")"]);
+}
+
+#[test]
+fn integers() {
+    fn int(input: &str) -> (i128, Option<IntSuffix>) {
+        let t = run(input);
+        let i = t.integer(input).unwrap();
+        (i.value, i.suffix)
+    }
+
+    assert_eq!(int("0xff"), (255, None));
+    assert_eq!(int("-0x10"), (-16, None));
+    assert_eq!(int("0b1010_1010"), (0b1010_1010, None));
+    assert_eq!(int("0o777"), (0o777, None));
+    assert_eq!(int("1_000_000"), (1_000_000, None));
+    assert_eq!(int("42u8"), (42, Some(IntSuffix::U8)));
+    assert_eq!(int("0xffff_ffffu64"), (0xffff_ffff, Some(IntSuffix::U64)));
+    assert_eq!(int("-1i8"), (-1, Some(IntSuffix::I8)));
+    assert_eq!(int("+7_usize"), (7, Some(IntSuffix::Usize)));
+    assert!(run("0x").is_symbol());
+    assert!(run("0xfg").is_symbol());
+    assert!(run("1u7").is_symbol());
+    assert!(run("1e3").is_float());
+    assert!(run("abc").is_symbol());
+
+    use tokenizer::TokenizeErrorKind::IntegerRange;
+    assert_eq!(errors("256u8"), vec![IntegerRange]);
+    assert_eq!(errors("-1u32"), vec![IntegerRange]);
+    assert_eq!(errors("0x1_0000_0000_0000_0000"), vec![IntegerRange]);
+}
+
+#[test]
+fn literals() {
+    fn string(input: &str) -> Vec<u8> {
+        run(input).string_bytes(input).unwrap()
+    }
+
+    fn chr(input: &str) -> u32 {
+        run(input).char_value(input).unwrap()
+    }
+
+    assert_eq!(string(r#""a\tb\n""#), b"a\tb\n");
+    assert_eq!(string(r#""\"\\\0\r""#), b"\"\\\0\r");
+    assert_eq!(string(r#""\x41\xff""#), vec![0x41, 0xff]);
+    assert_eq!(string(r#""\u{3bb}\u{1F600}""#), "λ😀".as_bytes());
+    assert_eq!(string("\"one \\\n     two\""), b"one two");
+    assert_eq!(chr("#'a'"), 'a' as u32);
+    assert_eq!(chr("#'('"), '(' as u32);
+    assert_eq!(chr(r"#'\''"), '\'' as u32);
+    assert_eq!(chr(r"#'\x7f'"), 0x7f);
+    assert_eq!(chr(r"#'\u{3bb}'"), 'λ' as u32);
+    assert_eq!(chr("#'λ'"), 'λ' as u32);
+
+    use tokenizer::TokenizeErrorKind::*;
+    assert_eq!(errors(r#""\x4""#), vec![BadEscape]);
+    assert_eq!(errors(r#""\u{d800}""#), vec![BadEscape]);
+    assert_eq!(errors(r#""\u{}""#), vec![BadEscape]);
+    assert_eq!(errors(r"#'\u{3bb'"), vec![BadEscape]);
+    assert_eq!(errors(r"#'\x41\x42'"), vec![InvalidChar]);
+
+    let input = "(print \"ok\\q\")";
+    let e = Tokenizer::tokenize(input).unwrap_err().remove(0);
+    assert_eq!(&input[e.index.start()..e.index.end()], "\\q");
+}
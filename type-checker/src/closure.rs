@@ -0,0 +1,27 @@
+use parser::Type;
+use string_interner::Symbol;
+use tokenizer::Index;
+
+/// A lambda along with the variables it captures from the functions around it.
+///
+/// Closure conversion turns every lambda into a top level function that takes a pointer to its
+/// environment as a hidden first argument. The environment is laid out like a struct with one
+/// field per captured variable, in the order of `captures`, and is filled in with copies of the
+/// variables when the lambda is evaluated. Assigning to a captured variable inside the lambda only
+/// changes its copy.
+///
+/// Values of function type are a pair of a code pointer and an environment pointer. The
+/// environment pointer is null for named functions and for lambdas that capture nothing, so those
+/// need no allocation, and a call through a function value always passes the environment along.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Closure {
+    /// Span of the `lambda` expression
+    pub span: Index,
+    pub captures: Vec<(Symbol, Type)>,
+}
+
+impl Closure {
+    pub fn capturesp(&self) -> bool {
+        !self.captures.is_empty()
+    }
+}
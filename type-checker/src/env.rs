@@ -1,10 +1,32 @@
-use parser::Type;
-use string_interner::Symbol;
+use closure::Closure;
+use error::TypeError;
+use infer::Unifier;
+use mono::{Instance, Key};
+
+use parser::{IntrinsicOp, Type};
+use string_interner::{get_value, Symbol};
+use tokenizer::Index;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+/// Type parameters and fields of a struct declaration
+pub type StructDef = (Vec<Symbol>, Vec<(Symbol, Type)>);
+
+/// Type parameters and variants of an enum declaration, along with the types of each payload
+pub type EnumDef = (Vec<Symbol>, Vec<(Symbol, Vec<Type>)>);
+
+/// Signature of a method declared in an impl block
+#[derive(Clone, Debug)]
+pub struct Method {
+    /// Type parameters of the impl block followed by those of the method
+    pub params: Vec<Symbol>,
+    pub ty: Type,
+    /// Whether the first argument is `self`
+    pub receiver: bool,
+}
+
 #[derive(Clone, Default)]
 pub struct Environment {
     env: Rc<RefCell<_Environment>>,
@@ -19,17 +41,6 @@ impl Environment {
     }
     */
 
-    pub fn from_hashmap(map: HashMap<Symbol, Type>) -> Self {
-        let env = _Environment {
-            bindings: map,
-            parent: None,
-        };
-
-        Environment {
-            env: Rc::new(RefCell::new(env)),
-        }
-    }
-
     pub fn extend(&self) -> Self {
         let mut env = _Environment::new();
         env.parent = Some(self.clone());
@@ -38,19 +49,252 @@ impl Environment {
         }
     }
 
+    /// Extends the environment with a scope for the body of a loop.
+    pub fn extend_loop(&self) -> Self {
+        let env = self.extend();
+        env.env.borrow_mut().breaks = Some(Vec::new());
+        env
+    }
+
+    /// Extends the environment with a scope for the body of a lambda, which keeps track of the
+    /// variables it captures.
+    pub fn extend_lambda(&self) -> Self {
+        let env = self.extend();
+        env.env.borrow_mut().captures = Some(Vec::new());
+        env
+    }
+
     pub fn lookup_variable_type(&self, name: Symbol) -> Option<Type> {
-        self.env.borrow().lookup_variable_type(name)
+        let parent = {
+            let env = self.env.borrow();
+            if let Some(ty) = env.bindings.get(&name) {
+                return Some(ty.clone());
+            }
+            env.parent.clone()?
+        };
+
+        let ty = parent.lookup_variable_type(name)?;
+        // Only local variables need to be captured, top level definitions are always around
+        if let Some(ref mut captures) = self.env.borrow_mut().captures {
+            if parent.is_local(name) && !captures.iter().any(|(n, _)| *n == name) {
+                captures.push((name, ty.clone()));
+            }
+        }
+        Some(ty)
+    }
+
+    /// Whether `name` is bound somewhere other than the outermost scope
+    fn is_local(&self, name: Symbol) -> bool {
+        let env = self.env.borrow();
+        match env.parent {
+            Some(ref parent) => env.bindings.contains_key(&name) || parent.is_local(name),
+            None => false,
+        }
+    }
+
+    /// Variables from enclosing functions used in this lambda scope, in order of first use
+    pub fn captures(&self) -> Vec<(Symbol, Type)> {
+        self.env.borrow().captures.clone().unwrap_or_default()
+    }
+
+    /// Records a `break` at `span` out of the innermost loop. Returns false if there is no
+    /// enclosing loop.
+    pub fn add_break(&self, ty: Type, span: Index) -> bool {
+        self.env.borrow_mut().add_break((ty, span))
+    }
+
+    /// Types of the values given to `break` in this loop scope, along with where each break is
+    pub fn breaks(&self) -> Vec<(Type, Index)> {
+        self.env.borrow().breaks.clone().unwrap_or_default()
     }
 
     pub fn define_variable(&self, name: Symbol, ty: Type) {
         self.env.borrow_mut().define_variable(name, ty);
     }
+
+    /// Type parameters of the struct `name` and its fields in declaration order
+    pub fn lookup_struct(&self, name: Symbol) -> Option<StructDef> {
+        self.env.borrow().lookup_struct(name)
+    }
+
+    pub fn define_struct(&self, name: Symbol, def: StructDef) {
+        self.env.borrow_mut().structs.insert(name, def);
+    }
+
+    pub fn lookup_enum(&self, name: Symbol) -> Option<EnumDef> {
+        self.env.borrow().lookup_enum(name)
+    }
+
+    /// Enum that has a variant called `name`
+    pub fn lookup_variant(&self, name: Symbol) -> Option<Symbol> {
+        self.env.borrow().lookup_variant(name)
+    }
+
+    pub fn define_enum(&self, name: Symbol, def: EnumDef) {
+        let mut env = self.env.borrow_mut();
+        for (variant, _) in &def.1 {
+            env.variants.insert(*variant, name);
+        }
+        env.enums.insert(name, def);
+    }
+
+    pub fn lookup_method(&self, owner: Symbol, name: Symbol) -> Option<Method> {
+        self.env.borrow().lookup_method(owner, name)
+    }
+
+    pub fn define_method(&self, owner: Symbol, name: Symbol, method: Method) {
+        self.env.borrow_mut().methods.insert((owner, name), method);
+    }
+
+    /// Type parameters of the generic function `name`
+    pub fn lookup_generic(&self, name: Symbol) -> Option<Vec<Symbol>> {
+        self.env.borrow().lookup_generic(name)
+    }
+
+    pub fn define_generic(&self, name: Symbol, params: Vec<Symbol>) {
+        self.env.borrow_mut().generics.insert(name, params);
+    }
+
+    /// Operation of the intrinsic `name`
+    pub fn lookup_intrinsic(&self, name: Symbol) -> Option<IntrinsicOp> {
+        self.env.borrow().lookup_intrinsic(name)
+    }
+
+    pub fn define_intrinsic(&self, name: Symbol, op: IntrinsicOp) {
+        self.env.borrow_mut().intrinsics.insert(name, op);
+    }
+
+    /// Marks this scope as the body of the generic item `key`.
+    pub fn set_caller(&self, key: Key) {
+        self.env.borrow_mut().caller = Some(key);
+    }
+
+    /// Records a use of a generic item. Uses from inside other generic items are kept along with
+    /// the item they came from, so that they can be instantiated once it is.
+    pub fn record_instance(&self, instance: Instance) {
+        let caller = self.env.borrow().caller();
+        self.env.borrow_mut().record_instance(caller, instance);
+    }
+
+    /// Uses of generic items, with the type arguments inferred so far filled in
+    pub fn instances(&self) -> Vec<(Option<Key>, Instance)> {
+        let env = self.env.borrow();
+        env.instances.iter()
+            .map(|(caller, instance)| (*caller, Instance {
+                args: instance.args.iter().map(|ty| env.unifier.resolve(ty)).collect(),
+                ..instance.clone()
+            }))
+            .collect()
+    }
+
+    pub fn record_closure(&self, closure: Closure) {
+        let parent = self.env.borrow().parent.clone();
+        match parent {
+            Some(parent) => parent.record_closure(closure),
+            None => self.env.borrow_mut().closures.push(closure),
+        }
+    }
+
+    pub fn closures(&self) -> Vec<Closure> {
+        let env = self.env.borrow();
+        env.closures.iter()
+            .map(|closure| Closure {
+                captures: closure.captures.iter().map(|(name, ty)| (*name, env.unifier.resolve(ty))).collect(),
+                ..*closure
+            })
+            .collect()
+    }
+
+    /// Outermost scope, which holds what is inferred about types
+    fn root(&self) -> Environment {
+        match self.env.borrow().parent {
+            Some(ref parent) => parent.root(),
+            None => self.clone(),
+        }
+    }
+
+    /// New type variable for a type that is yet to be inferred
+    pub fn fresh_var(&self) -> Type {
+        self.root().env.borrow_mut().unifier.fresh()
+    }
+
+    pub fn resolve(&self, ty: &Type) -> Type {
+        self.root().env.borrow().unifier.resolve(ty)
+    }
+
+    /// Whether nothing determined some part of `ty`
+    pub fn unresolved(&self, ty: &Type) -> bool {
+        self.root().env.borrow().unifier.unresolved(ty)
+    }
+
+    /// Infers what it takes for a value of type `found` to be used where `expected` is wanted.
+    pub fn unify(&self, expected: &Type, found: &Type) -> Result<(), TypeError> {
+        let root = self.root();
+        let mut env = root.env.borrow_mut();
+        if env.unifier.unify(expected, found) {
+            Ok(())
+        } else {
+            Err(TypeError::mismatch(env.unifier.resolve(expected), env.unifier.resolve(found)))
+        }
+    }
+
+    /// Name in scope that is spelled much like `name`, which was probably meant in its place
+    pub fn similar_name(&self, name: Symbol) -> Option<Symbol> {
+        let spelling = get_value(name)?;
+        // Short names are similar to too many others to tell what was meant
+        let limit = spelling.chars().count() / 3;
+        let mut names = Vec::new();
+        self.env.borrow().names(&mut names);
+        names.into_iter()
+            .filter_map(|n| Some((edit_distance(&spelling, &get_value(n)?), n)))
+            .filter(|(distance, _)| *distance <= limit)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, n)| n)
+    }
+}
+
+/// Number of characters to insert, delete or replace, or pairs of neighbouring characters to
+/// swap, to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the prefixes of `a` up to the current one to every prefix of `b`
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let replaced = rows[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            row[j] = replaced.min(row[j - 1] + 1).min(rows[i - 1][j] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
 }
 
 #[derive(Default)]
 pub struct _Environment {
     bindings: HashMap<Symbol, Type>,
+    structs: HashMap<Symbol, StructDef>,
+    enums: HashMap<Symbol, EnumDef>,
+    variants: HashMap<Symbol, Symbol>,
+    methods: HashMap<(Symbol, Symbol), Method>,
+    generics: HashMap<Symbol, Vec<Symbol>>,
+    intrinsics: HashMap<Symbol, IntrinsicOp>,
     parent: Option<Environment>,
+    /// Generic item whose body this scope belongs to
+    caller: Option<Key>,
+    /// Only present for the scope of a lambda body
+    captures: Option<Vec<(Symbol, Type)>>,
+    /// Only used in the outermost scope
+    instances: Vec<(Option<Key>, Instance)>,
+    closures: Vec<Closure>,
+    /// Only used in the outermost scope
+    unifier: Unifier,
+    /// Only present for the scope of a loop body
+    breaks: Option<Vec<(Type, Index)>>,
 }
 
 impl _Environment {
@@ -58,17 +302,107 @@ impl _Environment {
         Default::default()
     }
 
-    pub fn lookup_variable_type(&self, name: Symbol) -> Option<Type> {
-        if let Some(ty) = self.bindings.get(&name) {
-            Some(ty.clone())
+    pub fn define_variable(&mut self, name: Symbol, ty: Type) {
+        self.bindings.insert(name, ty);
+    }
+
+    pub fn lookup_struct(&self, name: Symbol) -> Option<StructDef> {
+        if let Some(def) = self.structs.get(&name) {
+            Some(def.clone())
         } else if let Some(ref env) = self.parent {
-            env.lookup_variable_type(name)
+            env.lookup_struct(name)
         } else {
             None
         }
     }
 
-    pub fn define_variable(&mut self, name: Symbol, ty: Type) {
-        self.bindings.insert(name, ty);
+    pub fn lookup_enum(&self, name: Symbol) -> Option<EnumDef> {
+        if let Some(def) = self.enums.get(&name) {
+            Some(def.clone())
+        } else if let Some(ref env) = self.parent {
+            env.lookup_enum(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup_variant(&self, name: Symbol) -> Option<Symbol> {
+        if let Some(name) = self.variants.get(&name) {
+            Some(*name)
+        } else if let Some(ref env) = self.parent {
+            env.lookup_variant(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup_method(&self, owner: Symbol, name: Symbol) -> Option<Method> {
+        if let Some(method) = self.methods.get(&(owner, name)) {
+            Some(method.clone())
+        } else if let Some(ref env) = self.parent {
+            env.lookup_method(owner, name)
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup_generic(&self, name: Symbol) -> Option<Vec<Symbol>> {
+        if self.bindings.contains_key(&name) {
+            // A plain binding shadows any generic function of the same name further out
+            self.generics.get(&name).cloned()
+        } else if let Some(params) = self.generics.get(&name) {
+            Some(params.clone())
+        } else if let Some(ref env) = self.parent {
+            env.lookup_generic(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn lookup_intrinsic(&self, name: Symbol) -> Option<IntrinsicOp> {
+        if self.bindings.contains_key(&name) {
+            // Same as for generic functions, local variables shadow intrinsics
+            self.intrinsics.get(&name).cloned()
+        } else if let Some(ref env) = self.parent {
+            env.lookup_intrinsic(name)
+        } else {
+            None
+        }
+    }
+
+    /// Adds every name that can be used as a value here: variables, functions, structs and
+    /// variants.
+    fn names(&self, names: &mut Vec<Symbol>) {
+        names.extend(self.bindings.keys().chain(self.structs.keys()).chain(self.variants.keys()));
+        if let Some(ref env) = self.parent {
+            env.env.borrow().names(names);
+        }
+    }
+
+    fn caller(&self) -> Option<Key> {
+        match (self.caller, &self.parent) {
+            (Some(key), _) => Some(key),
+            (None, Some(env)) => env.env.borrow().caller(),
+            (None, None) => None,
+        }
+    }
+
+    fn record_instance(&mut self, caller: Option<Key>, instance: Instance) {
+        if let Some(ref env) = self.parent {
+            env.env.borrow_mut().record_instance(caller, instance);
+        } else {
+            self.instances.push((caller, instance));
+        }
+    }
+
+    pub fn add_break(&mut self, brk: (Type, Index)) -> bool {
+        if let Some(ref mut breaks) = self.breaks {
+            breaks.push(brk);
+            true
+        } else if let Some(ref env) = self.parent {
+            env.env.borrow_mut().add_break(brk)
+        } else {
+            false
+        }
     }
 }
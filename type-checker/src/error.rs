@@ -1,18 +1,180 @@
+use parser::Type;
+use string_interner::{get_value, Symbol};
+use tokenizer::Index;
+
 use std::fmt::{self, Display, Formatter};
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    /// Span of the offending expression or declaration, if the error can be pinned down to one
+    pub span: Option<Index>,
+    /// Identifier, field, type or function the error is about
+    pub name: Option<Symbol>,
+    /// Type that was wanted
+    pub expected: Option<Box<Type>>,
+    /// Type that was found instead
+    pub found: Option<Box<Type>>,
+    /// Position of the argument that doesn't fit, counting from zero
+    pub arg: Option<usize>,
+    /// Name in scope that is spelled much like an unbound identifier
+    pub suggestion: Option<Symbol>,
+}
+
+impl TypeError {
+    pub fn new(kind: TypeErrorKind) -> Self {
+        TypeError {
+            kind,
+            span: None,
+            name: None,
+            expected: None,
+            found: None,
+            arg: None,
+            suggestion: None,
+        }
+    }
+
+    /// Error for a value of type `found` where one of type `expected` is wanted
+    pub fn mismatch(expected: Type, found: Type) -> Self {
+        TypeError::new(TypeErrorKind::Mismatch).expected(expected).found(found)
+    }
+
+    /// Sets the span, unless a more precise one was already given where the error came from.
+    pub fn at(mut self, span: Index) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    pub fn name(mut self, name: Symbol) -> Self {
+        self.name.get_or_insert(name);
+        self
+    }
+
+    pub fn expected(mut self, ty: Type) -> Self {
+        self.expected = Some(Box::new(ty));
+        self
+    }
+
+    pub fn found(mut self, ty: Type) -> Self {
+        self.found = Some(Box::new(ty));
+        self
+    }
+
+    pub fn arg(mut self, arg: usize) -> Self {
+        self.arg.get_or_insert(arg);
+        self
+    }
+
+    pub fn suggestion(mut self, suggestion: Option<Symbol>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
+}
+
+impl From<TypeErrorKind> for TypeError {
+    fn from(kind: TypeErrorKind) -> Self {
+        TypeError::new(kind)
+    }
+}
+
+/// Errors read like "Mismatched types in argument 2 of `push`, expected `i32`, found `bool`" or
+/// "Unbound identifier `lenght`, did you mean `length`?". Names that the message doesn't lead up to
+/// go in front, as in "`List`: Struct contains itself and would be infinitely large".
+impl Display for TypeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = self.name.map(|name| get_value(name).unwrap_or_default());
+        match (&name, self.arg) {
+            (Some(name), None) if !self.kind.namedp() => write!(f, "`{}`: {}", name, self.kind)?,
+            (Some(name), None) => write!(f, "{} `{}`", self.kind, name)?,
+            (Some(name), Some(arg)) => write!(f, "{} in argument {} of `{}`", self.kind, arg + 1, name)?,
+            (None, Some(arg)) => write!(f, "{} in argument {}", self.kind, arg + 1)?,
+            (None, None) => write!(f, "{}", self.kind)?,
+        }
+        if let Some(ref expected) = self.expected {
+            write!(f, ", expected `{}`", expected)?;
+        }
+        if let Some(ref found) = self.found {
+            write!(f, ", found `{}`", found)?;
+        }
+        if let Some(suggestion) = self.suggestion {
+            write!(f, ", did you mean `{}`?", get_value(suggestion).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
-pub enum TypeError {
+pub enum TypeErrorKind {
     UnboundIdentifier,
+    /// Value whose type is the wrong kind of type for how it is used, such as calling a number
     Incompatible,
+    /// Types that can't be made equal
+    Mismatch,
     Args,
+    UnknownType,
+    UnknownField,
+    /// Struct constructor that doesn't give every field exactly once
+    Fields,
+    /// Struct that contains itself
+    Recursive,
+    /// Type that nothing determines, such as the type parameters of a call that neither the
+    /// arguments nor the use of its value fix
+    Ambiguous,
+    UnknownVariant,
+    /// Enum with two variants of the same name
+    Variants,
+    /// Match that doesn't cover every value
+    NonExhaustive,
+    /// Generic function that keeps being used with ever larger types
+    Instances,
+    /// Intrinsic declared with a signature that doesn't fit its operation
+    Intrinsic,
+    /// Intrinsic used with operands of a type it doesn't support
+    Operand,
+    /// Cast between types that don't convert to each other
+    Cast,
+    /// Function declared `!` whose body can finish
+    Returns,
+    /// Function with a return type whose body can finish without a value, as with an `if` that
+    /// has no `else`
+    NoValue,
+    /// Integer literal outside of the range of the type it is given
+    Range,
+    /// Expression or macro at the top level, where only items can be
+    Item,
 }
 
-impl Display for TypeError {
+impl TypeErrorKind {
+    /// Whether the message ends with what the name of the error names
+    fn namedp(self) -> bool {
+        use self::TypeErrorKind::*;
+        matches!(self, UnboundIdentifier | UnknownType | UnknownField | UnknownVariant)
+    }
+}
+
+impl Display for TypeErrorKind {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            TypeError::UnboundIdentifier => write!(f, "Unbound identifier"),
-            TypeError::Incompatible => write!(f, "Incompatible types"),
-            TypeError::Args => write!(f, "Incorrect number of arguments"),
+            TypeErrorKind::UnboundIdentifier => write!(f, "Unbound identifier"),
+            TypeErrorKind::Incompatible => write!(f, "Incompatible types"),
+            TypeErrorKind::Mismatch => write!(f, "Mismatched types"),
+            TypeErrorKind::Args => write!(f, "Incorrect number of arguments"),
+            TypeErrorKind::UnknownType => write!(f, "Unknown type"),
+            TypeErrorKind::UnknownField => write!(f, "No such field"),
+            TypeErrorKind::Fields => write!(f, "Every field must be given exactly once"),
+            TypeErrorKind::Recursive => write!(f, "Struct contains itself and would be infinitely large"),
+            TypeErrorKind::UnknownVariant => write!(f, "No such variant"),
+            TypeErrorKind::Variants => write!(f, "Every variant must have a distinct name"),
+            TypeErrorKind::NonExhaustive => write!(f, "Match does not cover every value"),
+            TypeErrorKind::Ambiguous => write!(f, "Type can't be inferred, add an annotation"),
+            TypeErrorKind::Instances => write!(f, "Too many instances of a generic function"),
+            TypeErrorKind::Intrinsic => write!(f, "Intrinsic signature doesn't fit its operation"),
+            TypeErrorKind::Operand => write!(f, "Intrinsic doesn't support operands of this type"),
+            TypeErrorKind::Cast => write!(f, "Value can't be cast to this type"),
+            TypeErrorKind::Returns => write!(f, "Function declared `!` can return"),
+            TypeErrorKind::NoValue => write!(f, "Function can finish without giving back a value"),
+            TypeErrorKind::Range => write!(f, "Integer literal does not fit in its type"),
+            TypeErrorKind::Item => write!(f, "Expected an item"),
         }
     }
 }
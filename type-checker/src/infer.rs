@@ -0,0 +1,108 @@
+use parser::Type;
+use string_interner::{get_symbol_uninterned, Symbol};
+
+use std::collections::HashMap;
+
+/// Type variables that stand for types which are still being inferred, along with what each of
+/// them has been found to be so far. They are written `Type::Var` like type parameters, but unlike
+/// those they can be made equal to any type.
+#[derive(Default)]
+pub struct Unifier {
+    vars: HashMap<Symbol, Option<Type>>,
+}
+
+impl Unifier {
+    pub fn fresh(&mut self) -> Type {
+        let var = get_symbol_uninterned("_".into());
+        self.vars.insert(var, None);
+        Type::Var(var)
+    }
+
+    /// Replaces every variable in `ty` that has been inferred with what it stands for.
+    pub fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match self.vars.get(v) {
+                Some(Some(bound)) => self.resolve(bound),
+                _ => ty.clone(),
+            },
+            Type::Ptr(ty) => Type::Ptr(Box::new(self.resolve(ty))),
+            Type::Struct(name, tys) => Type::Struct(*name, tys.iter().map(|t| self.resolve(t)).collect()),
+            Type::Arrow(tys, ret) => Type::Arrow(tys.iter().map(|t| self.resolve(t)).collect(),
+                                                 Box::new(self.resolve(ret))),
+            _ => ty.clone(),
+        }
+    }
+
+    /// Whether `ty` still contains a variable that nothing determined
+    pub fn unresolved(&self, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(v) => self.vars.contains_key(&v),
+            Type::Ptr(ty) => self.unresolved(&ty),
+            Type::Struct(_, tys) => tys.iter().any(|t| self.unresolved(t)),
+            Type::Arrow(tys, ret) => tys.iter().any(|t| self.unresolved(t)) || self.unresolved(&ret),
+            _ => false,
+        }
+    }
+
+    /// Makes `found` equal to `expected` by inferring the variables in either. A value that never
+    /// exists fits anywhere and says nothing about the variables. Nothing is inferred if the types
+    /// can't be made equal.
+    pub fn unify(&mut self, expected: &Type, found: &Type) -> bool {
+        let mut bound = Vec::new();
+        let unified = self.unify_inner(expected, found, &mut bound);
+        if !unified {
+            for var in bound {
+                self.vars.insert(var, None);
+            }
+        }
+        unified
+    }
+
+    fn unify_inner(&mut self, expected: &Type, found: &Type, bound: &mut Vec<Symbol>) -> bool {
+        let expected = self.shallow(expected);
+        let found = self.shallow(found);
+        match (&expected, &found) {
+            (_, Type::Never) => true,
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(v), ty) | (ty, Type::Var(v)) if self.vars.contains_key(v) => {
+                if self.occurs(*v, ty) {
+                    return false;
+                }
+                self.vars.insert(*v, Some(ty.clone()));
+                bound.push(*v);
+                true
+            }
+            (Type::Ptr(e), Type::Ptr(f)) => self.unify_inner(e, f, bound),
+            (Type::Struct(e, es), Type::Struct(f, fs)) => {
+                e == f && es.len() == fs.len() && es.iter().zip(fs).all(|(e, f)| self.unify_inner(e, f, bound))
+            }
+            (Type::Arrow(es, e), Type::Arrow(fs, f)) => {
+                es.len() == fs.len() && es.iter().zip(fs).all(|(e, f)| self.unify_inner(e, f, bound))
+                    && self.unify_inner(e, f, bound)
+            }
+            _ => expected == found,
+        }
+    }
+
+    /// Follows inferred variables until reaching a type that isn't one
+    fn shallow(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match self.vars.get(v) {
+                Some(Some(bound)) => self.shallow(bound),
+                _ => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    /// Whether `var` appears in `ty`, which would make it infinitely large
+    fn occurs(&self, var: Symbol, ty: &Type) -> bool {
+        match self.shallow(ty) {
+            Type::Var(v) => v == var,
+            Type::Ptr(ty) => self.occurs(var, &ty),
+            Type::Struct(_, tys) => tys.iter().any(|t| self.occurs(var, t)),
+            Type::Arrow(tys, ret) => tys.iter().any(|t| self.occurs(var, t)) || self.occurs(var, &ret),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Result, TypeError, TypeErrorKind};
+use env::Environment;
+use mono::substitute;
+
+use parser::Type;
+use string_interner::Symbol;
+
+/// Size and alignment of a type in bytes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+}
+
+impl Layout {
+    fn new(size: usize, align: usize) -> Self {
+        Layout {
+            size,
+            align,
+        }
+    }
+}
+
+/// Layout of a struct, along with the offset of each field in declaration order
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructLayout {
+    pub layout: Layout,
+    pub offsets: Vec<usize>,
+}
+
+/// Layout of an enum. A value starts with its tag, the index of its variant in declaration order,
+/// followed by the payload of that variant. Every payload starts at the same offset, so the size is
+/// that of the largest one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnumLayout {
+    pub layout: Layout,
+    /// The tag is the smallest unsigned integer that can number every variant
+    pub tag: Layout,
+    /// Offset of the payload from the start of the value
+    pub payload: usize,
+    /// Each payload is laid out like a struct with the fields of its variant
+    pub variants: Vec<StructLayout>,
+}
+
+// Every target we support has 64 bit pointers
+const POINTER: Layout = Layout { size: 8, align: 8 };
+// Function values carry an environment pointer along with the code pointer, see `Closure`
+const FUNCTION: Layout = Layout { size: 16, align: 8 };
+
+/// Fields are laid out in order, each at the next offset that satisfies its alignment. The size
+/// is padded to a multiple of the alignment so that the struct can be stored in arrays. Generic
+/// structs are laid out for the type arguments `args`.
+pub fn struct_layout(name: Symbol, args: &[Type], env: &Environment) -> Result<StructLayout> {
+    _struct_layout(name, args, env, &mut Vec::new())
+}
+
+fn _layout(ty: &Type, env: &Environment, visiting: &mut Vec<Symbol>) -> Result<Layout> {
+    Ok(match ty {
+        Type::U8 | Type::I8 | Type::Bool => Layout::new(1, 1),
+        Type::U16 | Type::I16 => Layout::new(2, 2),
+        Type::U32 | Type::I32 | Type::F32 | Type::Char => Layout::new(4, 4),
+        Type::U64 | Type::I64 | Type::Usize | Type::Isize | Type::F64 => Layout::new(8, 8),
+        Type::String | Type::Ptr(_) => POINTER,
+        Type::Arrow(..) => FUNCTION,
+        Type::Empty | Type::Never => Layout::new(0, 1),
+        Type::Struct(name, args) if env.lookup_enum(*name).is_some() => _enum_layout(*name, args, env, visiting)?.layout,
+        Type::Struct(name, args) => _struct_layout(*name, args, env, visiting)?.layout,
+        // Only instances of generic structs have a layout
+        Type::Var(_) | Type::Hole => return Err(TypeError::new(TypeErrorKind::UnknownType).found(ty.clone())),
+    })
+}
+
+fn _struct_layout(name: Symbol, args: &[Type], env: &Environment, visiting: &mut Vec<Symbol>)
+                  -> Result<StructLayout>
+{
+    // A struct that contains itself, other than through a pointer, would be infinitely large
+    if visiting.contains(&name) {
+        return Err(TypeError::new(TypeErrorKind::Recursive).name(name));
+    }
+    let (params, fields) = env.lookup_struct(name).ok_or_else(|| TypeError::new(TypeErrorKind::UnknownType).name(name))?;
+    if params.len() != args.len() {
+        return Err(TypeError::new(TypeErrorKind::Args).name(name));
+    }
+
+    visiting.push(name);
+    let fields: Vec<_> = fields.iter().map(|(_, ty)| substitute(ty, &params, args)).collect();
+    let layout = fields_layout(&fields, env, visiting);
+    visiting.pop();
+    layout
+}
+
+/// Lays out `fields` in order, as in a struct.
+fn fields_layout(fields: &[Type], env: &Environment, visiting: &mut Vec<Symbol>) -> Result<StructLayout> {
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut size = 0;
+    let mut align = 1;
+    for ty in fields {
+        let field = _layout(ty, env, visiting)?;
+        size = align_to(size, field.align);
+        offsets.push(size);
+        size += field.size;
+        align = align.max(field.align);
+    }
+
+    Ok(StructLayout {
+        layout: Layout::new(align_to(size, align), align),
+        offsets,
+    })
+}
+
+/// Generic enums are laid out for the type arguments `args`.
+pub fn enum_layout(name: Symbol, args: &[Type], env: &Environment) -> Result<EnumLayout> {
+    _enum_layout(name, args, env, &mut Vec::new())
+}
+
+fn _enum_layout(name: Symbol, args: &[Type], env: &Environment, visiting: &mut Vec<Symbol>) -> Result<EnumLayout> {
+    // Payloads are stored inline, so the same goes for enums as for structs
+    if visiting.contains(&name) {
+        return Err(TypeError::new(TypeErrorKind::Recursive).name(name));
+    }
+    let (params, variants) = env.lookup_enum(name).ok_or_else(|| TypeError::new(TypeErrorKind::UnknownType).name(name))?;
+    if params.len() != args.len() {
+        return Err(TypeError::new(TypeErrorKind::Args).name(name));
+    }
+
+    let tag = match variants.len() {
+        0..=0x100 => Layout::new(1, 1),
+        0x101..=0x10000 => Layout::new(2, 2),
+        _ => Layout::new(4, 4),
+    };
+
+    visiting.push(name);
+    let mut payloads = Vec::with_capacity(variants.len());
+    for (_, fields) in &variants {
+        let fields: Vec<_> = fields.iter().map(|ty| substitute(ty, &params, args)).collect();
+        payloads.push(fields_layout(&fields, env, visiting)?);
+    }
+    visiting.pop();
+
+    let payload_size = payloads.iter().map(|p| p.layout.size).max().unwrap_or(0);
+    let payload_align = payloads.iter().map(|p| p.layout.align).max().unwrap_or(1);
+    let payload = align_to(tag.size, payload_align);
+    let align = tag.align.max(payload_align);
+    Ok(EnumLayout {
+        layout: Layout::new(align_to(payload + payload_size, align), align),
+        tag,
+        payload,
+        variants: payloads,
+    })
+}
+
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
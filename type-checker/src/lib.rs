@@ -1,274 +1,1122 @@
 extern crate parser;
 extern crate string_interner;
+extern crate tokenizer;
 
+mod closure;
 mod env;
 mod error;
+mod infer;
+mod layout;
+mod mono;
+mod pattern;
 
-pub use error::TypeError;
+pub use closure::Closure;
+pub use error::{TypeError, TypeErrorKind};
+pub use layout::{EnumLayout, Layout, StructLayout};
+pub use mono::Instance;
 
-use env::Environment;
+use env::{Environment, Method};
+use mono::Key;
 
-use parser::{Ast, Type};
+use parser::{walk, Arg, Arm, Ast, Binding, Clause, CompilePrimitive, IntrinsicOp, MacroError, Operands, Rewrite, Type,
+             Variant};
 use string_interner::Symbol;
+use tokenizer::Index;
 
 use std::collections::HashMap;
 
 pub type Result<T> = std::result::Result<T, TypeError>;
 
 pub fn type_check(ast: &[Ast]) -> Result<()> {
+    infer(ast).map(|_| ())
+}
+
+/// Type checks `ast` and gives it back with the types that were left out of constants and `let`
+/// bindings filled in.
+pub fn infer(ast: &[Ast]) -> Result<Vec<Ast>> {
+    let (ast, env) = check_items(ast)?;
+    // Intrinsics used by generic functions are only checked once the types are known
+    instantiate(&env)?;
+    let mut resolve = Resolve { env: &env };
+    Ok(ast.into_iter().map(|a| resolve.expr(a).expect("resolving types can't fail")).collect())
+}
+
+/// Checks every item and gives back the top level environment, which holds the uses of generic
+/// functions, along with the items with a type variable in place of every type that was left out.
+fn check_items(ast: &[Ast]) -> Result<(Vec<Ast>, Environment)> {
+    let env = Environment::default();
+    let mut holes = Holes { env: &env, vars: Vec::new() };
+    let ast: Vec<Ast> = ast.iter().map(|a| holes.expr(a.clone()).expect("adding type variables can't fail")).collect();
+    let vars = holes.vars;
+
     // Add top level definitions to env right away
     // This avoids the C problem of values needing to be declared before their usage in a file.
-    let mut bindings: HashMap<Symbol, Type> = HashMap::new();
-    let mut holes = 0;
-    for a in ast {
-        if let Ast::Define { name, ty, .. } = a {
-            if *ty == Type::Hole {
-                holes += 1;
+    for a in &ast {
+        if let Ast::Define { name, ty, .. } | Ast::Defn { name, ty, .. } = a {
+            env.define_variable(*name, ty.clone());
+        }
+    }
+    for a in &ast {
+        match a {
+            Ast::Defn { name, params, .. } if !params.is_empty() => env.define_generic(*name, params.clone()),
+            Ast::Intrinsic { op, name, params, ty, span, .. } => {
+                declare_intrinsic(*op, *name, params, ty, &env).map_err(|e| e.at(*span))?;
             }
-            bindings.insert(*name, ty.clone());
-        } else if let Ast::Defn { name, ty, .. } = a {
-            bindings.insert(*name, ty.clone());
+            _ => (),
+        }
+    }
+    declare_types(&ast, &env)?;
+    for a in &ast {
+        match a {
+            Ast::Struct { name, span, .. } => check_struct(*name, &env).map_err(|e| e.at(*span))?,
+            Ast::Enum { name, span, .. } => check_enum(*name, &env).map_err(|e| e.at(*span))?,
+            _ => (),
+        }
+    }
+    for a in &ast {
+        if let Ast::Impl { name, params, methods, span } = a {
+            define_impl(*name, params, methods, &env).map_err(|e| e.at(*span))?;
         }
     }
 
-    // We limit this loop to 20 iterations. Otherwise we might end in an infinite loop. I don't
-    // think this restriction will be necessary with a more sophisticated type checker.
-    let mut iterations = 0;
-    while holes > 0 && iterations < 20 {
-        iterations += 1;
-        'inner: for a in ast {
-            if let Ast::Define { name, ty, value } = a {
-                if *ty != Type::Hole {
-                    continue 'inner;
-                }
+    // Constants go first so that functions see what their values made of their types
+    for a in &ast {
+        if let Ast::Define { ty, value, span, .. } = a {
+            check_define(ty, value, &env).map_err(|e| e.at(*span))?;
+        }
+    }
+    for a in &ast {
+        match a {
+            // Resolved by `parser::load` before type checking
+            Ast::Include { .. } | Ast::Export(..) | Ast::Intrinsic { .. } => (),
+            Ast::Define { .. } | Ast::Struct { .. } | Ast::Enum { .. } => (),
+            Ast::Impl { name, methods, .. } => check_impl(*name, methods, &env)?,
+            Ast::Defn { name, params, ty, args, body, span, .. } => {
+                check_defn((None, *name), params, ty, args, body, &env).map_err(|e| e.at(*span))?;
+            }
+            // The parser only gives back items, but macros have to be expanded first
+            _ => return Err(TypeError::new(TypeErrorKind::Item).at(a.span())),
+        }
+    }
 
-                match &**value {
-                    Ast::Identifier(t) => {
-                        let ty = if let Some(ty) = bindings.get(t) {
-                            if *ty == Type::Hole {
-                                continue 'inner;
-                            }
-                            ty.clone()
-                        } else {
-                            return Err(TypeError::UnboundIdentifier);
-                        };
-                        holes -= 1;
-                        bindings.insert(*name, ty);
-                    }
-                    Ast::Application(v) => if let Ast::Identifier(t) = v[0] {
-                        let ty = if let Some(ty) = bindings.get(&t) {
-                            if *ty == Type::Hole {
-                                continue 'inner;
-                            }
-                            ty.clone()
-                        } else {
-                            return Err(TypeError::UnboundIdentifier);
-                        };
-                        holes -= 1;
-                        bindings.insert(*name, ty);
-                    } else {
-                        // TODO: error
-                    },
-                    // TODO: we should probably allow If and Block in constants.
-                    _ => (),
-                }
+    // Everything has been seen, so whatever is still unknown can't be inferred
+    if let Some((_, name, span)) = vars.iter().find(|(ty, _, _)| env.unresolved(ty)) {
+        return Err(TypeError::new(TypeErrorKind::Ambiguous).name(*name).at(*span));
+    }
+    for (_, instance) in env.instances() {
+        if instance.args.iter().any(|ty| env.unresolved(ty)) {
+            return Err(TypeError::new(TypeErrorKind::Ambiguous).name(instance.name));
+        }
+    }
+    Ok((ast, env))
+}
+
+/// Replaces the types that were left out of constants and `let` bindings with type variables.
+struct Holes<'a> {
+    env: &'a Environment,
+    /// Type variable of each constant or binding along with its name and span
+    vars: Vec<(Type, Symbol, Index)>,
+}
+
+impl<'a> Holes<'a> {
+    fn fill(&mut self, ty: Type, name: Symbol, span: Index) -> Type {
+        if ty != Type::Hole {
+            return ty;
+        }
+        let var = self.env.fresh_var();
+        self.vars.push((var.clone(), name, span));
+        var
+    }
+}
+
+impl<'a> Rewrite for Holes<'a> {
+    fn expr(&mut self, ast: Ast) -> std::result::Result<Ast, MacroError> {
+        Ok(match walk(self, ast)? {
+            Ast::Define { name, ty, value, span } => Ast::Define { name, ty: self.fill(ty, name, span), value, span },
+            Ast::Let { bindings, body, sequential, span } => {
+                let bindings = bindings.into_iter()
+                    .map(|b| Binding { ty: self.fill(b.ty.clone(), b.name, b.span), ..b })
+                    .collect();
+                Ast::Let { bindings, body, sequential, span }
+            }
+            ast => ast,
+        })
+    }
+}
+
+/// Replaces the type variables of constants and `let` bindings with the types inferred for them.
+struct Resolve<'a> {
+    env: &'a Environment,
+}
+
+impl<'a> Rewrite for Resolve<'a> {
+    fn expr(&mut self, ast: Ast) -> std::result::Result<Ast, MacroError> {
+        Ok(match walk(self, ast)? {
+            Ast::Define { name, ty, value, span } => Ast::Define { name, ty: self.env.resolve(&ty), value, span },
+            Ast::Let { bindings, body, sequential, span } => {
+                let bindings = bindings.into_iter().map(|b| Binding { ty: self.env.resolve(&b.ty), ..b }).collect();
+                Ast::Let { bindings, body, sequential, span }
             }
+            ast => ast,
+        })
+    }
+}
+
+/// Checks the body of a function. Bodies of generic functions are checked once, with their type
+/// parameters standing for types that are only equal to themselves.
+fn check_defn(key: Key, params: &[Symbol], ty: &Type, args: &[Arg], body: &[Ast], env: &Environment) -> Result<()> {
+    check_type(ty, env)?;
+    let fun_env = env.extend();
+    if !params.is_empty() {
+        fun_env.set_caller(key);
+    }
+    for arg in args {
+        fun_env.define_variable(arg.name, arg.ty.clone());
+    }
+    check_fun(ty.arrow_split().1, body, fun_env)
+}
+
+/// Validates the layout of a non-generic struct. Generic structs only get a layout once they are
+/// given type arguments, so only the types of their fields are checked.
+fn check_struct(name: Symbol, env: &Environment) -> Result<()> {
+    let (params, fields) = env.lookup_struct(name).unwrap();
+    if params.is_empty() {
+        layout::struct_layout(name, &[], env)?;
+    } else {
+        for (_, ty) in &fields {
+            check_type(ty, env)?;
         }
     }
+    Ok(())
+}
 
-    let env = Environment::from_hashmap(bindings);
+/// Same as `check_struct`, for the payloads of an enum
+fn check_enum(name: Symbol, env: &Environment) -> Result<()> {
+    let (params, variants) = env.lookup_enum(name).unwrap();
+    if params.is_empty() {
+        layout::enum_layout(name, &[], env)?;
+    } else {
+        for ty in variants.iter().flat_map(|(_, fields)| fields) {
+            check_type(ty, env)?;
+        }
+    }
+    Ok(())
+}
 
+/// Adds every struct and enum declared at the top level of `ast` to `env`.
+fn declare_types(ast: &[Ast], env: &Environment) -> Result<()> {
     for a in ast {
         match a {
-            // TODO
-            Ast::Include(_) => (),
-            Ast::Define { name, .. } => assert!(env.lookup_variable_type(*name).unwrap() != Type::Hole),
-            Ast::Defn { ty, args, body, .. } => {
-                let fun_env = env.extend();
-                for arg in args {
-                    fun_env.define_variable(arg.name, arg.ty.clone());
-                }
-                check_fun(ty.arrow_split().1, body, fun_env)?;
-            }
-            // These should already be prevented by the parser
-            _ => unreachable!(),
+            Ast::Struct { name, params, fields, .. } => define_struct(*name, params, fields, env)?,
+            Ast::Enum { name, params, variants, .. } => define_enum(*name, params, variants, env)?,
+            _ => (),
         }
     }
+    Ok(())
+}
+
+/// Adds the methods of `{impl (name params...) ...}` to the environment.
+fn define_impl(name: Symbol, params: &[Symbol], methods: &[Ast], env: &Environment) -> Result<()> {
+    check_type(&Type::Struct(name, params.iter().map(|p| Type::Var(*p)).collect()), env)?;
+    for method in methods {
+        if let Ast::Defn { name: method_name, params: method_params, ty, args, .. } = method {
+            let params = params.iter().chain(method_params).cloned().collect();
+            let receiver = args.first().is_some_and(|arg| string_interner::get_value(arg.name).as_deref() == Some("self"));
+            env.define_method(name, *method_name, Method { params, ty: ty.clone(), receiver });
+        }
+    }
+    Ok(())
+}
 
+fn check_impl(name: Symbol, methods: &[Ast], env: &Environment) -> Result<()> {
+    for method in methods {
+        if let Ast::Defn { name: method_name, ty, args, body, span, .. } = method {
+            let params = env.lookup_method(name, *method_name).unwrap().params;
+            check_defn((Some(name), *method_name), &params, ty, args, body, env).map_err(|e| e.at(*span))?;
+        }
+    }
     Ok(())
 }
 
+/// Type of the last expression of `body`, or `!` if any expression in it never finishes, since
+/// the rest of the block is then never reached.
 fn check_block(body: &[Ast], env: Environment) -> Result<Type> {
-    for (i, expr) in body.iter().enumerate() {
-        match expr {
-            Ast::Include(_) | Ast::Asm(_) => (),
-            Ast::Define { name, ty, value } => {
-                match &**value {
-                    Ast::Primitive(_) => if value.ty() != *ty {
-                        return Err(TypeError::Incompatible);
-                    },
-                    Ast::Identifier(s) => if let Some(ident_ty) = env.lookup_variable_type(*s) {
-                        if ident_ty != *ty {
-                            return Err(TypeError::Incompatible);
-                        }
-                    } else {
-                        return Err(TypeError::UnboundIdentifier);
-                    },
-                    Ast::Application(a) => if check_application(a, env.clone())? != *ty {
-                        return Err(TypeError::Incompatible);
-                    },
-                    _ => unreachable!(),
-                }
-                env.define_variable(*name, ty.clone());
+    let mut diverges = false;
+    let mut ty = Type::Empty;
+    for expr in body {
+        ty = match expr {
+            Ast::Include { .. } | Ast::Export(..) | Ast::Asm(..) | Ast::Macro { .. } => Type::Empty,
+            Ast::Intrinsic { op, name, params, ty, span, .. } => {
+                declare_intrinsic(*op, *name, params, ty, &env).map_err(|e| e.at(*span))?;
+                Type::Empty
             }
-            Ast::Defn { name, ty, args, body } => {
+            Ast::Define { name, ty, value, span } => {
+                check_define(ty, value, &env).map_err(|e| e.at(*span))?;
                 env.define_variable(*name, ty.clone());
-                let fun_env = env.extend();
-                for arg in args {
-                    fun_env.define_variable(arg.name, arg.ty.clone());
-                }
-                check_fun(ty.arrow_split().1, body, fun_env)?;
+                Type::Empty
             }
-            Ast::Application(a) => {
-                let ty = check_application(a, env.clone())?;
-                if i == body.len() - 1 {
-                    return Ok(ty);
-                }
+            Ast::Struct { name, params, fields, span } => {
+                define_struct(*name, params, fields, &env)?;
+                check_struct(*name, &env).map_err(|e| e.at(*span))?;
+                Type::Empty
             }
-            Ast::Primitive(_) => return Ok(expr.ty()),
-            Ast::Identifier(s) => if let Some(ident_ty) = env.lookup_variable_type(*s) {
-                return Ok(ident_ty);
-            } else {
-                return Err(TypeError::UnboundIdentifier);
-            },
-            Ast::Block(b) => {
-                let ty = check_block(b, env.extend())?;
-                if i == body.len() - 1 {
-                    return Ok(ty);
-                }
+            Ast::Enum { name, params, variants, span } => {
+                define_enum(*name, params, variants, &env)?;
+                check_enum(*name, &env).map_err(|e| e.at(*span))?;
+                Type::Empty
+            }
+            Ast::Impl { name, params, methods, span } => {
+                define_impl(*name, params, methods, &env).map_err(|e| e.at(*span))?;
+                check_impl(*name, methods, &env)?;
+                Type::Empty
             }
-            Ast::If{ predicate, consequent, alternative } => {
-                let ty = check_if(&**predicate, &**consequent, alternative, env.clone())?;
-                if i == body.len() - 1 {
-                    return Ok(ty.unwrap_or(Type::Empty));
+            Ast::Defn { name, params, ty, args, body, span, .. } => {
+                env.define_variable(*name, ty.clone());
+                if !params.is_empty() {
+                    env.define_generic(*name, params.clone());
                 }
+                check_defn((None, *name), params, ty, args, body, &env).map_err(|e| e.at(*span))?;
+                Type::Empty
             }
-        }
+            Ast::Application(a, span) => check_application(a, *span, env.clone())?,
+            Ast::Primitive(..) => check_primitive(expr)?,
+            Ast::Identifier(s, span) => match env.lookup_variable_type(*s) {
+                Some(ident_ty) => env.resolve(&ident_ty),
+                None => return Err(unbound(*s, *span, &env)),
+            },
+            Ast::Block(b, _) => check_block(b, env.extend())?,
+            Ast::If { predicate, consequent, alternative, .. } => check_if(predicate, consequent, alternative, env.clone())?,
+            Ast::Cond { clauses, alternative, span } => check_cond(clauses, alternative, *span, env.clone())?,
+            Ast::Match { value, arms, span } => check_match(value, arms, *span, env.clone())?,
+            Ast::Let { bindings, body: let_body, sequential, .. } => {
+                check_let(bindings, let_body, *sequential, env.clone())?
+            }
+            Ast::While { .. } | Ast::Loop(..) | Ast::Break(..) | Ast::Continue(..) => check_loop(expr, env.clone())?,
+            Ast::Field { .. } | Ast::Set { .. } | Ast::Subscript { .. } | Ast::Deref(..) | Ast::AddrOf(..)
+            | Ast::Cast { .. } | Ast::Lambda { .. } => check_expr(expr, env.clone())?,
+        };
+        diverges |= ty == Type::Never;
     }
 
-    Ok(Type::Empty)
+    Ok(if diverges { Type::Never } else { ty })
+}
+
+/// Checks the value of a constant against its type, which is a type variable if it was left
+/// out.
+fn check_define(ty: &Type, value: &Ast, env: &Environment) -> Result<()> {
+    check_value(value, ty, env)
 }
 
 fn check_fun(ty: Type, body: &[Ast], env: Environment) -> Result<()> {
-    let ret_ty = check_block(body, env.clone())?;
-    if ty != Type::Empty && ty != Type::Never && ret_ty != ty {
-        return Err(TypeError::Incompatible);
+    // Inline assembly leaves the result wherever the calling convention says, so the return type
+    // is taken on trust
+    if body.len() == 1 && body[0].is_asm() {
+        return Ok(());
     }
-    Ok(())
+    // A literal giving back the value takes on the return type
+    if let Some((value, rest)) = body.split_last() {
+        if ty != Type::Never && check_literal(value, &ty)? {
+            check_block(rest, env)?;
+            return Ok(());
+        }
+    }
+    let ret_ty = env.resolve(&check_block(body, env.clone())?);
+    let at_end = |e: TypeError| match body.last() {
+        Some(value) => e.at(value.span()),
+        None => e,
+    };
+    if ty == Type::Never {
+        if ret_ty != Type::Never {
+            return Err(at_end(TypeError::new(TypeErrorKind::Returns).found(ret_ty)));
+        }
+        return Ok(());
+    }
+    if ty == Type::Empty {
+        return Ok(());
+    }
+    // Such as an `if` without an `else` or a `while` loop at the end
+    if ret_ty == Type::Empty {
+        return Err(at_end(TypeError::new(TypeErrorKind::NoValue).expected(ty).found(ret_ty)));
+    }
+    // A body that never finishes satisfies any return type
+    env.unify(&ty, &ret_ty).map_err(at_end)
+}
+
+/// Error for the use of `name` where nothing of that name is in scope
+fn unbound(name: Symbol, span: Index, env: &Environment) -> TypeError {
+    TypeError::new(TypeErrorKind::UnboundIdentifier).name(name).at(span).suggestion(env.similar_name(name))
 }
 
-fn check_application(a: &[Ast], env: Environment) -> Result<Type> {
-    let app_ty = match a[0] {
-        Ast::Identifier(s) => if let Some(ty) = env.lookup_variable_type(s) {
-            ty
+fn check_application(a: &[Ast], span: Index, env: Environment) -> Result<Type> {
+    // `()` calls nothing
+    let callee = a.first().ok_or_else(|| TypeError::new(TypeErrorKind::Incompatible).found(Type::Empty).at(span))?;
+    let span = callee.span();
+    let (callee, app_ty) = match *callee {
+        Ast::Identifier(s, _) => if let Some(ty) = env.lookup_variable_type(s) {
+            if let Some(op) = env.lookup_intrinsic(s) {
+                let params = env.lookup_generic(s).unwrap_or_default();
+                return check_intrinsic_call(op, s, &params, &ty, &a[1..], env).map_err(|e| e.at(span));
+            } else if let Some(params) = env.lookup_generic(s) {
+                return check_call((None, s), &params, &ty, None, &a[1..], env).map_err(|e| e.at(span));
+            }
+            (Some(s), env.resolve(&ty))
+        } else if env.lookup_struct(s).is_some() {
+            return check_constructor(s, &a[1..], env).map_err(|e| e.at(span));
+        } else if let Some(enum_name) = env.lookup_variant(s) {
+            return check_variant(enum_name, s, &a[1..], env).map_err(|e| e.at(span));
+        } else if a.len() > 1 {
+            // `(push buf value)` calls the method `push` of `buf`
+            let receiver = check_expr(&a[1], env.clone())?;
+            return match receiver_struct(&receiver).and_then(|owner| env.lookup_method(owner, s)) {
+                Some(ref method) if method.receiver => {
+                    let owner = receiver_struct(&receiver);
+                    check_call((owner, s), &method.params, &method.ty, Some(receiver), &a[2..], env)
+                        .map_err(|e| e.at(span))
+                }
+                _ => Err(unbound(s, span, &env)),
+            };
         } else {
-            return Err(TypeError::UnboundIdentifier);
+            return Err(unbound(s, span, &env));
         },
-        Ast::Application(_) => todo!(),
-        // TODO
-        _ => unreachable!(),
+        Ast::Field { ref value, field, .. } => {
+            if let Some(ty) = check_method_call(value, field, &a[1..], &env).map_err(|e| e.at(span))? {
+                return Ok(ty);
+            }
+            (Some(field), check_field(&a[0], env.clone())?)
+        }
+        // Anything else has to evaluate to a function
+        ref callee => (None, check_expr(callee, env.clone())?),
     };
 
     if !app_ty.is_arrow() {
-        return Err(TypeError::Incompatible);
+        let error = TypeError::new(TypeErrorKind::Incompatible).found(app_ty).at(span);
+        return Err(match callee {
+            Some(name) => error.name(name),
+            None => error,
+        });
     }
 
     let (arg_tys, ret_ty) = app_ty.arrow_split();
 
     // Make sure that the number of arguments given is as expected
     if arg_tys.len() != a.len() -1 {
-        return Err(TypeError::Args);
+        let error = TypeError::new(TypeErrorKind::Args).at(span);
+        return Err(match callee {
+            Some(name) => error.name(name),
+            None => error,
+        });
     }
 
-    for j in 0..arg_tys.len() {
-        match &a[j+1] {
-            arg @ Ast::Primitive(_) => if arg.ty() != arg_tys[j] {
-                return Err(TypeError::Incompatible);
-            },
-            Ast::Identifier(s) => if let Some(ty) = env.lookup_variable_type(*s) {
-                if ty != arg_tys[j] {
-                    return Err(TypeError::Incompatible);
-                }
+    check_values(arg_tys.iter().zip(&a[1..]), callee, 0, &env)?;
+    Ok(ret_ty)
+}
+
+/// Type of an expression, with the type variables inferred so far filled in
+macro_rules! match_if {
+    ( $m:expr, $env:ident ) => {{
+        let ty = match $m {
+            Ast::If { predicate, consequent, alternative, .. } => {
+                check_if(&*predicate, &*consequent, alternative, $env.clone())?
+            }
+            Ast::Block(b, _) => check_block(&b, $env.extend())?,
+            Ast::Cond { clauses, alternative, span } => check_cond(clauses, alternative, *span, $env.clone())?,
+            Ast::Match { value, arms, span } => check_match(value, arms, *span, $env.clone())?,
+            Ast::Let { bindings, body, sequential, .. } => check_let(bindings, body, *sequential, $env.clone())?,
+            Ast::While { .. } | Ast::Loop(..) | Ast::Break(..) | Ast::Continue(..) => check_loop($m, $env.clone())?,
+            Ast::Application(a, span) => check_application(&a, *span, $env.clone())?,
+            Ast::Identifier(s, span) => if let Some(ty) = $env.lookup_variable_type(*s) {
+                ty
             } else {
-                return Err(TypeError::UnboundIdentifier);
-            },
-            Ast::Application(a2) => if check_application(a2, env.clone())? != arg_tys[j] {
-                return Err(TypeError::Incompatible);
-            },
-            Ast::Block(b) => if check_block(b, env.extend())? != arg_tys[j] {
-                return Err(TypeError::Incompatible);
+                return Err(unbound(*s, *span, &$env));
             },
-            Ast::If{ predicate, consequent, alternative } => {
-                if let Some(ty) = check_if(&**predicate, &**consequent, alternative, env.clone())? {
-                    if arg_tys[j] != ty {
-                        return Err(TypeError::Incompatible);
-                    }
-                } else {
-                    return Err(TypeError::Incompatible);
-                }
-            },
-            _ => unreachable!(),
+            Ast::Primitive(..) => check_primitive($m)?,
+            Ast::Field { .. } => check_field($m, $env.clone())?,
+            Ast::Set { target, value, .. } => check_set(target, value, $env.clone())?,
+            Ast::Subscript { .. } | Ast::Deref(..) | Ast::AddrOf(..) => check_memory($m, $env.clone())?,
+            Ast::Cast { ty, value, .. } => check_cast(ty, value, $env.clone())?,
+            Ast::Lambda { args, body, span } => check_lambda(args, body, *span, $env.clone())?,
+            _ => return Err(TypeError::new(TypeErrorKind::Incompatible).at($m.span())),
+        };
+        $env.resolve(&ty)
+    }};
+}
+
+/// Both branches of an `if` must have the same type, unless one of them never finishes. Without
+/// an `else` the `if` has no value.
+fn check_if(predicate: &Ast, consequent: &Ast, alternative: &Option<Box<Ast>>, env: Environment) -> Result<Type> {
+    let pred_ty = match_if!(predicate, env);
+    env.unify(&Type::Bool, &pred_ty).map_err(|e| e.at(predicate.span()))?;
+
+    let cons_ty = match_if!(consequent, env);
+    match alternative {
+        Some(alternative) => {
+            let alt_ty = match_if!(&**alternative, env);
+            join_arms(vec![(cons_ty, consequent.span()), (alt_ty, alternative.span())], &env)
         }
+        None => Ok(Type::Empty),
+    }
+}
+
+/// Every arm of a `cond` must have the same type, other than arms which never finish. Without an
+/// `else` there may be no arm taken, so the `cond` has no value.
+fn check_cond(clauses: &[Clause], alternative: &Option<Vec<Ast>>, span: Index, env: Environment) -> Result<Type> {
+    let mut arms = Vec::new();
+    for clause in clauses {
+        env.unify(&Type::Bool, &match_if!(&clause.test, env)).map_err(|e| e.at(clause.test.span()))?;
+        arms.push((check_block(&clause.body, env.extend())?, clause.span));
+    }
+    if let Some(alternative) = alternative {
+        let span = alternative.last().map_or(span, |value| value.span());
+        arms.push((check_block(alternative, env.extend())?, span));
+    }
+
+    let ty = join_arms(arms, &env)?;
+    if alternative.is_some() {
+        Ok(ty)
+    } else {
+        Ok(Type::Empty)
+    }
+}
+
+/// Type shared by every arm of a branch, ignoring arms which never finish. Each arm comes with
+/// the span an error about it points at.
+fn join_arms(arms: Vec<(Type, Index)>, env: &Environment) -> Result<Type> {
+    let mut ty = Type::Never;
+    for (arm, span) in arms {
+        if ty == Type::Never {
+            ty = arm;
+        } else {
+            env.unify(&ty, &arm).map_err(|e| e.at(span))?;
+        }
+    }
+    Ok(env.resolve(&ty))
+}
+
+/// The patterns of a `match` must fit the type of the value and together cover every value it can
+/// have. Arms agree on their type the same way as those of a `cond`.
+fn check_match(value: &Ast, arms: &[Arm], span: Index, env: Environment) -> Result<Type> {
+    let ty = check_known(value, env.clone())?;
+    let mut rows = Vec::with_capacity(arms.len());
+    let mut types = Vec::with_capacity(arms.len());
+    for arm in arms {
+        let scope = env.extend();
+        rows.push(vec![pattern::check_pattern(&arm.pattern, &ty, &scope)?]);
+        types.push((check_block(&arm.body, scope)?, arm.span));
+    }
+
+    if pattern::useful(&rows, &[pattern::Pat::Wild], std::slice::from_ref(&ty), &env) {
+        return Err(TypeError::new(TypeErrorKind::NonExhaustive).found(ty).at(span));
+    }
+    join_arms(types, &env)
+}
+
+/// Type of an expression that produces a value
+fn check_expr(expr: &Ast, env: Environment) -> Result<Type> {
+    Ok(match_if!(expr, env))
+}
+
+/// Type of an expression whose value is taken apart, which needs to be known by then
+fn check_known(expr: &Ast, env: Environment) -> Result<Type> {
+    let ty = check_expr(expr, env.clone())?;
+    if ty.is_var() && env.unresolved(&ty) {
+        return Err(TypeError::new(TypeErrorKind::Ambiguous).at(expr.span()));
+    }
+    Ok(ty)
+}
+
+/// Struct that methods are looked up in for a value of type `ty`
+fn receiver_struct(ty: &Type) -> Option<Symbol> {
+    match ty {
+        Type::Struct(name, _) => Some(*name),
+        Type::Ptr(ty) => match **ty {
+            Type::Struct(name, _) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Checks `(value.name args...)` as a method call, where `value` is either the name of a struct or
+/// a value to call the method on. Gives back `None` if `name` is a field instead.
+fn check_method_call(value: &Ast, name: Symbol, args: &[Ast], env: &Environment) -> Result<Option<Type>> {
+    if let Ast::Identifier(s, _) = *value {
+        if env.lookup_variable_type(s).is_none() && env.lookup_struct(s).is_some() {
+            let method = env.lookup_method(s, name)
+                .ok_or_else(|| TypeError::new(TypeErrorKind::UnknownField).name(name))?;
+            // `(Stack.push s 1)` is the same as `(s.push 1)`
+            let (receiver, args) = match args.split_first() {
+                Some((receiver, args)) if method.receiver => (Some(check_expr(receiver, env.clone())?), args),
+                _ => (None, args),
+            };
+            return check_call((Some(s), name), &method.params, &method.ty, receiver, args, env.clone()).map(Some);
+        }
+    }
+
+    let receiver = check_expr(value, env.clone())?;
+    let owner = match receiver_struct(&receiver) {
+        Some(owner) => owner,
+        None => return Ok(None),
+    };
+    if let Some((_, fields)) = env.lookup_struct(owner) {
+        if fields.iter().any(|(f, _)| *f == name) {
+            return Ok(None);
+        }
+    }
+
+    match env.lookup_method(owner, name) {
+        Some(ref method) if method.receiver => {
+            check_call((Some(owner), name), &method.params, &method.ty, Some(receiver), args, env.clone()).map(Some)
+        }
+        // Methods without `self` are called through the struct name
+        Some(_) => Err(TypeError::new(TypeErrorKind::Args).name(name)),
+        None => Err(TypeError::new(TypeErrorKind::UnknownField).name(name).found(receiver)),
+    }
+}
+
+/// Checks a call to the function or method `key` of type `ty`, inferring its type parameters
+/// `params` from the arguments. Methods called on a struct rather than a pointer to one get a
+/// pointer to it as `self`.
+fn check_call(key: Key, params: &[Symbol], ty: &Type, receiver: Option<Type>, args: &[Ast], env: Environment)
+              -> Result<Type>
+{
+    let (type_args, ret_ty) = infer_call(key.1, params, ty, receiver, args, &env)?;
+    if !params.is_empty() {
+        env.record_instance(Instance {
+            owner: key.0,
+            name: key.1,
+            args: type_args,
+        });
+    }
+    Ok(ret_ty)
+}
+
+/// Checks the arguments of a call to the function `name` of type `ty`. Every call gets its own
+/// type variables for the type parameters `params`, which are given back along with the return
+/// type.
+fn infer_call(name: Symbol, params: &[Symbol], ty: &Type, receiver: Option<Type>, args: &[Ast], env: &Environment)
+              -> Result<(Vec<Type>, Type)>
+{
+    let type_args: Vec<Type> = params.iter().map(|_| env.fresh_var()).collect();
+    let (arg_tys, ret_ty) = mono::substitute(ty, params, &type_args).arrow_split();
+    if args.len() + receiver.is_some() as usize != arg_tys.len() {
+        return Err(TypeError::new(TypeErrorKind::Args).name(name));
+    }
+
+    let mut expected = &arg_tys[..];
+    if let Some(receiver) = receiver {
+        let receiver = match receiver {
+            Type::Struct(..) => Type::Ptr(Box::new(receiver)),
+            receiver => receiver,
+        };
+        env.unify(&arg_tys[0], &receiver).map_err(|e| e.arg(0).name(name))?;
+        expected = &arg_tys[1..];
+    }
+    check_values(expected.iter().zip(args), Some(name), arg_tys.len() - expected.len(), env)?;
+    Ok((type_args, ret_ty))
+}
+
+/// Makes sure that the signature of an intrinsic fits its operation, then defines it in `env`.
+/// Generic intrinsics take a single type parameter, the type of their operands.
+fn declare_intrinsic(op: IntrinsicOp, name: Symbol, params: &[Symbol], ty: &Type, env: &Environment) -> Result<()> {
+    let (args, ret) = ty.arrow_split();
+    let intrinsic = || TypeError::new(TypeErrorKind::Intrinsic).name(name);
+    let operand = match args.first() {
+        Some(operand) if args.len() == op.arity() => operand,
+        _ => return Err(intrinsic()),
+    };
+    let expected = if op.comparisonp() { Type::Bool } else { operand.clone() };
+    if args.iter().any(|arg| arg != operand) || ret != expected {
+        return Err(intrinsic());
+    }
+    match params {
+        [] => if !operands_fit(op, operand) {
+            return Err(TypeError::new(TypeErrorKind::Operand).name(name).found(operand.clone()));
+        },
+        [param] if *operand == Type::Var(*param) => (),
+        _ => return Err(intrinsic()),
+    }
+
+    env.define_variable(name, ty.clone());
+    if !params.is_empty() {
+        env.define_generic(name, params.to_vec());
     }
+    env.define_intrinsic(name, op);
+    Ok(())
+}
 
+/// Calls of generic intrinsics are recorded like those of other generic functions, so that
+/// backends know which operand sizes they are used with.
+fn check_intrinsic_call(op: IntrinsicOp, name: Symbol, params: &[Symbol], ty: &Type, args: &[Ast], env: Environment)
+                        -> Result<Type>
+{
+    let (type_args, ret_ty) = infer_call(name, params, ty, None, args, &env)?;
+    if let Some(operand) = type_args.first() {
+        // Type parameters of a generic caller are checked once it is instantiated, and operands
+        // that are yet to be inferred once everything has been checked
+        let operand = env.resolve(operand);
+        if !operand.is_var() && !operands_fit(op, &operand) {
+            return Err(TypeError::new(TypeErrorKind::Operand).name(name).found(operand));
+        }
+        env.record_instance(Instance {
+            owner: None,
+            name,
+            args: type_args,
+        });
+    }
     Ok(ret_ty)
 }
 
-macro_rules! match_if {
-    ( $m:expr, $env:ident ) => {
-        match $m {
-            Ast::If { predicate, consequent, alternative } => {
-                if let Some(ty) = check_if(&*predicate, &*consequent, alternative, $env.clone())? {
-                    ty
-                } else {
-                    // TODO
-                    Type::Empty
-                    //return Err(TypeError::Incompatible);
+fn operands_fit(op: IntrinsicOp, ty: &Type) -> bool {
+    match op.operands() {
+        Operands::Number => is_integer(ty) || is_float(ty),
+        Operands::Integer => is_integer(ty),
+        Operands::Bits => is_integer(ty) || *ty == Type::Bool,
+        Operands::Ordered => is_integer(ty) || is_float(ty) || *ty == Type::Char || ty.is_ptr(),
+        Operands::Scalar => is_integer(ty) || is_float(ty) || *ty == Type::Char || *ty == Type::Bool || ty.is_ptr(),
+    }
+}
+
+fn define_struct(name: Symbol, params: &[Symbol], fields: &[Arg], env: &Environment) -> Result<()> {
+    let mut types = Vec::with_capacity(fields.len());
+    for field in fields {
+        if types.iter().any(|(name, _)| *name == field.name) {
+            return Err(TypeError::new(TypeErrorKind::Fields).name(field.name).at(field.span));
+        }
+        types.push((field.name, field.ty.clone()));
+    }
+    env.define_struct(name, (params.to_vec(), types));
+    Ok(())
+}
+
+fn define_enum(name: Symbol, params: &[Symbol], variants: &[Variant], env: &Environment) -> Result<()> {
+    let mut types: Vec<(Symbol, Vec<Type>)> = Vec::with_capacity(variants.len());
+    for variant in variants {
+        if types.iter().any(|(name, _)| *name == variant.name) {
+            return Err(TypeError::new(TypeErrorKind::Variants).name(variant.name).at(variant.span));
+        }
+        types.push((variant.name, variant.fields.clone()));
+    }
+    env.define_enum(name, (params.to_vec(), types));
+    Ok(())
+}
+
+/// Fields of the struct `name` with the type arguments `args` filled in
+fn struct_fields(name: Symbol, args: &[Type], env: &Environment) -> Result<Vec<(Symbol, Type)>> {
+    let (params, fields) = env.lookup_struct(name)
+        .ok_or_else(|| TypeError::new(TypeErrorKind::UnknownType).name(name))?;
+    Ok(fields.into_iter().map(|(f, ty)| (f, mono::substitute(&ty, &params, args))).collect())
+}
+
+/// Type parameters of the struct or enum `name`
+fn type_params(name: Symbol, env: &Environment) -> Option<Vec<Symbol>> {
+    env.lookup_struct(name).map(|(params, _)| params)
+        .or_else(|| env.lookup_enum(name).map(|(params, _)| params))
+}
+
+/// Makes sure that every struct named in `ty` exists and is given as many type arguments as it
+/// has type parameters.
+fn check_type(ty: &Type, env: &Environment) -> Result<()> {
+    match ty {
+        Type::Struct(name, args) => match type_params(*name, env) {
+            Some(ref params) if params.len() == args.len() => {
+                for arg in args {
+                    check_type(arg, env)?;
                 }
+            }
+            Some(_) => return Err(TypeError::new(TypeErrorKind::Args).name(*name)),
+            None => {
+                return Err(TypeError::new(TypeErrorKind::UnknownType).name(*name).suggestion(env.similar_name(*name)));
+            }
+        },
+        Type::Ptr(ty) => check_type(ty, env)?,
+        Type::Arrow(args, ret) => {
+            for arg in args {
+                check_type(arg, env)?;
+            }
+            check_type(ret, env)?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Checks `(Name (field value) ...)`, which must give every field of the struct exactly once. The
+/// type arguments of a generic struct are inferred from the values of its fields, or from where
+/// the struct is used.
+fn check_constructor(name: Symbol, args: &[Ast], env: Environment) -> Result<Type> {
+    let (params, fields) = env.lookup_struct(name).unwrap();
+    let type_args: Vec<Type> = params.iter().map(|_| env.fresh_var()).collect();
+    let mut given = Vec::with_capacity(fields.len());
+    let mut values = Vec::with_capacity(fields.len());
+    for arg in args {
+        let (field, value) = match arg {
+            Ast::Application(v, _) if v.len() == 2 => match v[0] {
+                Ast::Identifier(field, _) => (field, &v[1]),
+                _ => return Err(TypeError::new(TypeErrorKind::Fields).name(name).at(arg.span())),
             },
-            Ast::Block(b) => check_block(&b, $env.extend())?,
-            Ast::Application(a) => check_application(&a, $env.clone())?,
-            Ast::Identifier(s) => if let Some(ty) = $env.lookup_variable_type(*s) {
-                ty
-            } else {
-                return Err(TypeError::UnboundIdentifier);
-            },
-            Ast::Primitive(_) => $m.ty(),
-            _ => return Err(TypeError::Incompatible),
+            _ => return Err(TypeError::new(TypeErrorKind::Fields).name(name).at(arg.span())),
+        };
+
+        let ty = match fields.iter().find(|(f, _)| *f == field) {
+            Some((_, ty)) => ty,
+            None => return Err(TypeError::new(TypeErrorKind::UnknownField).name(field).at(arg.span())),
+        };
+        if given.contains(&field) {
+            return Err(TypeError::new(TypeErrorKind::Fields).name(field).at(arg.span()));
         }
+        given.push(field);
+        values.push((mono::substitute(ty, &params, &type_args), value));
+    }
+
+    check_values(values.iter().map(|(ty, value)| (ty, *value)), None, 0, &env)?;
+    if given.len() != fields.len() {
+        return Err(TypeError::new(TypeErrorKind::Fields).name(name));
+    }
+    Ok(env.resolve(&Type::Struct(name, type_args)))
+}
+
+/// Checks `(Variant payload...)`, inferring the type arguments of a generic enum from the payload
+/// and from where the value is used, as for `(None)`.
+fn check_variant(enum_name: Symbol, name: Symbol, args: &[Ast], env: Environment) -> Result<Type> {
+    let (params, variants) = env.lookup_enum(enum_name).unwrap();
+    let (_, fields) = variants.iter().find(|(v, _)| *v == name).unwrap();
+    if fields.len() != args.len() {
+        return Err(TypeError::new(TypeErrorKind::Args).name(name));
+    }
+
+    let type_args: Vec<Type> = params.iter().map(|_| env.fresh_var()).collect();
+    let fields: Vec<Type> = fields.iter().map(|ty| mono::substitute(ty, &params, &type_args)).collect();
+    check_values(fields.iter().zip(args), Some(name), 0, &env)?;
+    Ok(env.resolve(&Type::Struct(enum_name, type_args)))
+}
+
+/// Fields can be accessed on structs and on pointers to structs.
+fn check_field(expr: &Ast, env: Environment) -> Result<Type> {
+    let (value, field) = match expr {
+        Ast::Field { value, field, .. } => (value, *field),
+        _ => unreachable!(),
+    };
+
+    let ty = check_known(value, env.clone())?;
+    let incompatible = || TypeError::new(TypeErrorKind::Incompatible).found(ty.clone()).at(value.span());
+    let (name, args) = match ty {
+        Type::Struct(name, ref args) => (name, args.clone()),
+        Type::Ptr(ref pointee) => match **pointee {
+            Type::Struct(name, ref args) => (name, args.clone()),
+            _ => return Err(incompatible()),
+        },
+        _ => return Err(incompatible()),
     };
+
+    if env.lookup_enum(name).is_some() {
+        return Err(incompatible());
+    }
+    let fields = struct_fields(name, &args, &env)?;
+    match fields.into_iter().find(|(f, _)| *f == field) {
+        Some((_, ty)) => Ok(ty),
+        None => Err(TypeError::new(TypeErrorKind::UnknownField).name(field).found(ty).at(expr.span())),
+    }
 }
 
-fn check_if(predicate: &Ast, consequent: &Ast, alternative: &Option<Box<Ast>>, env: Environment) -> Result<Option<Type>> {
-    let pred_ty = match_if!(predicate, env);
-    if pred_ty != Type::Bool {
-        return Err(TypeError::Incompatible);
+fn check_set(target: &Ast, value: &Ast, env: Environment) -> Result<Type> {
+    let target_ty = match_if!(target, env);
+    check_value(value, &target_ty, &env)?;
+    Ok(Type::Empty)
+}
+
+/// Checks indexing, `deref` and `addr-of`. Indexing works on pointers, where `p[i]` is the `i`th
+/// element after the one `p` points to, and on strings, which give bytes.
+fn check_memory(expr: &Ast, env: Environment) -> Result<Type> {
+    match expr {
+        Ast::Subscript { value, index, .. } => {
+            let index_ty = check_known(index, env.clone())?;
+            if !is_integer(&index_ty) {
+                return Err(TypeError::new(TypeErrorKind::Incompatible).found(index_ty).at(index.span()));
+            }
+            match check_known(value, env.clone())? {
+                Type::Ptr(ty) => Ok(*ty),
+                Type::String => Ok(Type::U8),
+                ty => Err(TypeError::new(TypeErrorKind::Incompatible).found(ty).at(value.span())),
+            }
+        }
+        Ast::Deref(pointer, _) => match check_known(pointer, env)? {
+            Type::Ptr(ty) => Ok(*ty),
+            ty => Err(TypeError::new(TypeErrorKind::Incompatible).found(ty).at(pointer.span())),
+        },
+        Ast::AddrOf(place, _) => Ok(Type::Ptr(Box::new(match_if!(&**place, env)))),
+        _ => unreachable!(),
     }
+}
 
-    let cons_ty = match_if!(consequent, env);
+/// A lambda can use the variables around it, which are recorded as its captures for closure
+/// conversion.
+fn check_lambda(args: &[Arg], body: &[Ast], span: Index, env: Environment) -> Result<Type> {
+    let lambda_env = env.extend_lambda();
+    for arg in args {
+        check_type(&arg.ty, &env).map_err(|e| e.at(arg.ty_span))?;
+        lambda_env.define_variable(arg.name, arg.ty.clone());
+    }
+
+    let ret_ty = check_block(body, lambda_env.clone())?;
+    env.record_closure(Closure {
+        span,
+        captures: lambda_env.captures(),
+    });
+    Ok(Type::Arrow(args.iter().map(|arg| arg.ty.clone()).collect(), Box::new(env.resolve(&ret_ty))))
+}
+
+fn is_integer(ty: &Type) -> bool {
+    matches!(ty, Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::Usize
+                 | Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::Isize)
+}
+
+fn is_float(ty: &Type) -> bool {
+    matches!(ty, Type::F32 | Type::F64)
+}
+
+/// Whether `value` is a literal whose type depends on where it is used
+fn untyped_literal(value: &Ast) -> bool {
+    match value {
+        Ast::Primitive(CompilePrimitive::Integer(i), _) => i.suffix.is_none(),
+        Ast::Primitive(CompilePrimitive::Float(_), _) => true,
+        _ => false,
+    }
+}
+
+/// Whether the untyped literal `value` can be given the type `ty`. Integers fit integer types
+/// that can hold their value, and float types. Floats fit either float type.
+fn literal_fits(value: &Ast, ty: &Type) -> bool {
+    match value {
+        Ast::Primitive(CompilePrimitive::Integer(i), _) if i.suffix.is_none() => match ty.int_suffix() {
+            Some(suffix) => i.fits(suffix.min(), suffix.max()),
+            None => is_float(ty),
+        },
+        Ast::Primitive(CompilePrimitive::Float(_), _) => is_float(ty),
+        _ => false,
+    }
+}
+
+/// Whether the untyped literal `value` can be given the type `ty`, or an error if it is an
+/// integer too large or too small for the integer type `ty`.
+fn check_literal(value: &Ast, ty: &Type) -> Result<bool> {
+    match value {
+        Ast::Primitive(CompilePrimitive::Integer(i), span) if i.suffix.is_none() && is_integer(ty) => {
+            if literal_fits(value, ty) {
+                Ok(true)
+            } else {
+                Err(TypeError::new(TypeErrorKind::Range).expected(ty.clone()).at(*span))
+            }
+        }
+        _ => Ok(literal_fits(value, ty)),
+    }
+}
 
-    let alternative = if let Some(a) = alternative {
-        a
+/// Type of the literal `value` on its own, where untyped integers are `i32`s
+fn check_primitive(value: &Ast) -> Result<Type> {
+    let ty = value.ty();
+    check_literal(value, &ty)?;
+    Ok(ty)
+}
+
+/// Checks `value` where a value of type `expected` is wanted, which is what untyped literals
+/// then become if they fit.
+fn check_expected(value: &Ast, expected: &Type, env: Environment) -> Result<Type> {
+    if check_literal(value, expected)? {
+        Ok(expected.clone())
     } else {
-        return Ok(None);
-    };
+        check_expr(value, env)
+    }
+}
 
-    let alt_ty = match_if!(&**alternative, env);
+/// Checks `value` where a value of type `expected` is wanted.
+fn check_value(value: &Ast, expected: &Type, env: &Environment) -> Result<()> {
+    let expected = env.resolve(expected);
+    let found = check_expected(value, &expected, env.clone())?;
+    env.unify(&expected, &found).map_err(|e| e.at(value.span()))
+}
+
+/// Checks each of the values where a value of the type paired with it is wanted. Untyped literals
+/// go last so that they can take on the types the other values determined, as in `(+ x 1)` for a
+/// `u8` x. When the values are the arguments of a call to `callee`, a mismatch tells which
+/// argument it is, counting from `offset`.
+fn check_values<'a, I>(values: I, callee: Option<Symbol>, offset: usize, env: &Environment) -> Result<()>
+    where I: IntoIterator<Item = (&'a Type, &'a Ast)>
+{
+    let (literals, others): (Vec<_>, Vec<_>) = values.into_iter().enumerate()
+        .partition(|(_, (_, value))| untyped_literal(value));
+    for (i, (ty, value)) in others.into_iter().chain(literals) {
+        check_value(value, ty, env).map_err(|e| match (e.kind, callee) {
+            (TypeErrorKind::Mismatch, Some(callee)) => e.arg(i + offset).name(callee),
+            _ => e,
+        })?;
+    }
+    Ok(())
+}
 
-    if cons_ty != alt_ty {
-        // TODO: not sure what to do here.
-        return Ok(None);
-        //return Err(TypeError::Incompatible);
+/// Checks `(as ty value)`. Numbers convert to each other, widening, truncating or rounding
+/// towards zero as needed. Chars and bools convert to integers and bytes to chars. Pointers
+/// convert to other pointers and to and from pointer sized integers, and strings to byte pointers.
+fn check_cast(ty: &Type, value: &Ast, env: Environment) -> Result<Type> {
+    check_type(ty, &env)?;
+    // A literal too large for `ty` is an `i32` which is then truncated
+    let from = if literal_fits(value, ty) { ty.clone() } else { check_expr(value, env)? };
+    let number = |ty: &Type| is_integer(ty) || is_float(ty);
+    let address = |ty: &Type| matches!(ty, Type::Usize | Type::Isize);
+    let convertible = from == *ty || from == Type::Never
+        || (number(&from) && number(ty))
+        || (matches!(from, Type::Char | Type::Bool) && is_integer(ty))
+        || (from == Type::U8 && *ty == Type::Char)
+        || (from.is_ptr() && (ty.is_ptr() || address(ty)))
+        || (address(&from) && ty.is_ptr())
+        || (from == Type::String && *ty == Type::Ptr(Box::new(Type::U8)));
+    if !convertible {
+        return Err(TypeError::new(TypeErrorKind::Cast).expected(ty.clone()).found(from).at(value.span()));
+    }
+    Ok(ty.clone())
+}
+
+fn check_let(bindings: &[Binding], body: &[Ast], sequential: bool, env: Environment) -> Result<Type> {
+    let scope = env.extend();
+    for binding in bindings {
+        // Values in a plain `let` can't see any of the new bindings
+        let value_env = if sequential { scope.clone() } else { env.clone() };
+        check_type(&binding.ty, &scope).map_err(|e| e.at(binding.ty_span.unwrap_or(binding.span)))?;
+        // Bindings without a type have a type variable for it
+        check_value(&binding.value, &binding.ty, &value_env)?;
+        scope.define_variable(binding.name, binding.ty.clone());
+    }
+
+    check_block(body, scope)
+}
+
+/// Checks `while`, `loop`, `break` and `continue`.
+fn check_loop(expr: &Ast, env: Environment) -> Result<Type> {
+    match expr {
+        Ast::While { predicate, body, .. } => {
+            env.unify(&Type::Bool, &match_if!(&**predicate, env)).map_err(|e| e.at(predicate.span()))?;
+
+            let loop_env = env.extend_loop();
+            check_block(body, loop_env.clone())?;
+            // `while` has no value to give back, so neither can its breaks
+            for (ty, span) in loop_env.breaks() {
+                env.unify(&Type::Empty, &ty).map_err(|e| e.at(span))?;
+            }
+            Ok(Type::Empty)
+        }
+        Ast::Loop(body, _) => {
+            let loop_env = env.extend_loop();
+            check_block(body, loop_env.clone())?;
+            let breaks = loop_env.breaks();
+            match breaks.first() {
+                // There is no way out of the loop
+                None => Ok(Type::Never),
+                Some((ty, _)) => {
+                    for (other, span) in &breaks[1..] {
+                        env.unify(ty, other).map_err(|e| e.at(*span))?;
+                    }
+                    Ok(env.resolve(ty))
+                }
+            }
+        }
+        Ast::Break(value, span) => {
+            let ty = match value {
+                Some(value) => match_if!(&**value, env),
+                None => Type::Empty,
+            };
+            if !env.add_break(ty, *span) {
+                return Err(TypeError::new(TypeErrorKind::Incompatible).at(*span));
+            }
+            Ok(Type::Never)
+        }
+        Ast::Continue(..) => Ok(Type::Never),
+        _ => unreachable!(),
+    }
+}
+
+/// Computes the layout of every non-generic struct declared at the top level of `ast`.
+pub fn struct_layouts(ast: &[Ast]) -> Result<HashMap<Symbol, StructLayout>> {
+    let env = Environment::default();
+    declare_types(ast, &env)?;
+
+    let mut layouts = HashMap::new();
+    for a in ast {
+        if let Ast::Struct { name, params, .. } = a {
+            if params.is_empty() {
+                layouts.insert(*name, layout::struct_layout(*name, &[], &env)?);
+            }
+        }
+    }
+    Ok(layouts)
+}
+
+/// Computes the layout of every non-generic enum declared at the top level of `ast`.
+pub fn enum_layouts(ast: &[Ast]) -> Result<HashMap<Symbol, EnumLayout>> {
+    let env = Environment::default();
+    declare_types(ast, &env)?;
+
+    let mut layouts = HashMap::new();
+    for a in ast {
+        if let Ast::Enum { name, params, .. } = a {
+            if params.is_empty() {
+                layouts.insert(*name, layout::enum_layout(*name, &[], &env)?);
+            }
+        }
+    }
+    Ok(layouts)
+}
+
+/// Type checks `ast` and lists every lambda in it along with the variables it captures.
+pub fn closures(ast: &[Ast]) -> Result<Vec<Closure>> {
+    Ok(check_items(ast)?.1.closures())
+}
+
+// Past this many instances a generic function is most likely instantiating itself with ever larger
+// types, as in `(defn ((f T) ([x T])) (f (Box (value x))))`.
+const MAX_INSTANCES: usize = 256;
+
+/// Type checks `ast` and lists every instance of a generic function or method that lowering needs
+/// to emit. Uses from inside generic functions are followed through, so calling `(f 1)` where `f`
+/// calls `(g x)` gives instances of both `f` and `g` for `i32`.
+pub fn monomorphize(ast: &[Ast]) -> Result<Vec<Instance>> {
+    let (_, env) = check_items(ast)?;
+    instantiate(&env)
+}
+
+/// Finds every instance reachable from the uses outside of generic functions.
+fn instantiate(env: &Environment) -> Result<Vec<Instance>> {
+    let uses = env.instances();
+
+    let mut instances: Vec<Instance> = Vec::new();
+    let mut queue: Vec<Instance> = uses.iter()
+        .filter(|(caller, _)| caller.is_none())
+        .map(|(_, instance)| instance.clone())
+        .collect();
+    while let Some(instance) = queue.pop() {
+        if instances.contains(&instance) {
+            continue;
+        }
+        if instances.len() == MAX_INSTANCES {
+            return Err(TypeError::new(TypeErrorKind::Instances).name(instance.name));
+        }
+        if let (None, Some(op)) = (instance.owner, env.lookup_intrinsic(instance.name)) {
+            if !operands_fit(op, &instance.args[0]) {
+                return Err(TypeError::new(TypeErrorKind::Operand).name(instance.name).found(instance.args[0].clone()));
+            }
+        }
+
+        let params = match instance.owner {
+            Some(owner) => env.lookup_method(owner, instance.name).unwrap().params,
+            None => env.lookup_generic(instance.name).unwrap_or_default(),
+        };
+        for (caller, used) in &uses {
+            if *caller == Some(instance.key()) {
+                queue.push(Instance {
+                    args: used.args.iter().map(|ty| mono::substitute(ty, &params, &instance.args)).collect(),
+                    ..used.clone()
+                });
+            }
+        }
+        instances.push(instance);
     }
-    Ok(Some(cons_ty))
+    Ok(instances)
 }
@@ -0,0 +1,62 @@
+use parser::Type;
+use string_interner::{get_value, Symbol};
+
+use std::fmt::{self, Display, Formatter};
+
+/// Identifies a generic function by its name, and the struct whose impl block it is in for
+/// methods.
+pub type Key = (Option<Symbol>, Symbol);
+
+/// A generic function or method along with the types it is used with. Lowering emits one copy of
+/// the function for every instance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    /// Struct whose impl block the method is in
+    pub owner: Option<Symbol>,
+    pub name: Symbol,
+    /// Type arguments, in the order of the item's type parameters
+    pub args: Vec<Type>,
+}
+
+impl Instance {
+    pub fn key(&self) -> Key {
+        (self.owner, self.name)
+    }
+}
+
+/// Instances are named like `ArrayBuf::push<i32>`, which is also what lowering uses as the symbol
+/// name.
+impl Display for Instance {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(owner) = self.owner {
+            write!(f, "{}::", name(owner))?;
+        }
+        write!(f, "{}<", name(self.name))?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ">")
+    }
+}
+
+fn name(s: Symbol) -> String {
+    get_value(s).unwrap_or_default()
+}
+
+/// Replaces the type parameters `params` in `ty` with the matching `args`.
+pub fn substitute(ty: &Type, params: &[Symbol], args: &[Type]) -> Type {
+    match ty {
+        Type::Var(v) => match params.iter().position(|p| p == v) {
+            Some(i) => args[i].clone(),
+            None => ty.clone(),
+        },
+        Type::Ptr(ty) => Type::Ptr(Box::new(substitute(ty, params, args))),
+        Type::Struct(name, tys) => Type::Struct(*name, tys.iter().map(|t| substitute(t, params, args)).collect()),
+        Type::Arrow(tys, ret) => Type::Arrow(tys.iter().map(|t| substitute(t, params, args)).collect(),
+                                             Box::new(substitute(ret, params, args))),
+        _ => ty.clone(),
+    }
+}
@@ -0,0 +1,112 @@
+use super::{Result, TypeError, TypeErrorKind};
+use env::Environment;
+use mono::substitute;
+
+use parser::{Pattern, Type};
+use string_interner::Symbol;
+
+/// Pattern with its bindings checked, reduced to what matters for exhaustiveness
+#[derive(Clone, Debug)]
+pub enum Pat {
+    Wild,
+    /// Index of the variant and patterns for its payload
+    Variant(usize, Vec<Pat>),
+}
+
+/// Makes sure that `pattern` can match values of type `ty`, defining its bindings in `env`.
+pub fn check_pattern(pattern: &Pattern, ty: &Type, env: &Environment) -> Result<Pat> {
+    match pattern {
+        Pattern::Wildcard(_) => Ok(Pat::Wild),
+        Pattern::Binding(name, _) => {
+            env.define_variable(*name, ty.clone());
+            Ok(Pat::Wild)
+        }
+        Pattern::Variant { name, fields, span } => {
+            let incompatible = || TypeError::new(TypeErrorKind::Incompatible).found(ty.clone()).at(*span);
+            let variants = match ty {
+                Type::Struct(enum_name, args) => variants(*enum_name, args, env).ok_or_else(incompatible)?,
+                _ => return Err(incompatible()),
+            };
+            let i = variants.iter().position(|(v, _)| v == name)
+                .ok_or_else(|| TypeError::new(TypeErrorKind::UnknownVariant).name(*name).at(*span))?;
+            let payload = &variants[i].1;
+            if payload.len() != fields.len() {
+                return Err(TypeError::new(TypeErrorKind::Args).name(*name).at(*span));
+            }
+
+            let mut pats = Vec::with_capacity(fields.len());
+            for (field, ty) in fields.iter().zip(payload) {
+                pats.push(check_pattern(field, ty, env)?);
+            }
+            Ok(Pat::Variant(i, pats))
+        }
+    }
+}
+
+/// Variants of the enum `name` with the type arguments `args` filled in, if it is an enum
+fn variants(name: Symbol, args: &[Type], env: &Environment) -> Option<Vec<(Symbol, Vec<Type>)>> {
+    let (params, variants) = env.lookup_enum(name)?;
+    Some(variants.into_iter()
+         .map(|(v, tys)| (v, tys.iter().map(|ty| substitute(ty, &params, args)).collect()))
+         .collect())
+}
+
+/// Whether some value of the types `tys` is matched by `q` but by none of the `rows`. A match is
+/// exhaustive if a wildcard is not useful after all of its arms. This is the algorithm from
+/// Maranget's "Warnings for pattern matching".
+pub fn useful(rows: &[Vec<Pat>], q: &[Pat], tys: &[Type], env: &Environment) -> bool {
+    if q.is_empty() {
+        return rows.is_empty();
+    }
+
+    let variants = match &tys[0] {
+        Type::Struct(name, args) => variants(*name, args, env),
+        _ => None,
+    };
+    let variants = match variants {
+        Some(variants) => variants,
+        // Only wildcards match values of other types
+        None => return useful(&default(rows), &q[1..], &tys[1..], env),
+    };
+
+    // Checks the rows which match the variant `i` against `fields` followed by the rest of `q`
+    let useful_variant = |i: usize, fields: Vec<Pat>| {
+        let payload = &variants[i].1;
+        let q: Vec<_> = fields.into_iter().chain(q[1..].iter().cloned()).collect();
+        let tys: Vec<_> = payload.iter().chain(&tys[1..]).cloned().collect();
+        useful(&specialize(rows, i, payload.len()), &q, &tys, env)
+    };
+
+    match &q[0] {
+        Pat::Variant(i, fields) => useful_variant(*i, fields.clone()),
+        Pat::Wild => {
+            let complete = (0..variants.len())
+                .all(|i| rows.iter().any(|row| matches!(row[0], Pat::Variant(j, _) if i == j)));
+            if complete {
+                (0..variants.len()).any(|i| useful_variant(i, vec![Pat::Wild; variants[i].1.len()]))
+            } else {
+                useful(&default(rows), &q[1..], &tys[1..], env)
+            }
+        }
+    }
+}
+
+/// Rows that match the variant `i`, with the first pattern replaced by patterns for its `n` fields
+fn specialize(rows: &[Vec<Pat>], i: usize, n: usize) -> Vec<Vec<Pat>> {
+    rows.iter().filter_map(|row| {
+        let fields = match &row[0] {
+            Pat::Variant(j, fields) if *j == i => fields.clone(),
+            Pat::Variant(..) => return None,
+            Pat::Wild => vec![Pat::Wild; n],
+        };
+        Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+    }).collect()
+}
+
+/// Rows whose first pattern matches anything, without that pattern
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
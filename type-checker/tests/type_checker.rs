@@ -0,0 +1,610 @@
+extern crate parser;
+extern crate string_interner;
+extern crate tokenizer;
+extern crate type_checker;
+
+use parser::{Ast, Type};
+use tokenizer::{SourceMap, Tokenizer};
+use type_checker::{TypeError, TypeErrorKind};
+
+use std::fs;
+use std::path::Path;
+
+fn parse(input: &str) -> Vec<Ast> {
+    let tokens = Tokenizer::tokenize(input).unwrap();
+    parser::parse(tokens, input).unwrap()
+}
+
+fn check(input: &str) -> Result<(), TypeError> {
+    type_checker::type_check(&parse(input))
+}
+
+/// Declarations the tests share, after the generic arithmetic and comparisons of the core library
+const PRELUDE: &str = "
+(defn (len ([s string]) usize) 0usize)
+(defn (poll ()) (poll))
+(defn (exit ([code i32]) !) (exit code))
+(defn (byte () u8) 1u8)
+(defn (scale ([x f32]) f32) (* x 2))
+(defn (apply ([f (fn (i32) i32)] [x i32]) i32) (f x))
+
+(struct Point [x i32] [y i32])
+(struct Packed (a u8) (b (ptr Point)) (c u16))
+(struct Pixel [r u8] [g u8] [b u8])
+
+(struct (Pair A B) [a A] [b B])
+(defn ((first A B) ([p (Pair A B)]) A) p.a)
+(defn ((twice T) ([x T]) (Pair T T)) (Pair (a x) (b x)))
+(defn ((nested T) ([x T]) T) (first (twice x)))
+
+(struct (Stack T) [top T] [size i32])
+{impl (Stack T)
+  (defn (peek (self) T) self.top)
+  (defn (push (self [value T])) (set! self.top value) (set! self.size (+ self.size 1)))
+  (defn (twice (self [value T])) (self.push value) (push self value))
+  (defn (empty () i32) 0)}
+
+(enum Token LeftParen RightParen (Number i32))
+(enum Two (Both Token Token) Nothing)
+(enum (Option T) None (Some T))
+(struct Holder [o (Option bool)])
+(defn (unwrap-or ([o (Option i32)] [d i32]) i32) (match o ((Some x) x) (_ d)))
+";
+
+fn prelude() -> String {
+    include_str!("../../libs/core/intrinsics.inc").to_string() + PRELUDE
+}
+
+fn check_with_prelude(input: &str) -> Result<(), TypeError> {
+    check(&(prelude() + input))
+}
+
+fn kind<T>(result: Result<T, TypeError>) -> Result<T, TypeErrorKind> {
+    result.map_err(|e| e.kind)
+}
+
+/// Expected and found types of a mismatch
+fn mismatch<T>(result: Result<T, TypeError>) -> Option<(Type, Type)> {
+    match result {
+        Err(TypeError { kind: TypeErrorKind::Mismatch, expected: Some(expected), found: Some(found), .. }) => {
+            Some((*expected, *found))
+        }
+        _ => None,
+    }
+}
+
+/// Type an integer literal was too large or too small for
+fn range<T>(result: Result<T, TypeError>) -> Option<Type> {
+    match result {
+        Err(TypeError { kind: TypeErrorKind::Range, expected: Some(expected), .. }) => Some(*expected),
+        _ => None,
+    }
+}
+
+/// Message of the error in `input` and the text it points at
+fn error(input: &str) -> (String, String) {
+    let e = check(input).unwrap_err();
+    let span = e.span.expect("error without a span");
+    (e.to_string(), input[span.start()..span.end()].to_string())
+}
+
+/// Loads `path` and everything it includes the way the compiler does, then type checks it.
+fn check_library(path: &str) -> Result<(), TypeError> {
+    let libs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../libs");
+    let mut sources = SourceMap::new();
+    let file = sources.add_file(path.into(), fs::read_to_string(libs.join(path)).unwrap());
+    let ast = parser::load(file, &mut sources, &[libs]).unwrap();
+    type_checker::type_check(&parser::expand(ast, &mut sources).unwrap())
+}
+
+fn symbol(name: &str) -> string_interner::Symbol {
+    string_interner::get_symbol(name.into())
+}
+
+#[test]
+fn libraries() {
+    check_library("core/intrinsics.inc").unwrap();
+    check_library("core/macros.inc").unwrap();
+    check_library("core/option.inc").unwrap();
+    check_library("unix/lib.inc").unwrap();
+}
+
+#[test]
+#[ignore = "arraybuf.inc uses constructs the language doesn't have yet, such as fields of self in scope in methods"]
+fn arraybuf() {
+    check_library("collections/arraybuf.inc").unwrap();
+}
+
+#[test]
+fn let_bindings() {
+    check_with_prelude("(defn (f ([a i32]) i32) (let ([x i32 (+ a 1)] [s \"hi\"]) (+ x a)))").unwrap();
+    check_with_prelude("(defn (f () usize) (let ([s \"hi\"]) (len s)))").unwrap();
+    check_with_prelude("(defn (f () i32) (let* ([x 1] [y (+ x 1)]) y))").unwrap();
+    check_with_prelude("(defn (f ([a i32]) i32) (+ 1 (let ([x a]) x)))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f () i32) (let ([x usize 1]) x))")),
+               Some((Type::I32, Type::Usize)));
+    assert_eq!(mismatch(check_with_prelude("(defn (f () usize) (let ([x 1]) x))")),
+               Some((Type::Usize, Type::I32)));
+}
+
+#[test]
+fn let_scope() {
+    // Bindings are only visible in the body, and only to later bindings with `let*`
+    assert_eq!(kind(check_with_prelude("(defn (f () i32) (let ([x 1] [y (+ x 1)]) y))")),
+               Err(TypeErrorKind::UnboundIdentifier));
+    assert_eq!(kind(check_with_prelude("(defn (f () i32) (let ([x 1]) x) x)")),
+               Err(TypeErrorKind::UnboundIdentifier));
+}
+
+#[test]
+fn loops() {
+    check_with_prelude("(defn (f ([n i32])) (while (< n 10) (poll) (continue)))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) i32) (loop (if (< n 1) (break n)) (poll)))").unwrap();
+    check_with_prelude("(defn (f ()) (loop (while #t (break)) (break)))").unwrap();
+    check_with_prelude("(defn (f () i32) (+ 1 (loop (break 2))))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f ()) (while 1 (poll)))")), Some((Type::Bool, Type::I32)));
+}
+
+#[test]
+fn loop_values() {
+    // Without a break the loop never finishes, which satisfies any return type
+    check_with_prelude("(defn (f () i32) (loop (poll)))").unwrap();
+    check_with_prelude("(defn (f () !) (loop (poll)))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f () i32) (loop (break)))")), Err(TypeErrorKind::NoValue));
+    assert_eq!(mismatch(check_with_prelude("(defn (f () i32) (loop (if #t (break 1) (break #t))))")),
+               Some((Type::I32, Type::Bool)));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ()) (while #t (break 1)))")), Some((Type::Empty, Type::I32)));
+}
+
+#[test]
+fn cond() {
+    check_with_prelude("(defn (f ([n i32]) i32) (cond ((< n 0) 0) ((< n 10) (+ n 1) n) (else 10)))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) i32) (+ 1 (cond ((< n 0) (exit 1)) (else n))))").unwrap();
+    check_with_prelude("(defn (f ([n i32])) (cond ((< n 0) (exit 1)) ((< n 10) (+ n 1))))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([n i32]) i32) (cond (n 0) (else 1)))")),
+               Some((Type::Bool, Type::I32)));
+    // Without an else there is no value
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) i32) (cond ((< n 0) 0)))")), Err(TypeErrorKind::NoValue));
+}
+
+#[test]
+fn cond_clause_types() {
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([n i32]) i32) (cond ((< n 0) 0) (else #f)))")),
+               Some((Type::I32, Type::Bool)));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([n i32])) (cond ((< n 0) 0) ((< n 1) \"one\")))")),
+               Some((Type::I32, Type::String)));
+}
+
+#[test]
+fn if_branches() {
+    // Both branches of an `if` have the same type, unless one of them never finishes
+    check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) 0 n))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) (exit 1) n))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) i32) (+ 1 (if (< n 0) n (exit 1))))").unwrap();
+    check_with_prelude("(defn (f ([n i32])) (if (< n 0) (exit 1)))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) 0 #f))")),
+               Some((Type::I32, Type::Bool)));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([n i32])) (if (< n 0) 0 \"zero\"))")),
+               Some((Type::I32, Type::String)));
+}
+
+#[test]
+fn unreachable_code() {
+    // Nothing after a call to a function that never returns is reached
+    check_with_prelude("(defn (f ([n i32]) i32) (exit n) (len \"unreachable\"))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) (exit 1)) n)").unwrap();
+}
+
+#[test]
+fn never_returning_functions() {
+    check_with_prelude("(defn (f () !) (exit 1))").unwrap();
+    check_with_prelude("(defn (f ([n i32]) !) (if (< n 0) (exit 1) (exit 2)))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) !) (if (< n 0) (exit 1)))")), Err(TypeErrorKind::Returns));
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) !) n)")), Err(TypeErrorKind::Returns));
+    assert_eq!(kind(check_with_prelude("(defn (f () !) (loop (break)))")), Err(TypeErrorKind::Returns));
+}
+
+#[test]
+fn missing_values() {
+    // Every path of a function with a return type gives back a value
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) 1))")), Err(TypeErrorKind::NoValue));
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) i32) (while (< n 0) (exit 1)))")),
+               Err(TypeErrorKind::NoValue));
+    let error = check_with_prelude("(defn (f ([n i32]) i32) (if (< n 0) 1))").unwrap_err();
+    assert_eq!(error.to_string(), "Function can finish without giving back a value, expected `i32`, found `()`");
+}
+
+#[test]
+fn struct_fields() {
+    check_with_prelude("(defn (f ([p Point]) i32) (+ p.x p.y))").unwrap();
+    check_with_prelude("(defn (f ([p (ptr Packed)]) i32) p.b.y)").unwrap();
+    check_with_prelude("(defn (f ([p Point])) (set! p.x 3) (let ([q (Point (x p.y) (y 0))]) (set! q p)))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f ([p Point]) i32) p.z)")), Err(TypeErrorKind::UnknownField));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([p Point]) bool) p.x)")), Some((Type::Bool, Type::I32)));
+    assert_eq!(kind(check_with_prelude("(defn (f ([p i32]) i32) p.x)")), Err(TypeErrorKind::Incompatible));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([p Point])) (set! p.x #t))")), Some((Type::I32, Type::Bool)));
+    assert_eq!(kind(check_with_prelude("(defn (f ([p Pt])))")), Err(TypeErrorKind::UnknownType));
+}
+
+#[test]
+fn struct_constructors() {
+    check_with_prelude("(defn (f () Point) (Point (y 2) (x (+ 1 1))))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f () Point) (Point (x 1)))")), Err(TypeErrorKind::Fields));
+    assert_eq!(kind(check_with_prelude("(defn (f () Point) (Point (x 1) (x 1) (y 2)))")), Err(TypeErrorKind::Fields));
+    assert_eq!(kind(check_with_prelude("(defn (f () Point) (Point (x 1) (z 2)))")), Err(TypeErrorKind::UnknownField));
+}
+
+#[test]
+fn recursive_structs() {
+    assert_eq!(kind(check("(struct List [next List])")), Err(TypeErrorKind::Recursive));
+    check("(struct List [value i32] [next (ptr List)])").unwrap();
+}
+
+#[test]
+fn struct_layout() {
+    let layouts = type_checker::struct_layouts(&parse("
+        (struct Inner [a u8] [b u32])
+        (struct Outer [a u8] [inner Inner] [b u16] [c (ptr u8)] [d bool])")).unwrap();
+
+    let inner = &layouts[&symbol("Inner")];
+    assert_eq!((inner.layout.size, inner.layout.align), (8, 4));
+    assert_eq!(inner.offsets, vec![0, 4]);
+
+    let outer = &layouts[&symbol("Outer")];
+    assert_eq!((outer.layout.size, outer.layout.align), (32, 8));
+    assert_eq!(outer.offsets, vec![0, 4, 12, 16, 24]);
+}
+
+#[test]
+fn generics() {
+    check_with_prelude("(defn (f () i32) (first (Pair (a 1) (b \"s\"))))").unwrap();
+    check_with_prelude("(defn (f () string) (let ([p (Pair string i32) (Pair (a \"s\") (b 1))]) (first p)))").unwrap();
+    check_with_prelude("(defn (f () bool) (nested #t))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f () string) (first (Pair (a 1) (b 2))))")),
+               Some((Type::String, Type::I32)));
+    assert_eq!(kind(check_with_prelude("(defn (f ([p (Pair i32)])))")), Err(TypeErrorKind::Args));
+}
+
+#[test]
+fn type_parameters() {
+    // Type parameters are only equal to themselves inside a generic body
+    assert_eq!(mismatch(check("(defn ((g T) ([x T]) i32) x)")), Some((Type::I32, Type::Var(symbol("T")))));
+}
+
+#[test]
+fn generic_returns() {
+    // Type arguments can also come from where the value of a call is used
+    check("(defn ((none T) () T) (none))\n(defn (f () i32) (none))").unwrap();
+    assert_eq!(kind(check("(defn ((none T) () T) (none))\n(defn (f ()) (none))")), Err(TypeErrorKind::Ambiguous));
+}
+
+#[test]
+fn methods() {
+    check_with_prelude("(defn (f ([s (Stack i32)]) i32) (s.push 1) (push s 2) (s.peek))").unwrap();
+    check_with_prelude("(defn (f ([s (ptr (Stack string))]) string) (s.twice \"a\") (peek s))").unwrap();
+    check_with_prelude("(defn (f ([s (Stack i32)]) i32) (Stack.peek s))").unwrap();
+    // Fields are found before methods
+    check_with_prelude("(defn (f ([s (Stack i32)]) i32) s.size)").unwrap();
+}
+
+#[test]
+fn method_errors() {
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([s (Stack i32)])) (s.push #t))")), Some((Type::I32, Type::Bool)));
+    assert_eq!(kind(check_with_prelude("(defn (f ([s (Stack i32)])) (s.pop))")), Err(TypeErrorKind::UnknownField));
+    assert_eq!(kind(check_with_prelude("(defn (f ([s (Stack i32)])) (s.empty))")), Err(TypeErrorKind::Args));
+    // Nothing says which `Stack` is meant
+    assert_eq!(kind(check_with_prelude("(defn (f () i32) (Stack.empty))")), Err(TypeErrorKind::Ambiguous));
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32])) (push n 1))")), Err(TypeErrorKind::UnboundIdentifier));
+    assert_eq!(kind(check_with_prelude("{impl Missing (defn (f ()))}")), Err(TypeErrorKind::UnknownType));
+}
+
+#[test]
+fn instances() {
+    let ast = parse(&(prelude() + "
+(defn ((fill T) ([s (Stack T)] [v T])) (s.twice v))
+(defn (main ([s (Stack u8)])) (fill s 1u8))"));
+    // Leaving out the instances the prelude needs for itself
+    let mut names: Vec<_> = type_checker::monomorphize(&ast).unwrap().iter()
+        .map(|i| i.to_string())
+        .filter(|name| name.ends_with("<u8>"))
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["Stack::push<u8>", "Stack::twice<u8>", "fill<u8>"]);
+
+    // Instances that keep growing would never end
+    let ast = parse("(struct (Box T) [value T])\n(defn ((f T) ([x T])) (f (Box (value x))))\n(defn (main ()) (f 1))");
+    assert_eq!(kind(type_checker::monomorphize(&ast)), Err(TypeErrorKind::Instances));
+}
+
+#[test]
+fn enums() {
+    check_with_prelude("(defn (f () i32) (unwrap-or (Some 1) 2))").unwrap();
+    check_with_prelude("(defn (f () Two) (Both (Number 1) (LeftParen)))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f () Token) (Number #t))")), Some((Type::I32, Type::Bool)));
+    assert_eq!(kind(check("(enum E A A)")), Err(TypeErrorKind::Variants));
+}
+
+#[test]
+fn matches() {
+    check_with_prelude("(defn (f ([t Token]) i32) (match t ((Number n) n) ((LeftParen) 1) (_ 2)))").unwrap();
+    check_with_prelude("(defn (f ([t Token]) i32) (match t ((Number n) n) ((LeftParen) 1) ((RightParen) 2)))").unwrap();
+    check_with_prelude("(defn (f ([w Two]) i32) (match w ((Both (Number n) _) n) ((Both _ _) 0) ((Nothing) 1)))").unwrap();
+    // An arm that never finishes doesn't need to agree with the others
+    check_with_prelude("(defn (f ([o (Option bool)]) bool) (match o ((Some b) b) ((None) (loop))))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([t Token]) i32) (match t ((Number n) n) (_ #t)))")),
+               Some((Type::I32, Type::Bool)));
+}
+
+#[test]
+fn exhaustiveness() {
+    assert_eq!(kind(check_with_prelude("(defn (f ([t Token]) i32) (match t ((Number n) n) ((LeftParen) 1)))")),
+               Err(TypeErrorKind::NonExhaustive));
+    assert_eq!(kind(check_with_prelude("
+(defn (f ([w Two]) i32) (match w ((Both (Number _) _) 0) ((Both _ (LeftParen)) 0) ((Nothing) 0)))")),
+               Err(TypeErrorKind::NonExhaustive));
+}
+
+#[test]
+fn pattern_errors() {
+    assert_eq!(kind(check_with_prelude("(defn (f ([t Token]) i32) (match t ((Some x) 1) (_ 1)))")),
+               Err(TypeErrorKind::UnknownVariant));
+    assert_eq!(kind(check_with_prelude("(defn (f ([t Token]) i32) (match t ((Number) 1) (_ 1)))")),
+               Err(TypeErrorKind::Args));
+    assert_eq!(kind(check_with_prelude("(defn (f ([n i32]) i32) (match n ((Number x) x) (_ 1)))")),
+               Err(TypeErrorKind::Incompatible));
+}
+
+#[test]
+fn enum_layout() {
+    // The payloads of an enum are stored inline like the fields of a struct
+    let ast = parse("(enum Shape (Point) (Circle u8 u32) (Line u64 u8))\n(struct S [shape Shape] [tag u8])");
+    let shape = &type_checker::enum_layouts(&ast).unwrap()[&symbol("Shape")];
+    assert_eq!((shape.layout.size, shape.layout.align), (24, 8));
+    assert_eq!((shape.tag.size, shape.payload), (1, 8));
+    assert_eq!(shape.variants[1].offsets, vec![0, 4]);
+    let s = &type_checker::struct_layouts(&ast).unwrap()[&symbol("S")];
+    assert_eq!(s.offsets, vec![0, 24]);
+}
+
+#[test]
+fn recursive_enums() {
+    assert_eq!(kind(check("(enum List Nil (Cons i32 List))")), Err(TypeErrorKind::Recursive));
+    check("(enum List Nil (Cons i32 (ptr List)))").unwrap();
+}
+
+#[test]
+fn memory() {
+    check_with_prelude("(defn (f ([p (ptr i32)] [n usize]) i32) (set p[n] (+ p[0] 1)) (deref p))").unwrap();
+    check_with_prelude("(defn (f ([s string]) u8) s[0])").unwrap();
+    check_with_prelude("(defn (f ([pt Point]) (ptr i32)) (let ([q (addr-of pt)]) (set q.y 1) (set (deref q) pt) (addr-of q.x)))").unwrap();
+    check_with_prelude("(defn (f ([n i32])) (set n (+ n 1)))").unwrap();
+    check_with_prelude("(defn (f ([grid (ptr (ptr u8))]) u8) grid[1][2])").unwrap();
+}
+
+#[test]
+fn memory_errors() {
+    assert_eq!(kind(check("(defn (f ([p (ptr i32)]) i32) p[#t])")), Err(TypeErrorKind::Incompatible));
+    assert_eq!(kind(check("(defn (f ([n i32]) i32) n[0])")), Err(TypeErrorKind::Incompatible));
+    assert_eq!(kind(check("(defn (f ([n i32]) i32) (deref n))")), Err(TypeErrorKind::Incompatible));
+    assert_eq!(mismatch(check("(defn (f ([p (ptr i32)])) (set p[0] #t))")), Some((Type::I32, Type::Bool)));
+    assert_eq!(mismatch(check("(defn (f ([n i32]) (ptr u8)) (addr-of n))")),
+               Some((Type::Ptr(Box::new(Type::U8)), Type::Ptr(Box::new(Type::I32)))));
+}
+
+#[test]
+fn lambdas() {
+    check_with_prelude("(defn (f () i32) (apply (lambda ([x i32]) (+ x 1)) 2))").unwrap();
+    check_with_prelude("(defn (inc ([x i32]) i32) (+ x 1))\n(defn (f () i32) (apply inc 2))").unwrap();
+    check_with_prelude("(defn (adder ([n i32]) (fn (i32) i32)) (lambda ([x i32]) (+ x n)))\n(defn (f () i32) ((adder 1) 2))").unwrap();
+    check_with_prelude("(defn (f ([b bool]) i32) ((if b (lambda ([x i32]) x) (lambda ([x i32]) 0)) 1))").unwrap();
+    check_with_prelude("(defn (f () (fn ())) (lambda ()))").unwrap();
+}
+
+#[test]
+fn lambda_errors() {
+    let arrow = |arg, ret| Type::Arrow(vec![arg], Box::new(ret));
+    assert_eq!(mismatch(check_with_prelude("(defn (f () i32) (apply (lambda ([x bool]) 1) 2))")),
+               Some((arrow(Type::I32, Type::I32), arrow(Type::Bool, Type::I32))));
+    assert_eq!(mismatch(check_with_prelude("(defn (f () i32) (apply (lambda ([x i32]) #t) 2))")),
+               Some((arrow(Type::I32, Type::I32), arrow(Type::I32, Type::Bool))));
+    assert_eq!(kind(check("(defn (f () i32) ((lambda ([x i32]) x)))")), Err(TypeErrorKind::Args));
+    assert_eq!(kind(check("(defn (f ([n i32]) i32) (n 1))")), Err(TypeErrorKind::Incompatible));
+    assert_eq!(kind(check("(defn (f () i32) ((lambda () y)))")), Err(TypeErrorKind::UnboundIdentifier));
+}
+
+#[test]
+fn closures() {
+    let ast = parse(&(prelude() + "
+(define K 1)
+(defn (f ([a i32] [b i32]) i32)
+  (let ([c 2])
+    ((lambda ([x i32]) (+ ((lambda () (+ b c))) (+ x (+ K a)))) 1)))
+(defn (g () (fn () i32)) (lambda () (let ([y 1]) y)))"));
+    let closures = type_checker::closures(&ast).unwrap();
+    let names = |c: &type_checker::Closure| -> Vec<String> {
+        c.captures.iter().map(|(n, _)| string_interner::get_value(*n).unwrap()).collect()
+    };
+    // Inner lambdas are checked, and so recorded, first
+    assert_eq!(closures.len(), 3);
+    assert_eq!(names(&closures[0]), vec!["b", "c"]);
+    assert_eq!(names(&closures[1]), vec!["b", "c", "a"]);
+    assert!(!closures[2].capturesp());
+}
+
+#[test]
+fn intrinsics() {
+    check_with_prelude("(defn (f ([a i32] [b i32]) bool) (< (+ a (* b 2)) (% a 3)))").unwrap();
+    check_with_prelude("(defn (f ([a u8]) u8) (bit-and (<< a 1u8) (not a)))").unwrap();
+    check_with_prelude("(defn (f ([a bool] [p (ptr i32)]) bool) (bit-or a (>= p p)))").unwrap();
+    check_with_prelude("(defn ((sum T) ([a T] [b T]) T) (+ a b))\n(defn (f () usize) (sum 1usize 2usize))").unwrap();
+    // A variable can still be called `+` without turning into an intrinsic
+    check_with_prelude("(defn (f ([+ (fn (i32) i32)]) i32) (+ 1))").unwrap();
+}
+
+#[test]
+fn intrinsic_operands() {
+    assert_eq!(kind(check_with_prelude("(defn (f () bool) (+ #t #f))")), Err(TypeErrorKind::Operand));
+    assert_eq!(mismatch(check_with_prelude("(defn (f () i32) (+ 1 2u8))")), Some((Type::I32, Type::U8)));
+    assert_eq!(kind(check_with_prelude("(defn ((sum T) ([a T] [b T]) T) (+ a b))\n(defn (f () bool) (sum #t #f))")),
+               Err(TypeErrorKind::Operand));
+}
+
+#[test]
+fn intrinsic_signatures() {
+    assert_eq!(kind(check("(#intrinsic lt (less ([a i32] [b i32]) i32))")), Err(TypeErrorKind::Intrinsic));
+    assert_eq!(kind(check("(#intrinsic not (neg ([a i32] [b i32]) i32))")), Err(TypeErrorKind::Intrinsic));
+    assert_eq!(kind(check("(#intrinsic add (cat ([a string] [b string]) string))")), Err(TypeErrorKind::Operand));
+}
+
+#[test]
+fn literal_types() {
+    // Literals take on the type that is expected of them
+    check_with_prelude("(defn (f ([x u8]) u8) (+ x 1))").unwrap();
+    check_with_prelude("(defn (f ([x u8]) bool) (< 1 x))").unwrap();
+    check_with_prelude("(defn (f () Pixel) (Pixel (r 255) (g 0) (b 7)))").unwrap();
+    check_with_prelude("(defn (f () i64) (let ([x i64 1] [y f64 2.5]) (set y 1) x))").unwrap();
+    check_with_prelude("(defn (f () f32) (scale 1.5))").unwrap();
+    check_with_prelude("(defn (f () u16) 7)").unwrap();
+    check_with_prelude("(define (LIMIT u64) 100)").unwrap();
+    check_with_prelude("(defn (f () bool) (= #'a' #'b'))").unwrap();
+
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([x u8]) u8) (+ x 1.5))")), Some((Type::U8, Type::F64)));
+    assert_eq!(mismatch(check_with_prelude("(defn (f ([x u8] [y i32]) u8) (+ x y))")), Some((Type::U8, Type::I32)));
+    assert_eq!(kind(check_with_prelude("(defn (f ([x f64]) f64) (% x 2))")), Err(TypeErrorKind::Operand));
+}
+
+#[test]
+fn literal_ranges() {
+    // Unsuffixed literals are range checked against the type they are given, i32 by default
+    check_with_prelude("(define (LIMIT u64) 5000000000)").unwrap();
+    check_with_prelude("(defn (f () i64) -5000000000)").unwrap();
+    check_with_prelude("(defn (f ([x u64]) u64) (+ x 5000000000))").unwrap();
+
+    assert_eq!(range(check_with_prelude("(defn (f () Pixel) (Pixel (r 256) (g 0) (b 0)))")), Some(Type::U8));
+    assert_eq!(range(check_with_prelude("(defn (f () u8) 256)")), Some(Type::U8));
+    assert_eq!(range(check_with_prelude("(defn (f ([x u16]) u16) (+ x -1))")), Some(Type::U16));
+    assert_eq!(range(check_with_prelude("(define LIMIT 5000000000)")), Some(Type::I32));
+}
+
+#[test]
+fn casts() {
+    // Casts convert between numbers, and between pointers and addresses
+    check_with_prelude("(defn (f ([x i64]) u8) (as u8 x))").unwrap();
+    check_with_prelude("(defn (f ([x f64]) i32) (as i32 (* x 2.5)))").unwrap();
+    check_with_prelude("(defn (f ([c char]) u32) (as u32 c))").unwrap();
+    check_with_prelude("(defn (f ([b u8]) char) (as char b))").unwrap();
+    check_with_prelude("(defn (f ([p (ptr u8)]) (ptr Pixel)) (as (ptr Pixel) (as usize p)))").unwrap();
+    check_with_prelude("(defn (f () (ptr u8)) (as (ptr u8) \"text\"))").unwrap();
+    // A literal too large for the target stays an i32, which is then truncated
+    check_with_prelude("(defn (f () u8) (as u8 300))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f ([x u32]) char) (as char x))")), Err(TypeErrorKind::Cast));
+    assert_eq!(kind(check_with_prelude("(defn (f ([x i32]) bool) (as bool x))")), Err(TypeErrorKind::Cast));
+    assert_eq!(kind(check_with_prelude("(defn (f ([p Pixel]) i32) (as i32 p))")), Err(TypeErrorKind::Cast));
+    assert_eq!(kind(check_with_prelude("(defn (f ([p (ptr u8)]) u32) (as u32 p))")), Err(TypeErrorKind::Cast));
+}
+
+#[test]
+fn constant_inference() {
+    // Constants can be defined in terms of each other in any order, through any number of steps
+    let chain: String = (0..30).map(|i| format!("(define C{} C{})\n", i, i + 1)).collect();
+    check_with_prelude(&format!("{}(define C30 (byte))\n(defn (f () u8) C0)", chain)).unwrap();
+    check_with_prelude("(defn (f () u8) (define x (byte)) (define y x) y)").unwrap();
+    assert_eq!(kind(check("(define A B)\n(define B A)")), Err(TypeErrorKind::Ambiguous));
+}
+
+#[test]
+fn generic_values() {
+    // Generic values get their type arguments from where they are used
+    check_with_prelude("(defn (f () (Option i32)) (None))").unwrap();
+    check_with_prelude("(defn (f () i32) (unwrap-or (None) 2))").unwrap();
+    check_with_prelude("(defn (f () Holder) (Holder (o (None))))").unwrap();
+    check_with_prelude("(defn (f () (Option u8)) (let ([o (None)]) (set o (Some (byte))) o))").unwrap();
+
+    assert_eq!(kind(check_with_prelude("(defn (f ()) (let ([o (None)]) o))")), Err(TypeErrorKind::Ambiguous));
+    let error = check_with_prelude("(defn (f () (Option bool)) (Some 1))").unwrap_err();
+    assert_eq!(error.kind, TypeErrorKind::Mismatch);
+    assert_eq!(error.to_string(), "Mismatched types, expected `(Option bool)`, found `(Option i32)`");
+}
+
+#[test]
+fn inferred_types() {
+    // Every type that was left out is filled in
+    let input = prelude() + "(define K (byte))\n(defn (f ()) (let ([o (None)]) (set o (Some K))))";
+    let ast = type_checker::infer(&parse(&input)).unwrap();
+    match &ast[ast.len() - 2] {
+        Ast::Define { ty, .. } => assert_eq!(*ty, Type::U8),
+        a => panic!("expected a constant, found {:?}", a),
+    }
+    match &ast[ast.len() - 1] {
+        Ast::Defn { body, .. } => match &body[0] {
+            Ast::Let { bindings, .. } => assert_eq!(bindings[0].ty, Type::Struct(symbol("Option"), vec![Type::U8])),
+            a => panic!("expected a let, found {:?}", a),
+        },
+        a => panic!("expected a function, found {:?}", a),
+    }
+}
+
+#[test]
+fn suggestions() {
+    assert_eq!(error("(defn (f ([length i32]) i32) lenght)"),
+               ("Unbound identifier `lenght`, did you mean `length`?".into(), "lenght".into()));
+    assert_eq!(error("(struct Point [x i32])\n(defn (f () Piont) (Piont (x 1)))").0,
+               "Unknown type `Piont`, did you mean `Point`?");
+    assert_eq!(error("(defn (f ([x i32]) i32) (g x))").0, "Unbound identifier `g`");
+}
+
+#[test]
+fn mismatch_diagnostics() {
+    assert_eq!(error("(defn (add ([a i32] [b bool]) i32) a)\n(defn (f () i32) (add 1 2))"),
+               ("Mismatched types in argument 2 of `add`, expected `bool`, found `i32`".into(), "2".into()));
+    assert_eq!(error("(struct (Stack T) [top T])\n{impl (Stack T) (defn (push ([self (ptr (Stack T))] [v T])))}\n(defn (f ([s (Stack i32)])) (s.push #t))"),
+               ("Mismatched types in argument 2 of `push`, expected `i32`, found `bool`".into(), "#t".into()));
+    assert_eq!(error("(defn (f ([b bool]) i32) (cond (b 0) (else #f)))"),
+               ("Mismatched types, expected `i32`, found `bool`".into(), "#f".into()));
+    assert_eq!(error("(defn (f ([n i32]) bool) (if #t n #f))"),
+               ("Mismatched types, expected `i32`, found `bool`".into(), "#f".into()));
+}
+
+#[test]
+fn top_level() {
+    assert_eq!(error("(defn (f ()) 1)\n(defmacro (m) (f))"), ("Expected an item".into(), "(defmacro (m) (f))".into()));
+    // Not something the parser gives back, but other passes might
+    assert_eq!(kind(type_checker::type_check(&[Ast::Block(Vec::new(), Default::default())])), Err(TypeErrorKind::Item));
+}
+
+#[test]
+fn named_diagnostics() {
+    assert_eq!(error("(defn (f ([n i32]) i32) (n 1))"),
+               ("`n`: Incompatible types, found `i32`".into(), "n".into()));
+    assert_eq!(error("(struct List [next List])").0, "`List`: Struct contains itself and would be infinitely large");
+    assert_eq!(error("(define A B)\n(define B A)"),
+               ("`A`: Type can't be inferred, add an annotation".into(), "(define A B)".into()));
+}
+
+#[test]
+fn expansion_diagnostics() {
+    let input = "(defmacro (negate x) (if x #f 0))\n(defn (f ([b bool]) bool) (negate b))\n";
+    let mut sources = SourceMap::new();
+    let file = sources.add_file("<input>".into(), input.into());
+    let ast = parser::parse(sources.tokenize(file).unwrap(), input).unwrap();
+    let e = type_checker::type_check(&parser::expand(ast, &mut sources).unwrap()).unwrap_err();
+    let span = e.span.expect("error without a span");
+    assert_eq!(&input[span.start()..span.end()], "0");
+    assert_eq!(sources.diagnostic(span, &e.to_string()),
+               "error: Mismatched types, expected `bool`, found `i32`\n --> <input>:1:31\n  |\n\
+                1 | (defmacro (negate x) (if x #f 0))\n  |                               ^\n\
+                note: in expansion of `negate`\n --> <input>:2:27\n  |\n\
+                2 | (defn (f ([b bool]) bool) (negate b))\n  |                           ^^^^^^^^^^\n");
+}